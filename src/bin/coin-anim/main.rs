@@ -1,6 +1,6 @@
 use rust_boy::{
     gb_std::inputs::PadButton,
-    rust_boy::{AnimationType, InputManager, RustBoy, TileSource},
+    rust_boy::{ANIM_DISABLED, AnimationType, InputManager, RustBoy, TileSource},
 };
 
 fn main() {
@@ -10,13 +10,14 @@ fn main() {
     let coin = gb.add_sprite("Coin", TileSource::from_file("coin.2bpp", 7), 80, 72, 0);
 
     // Add looping animation with relative frame indices 0 to 6
-    gb.sprites
+    let coin_anim = gb
+        .sprites
         .add_animation(coin, "CoinAnim", 0, 6, AnimationType::Loop);
-    gb.sprites.disable_animation("CoinAnim");
+    gb.sprites.set_initial_animation(coin, ANIM_DISABLED);
     // Input handling
     let mut inputs = InputManager::new();
-    inputs.on_press(PadButton::A, gb.sprites.enable_animation("CoinAnim"));
-    inputs.on_press(PadButton::B, gb.sprites.disable_animation("CoinAnim"));
+    inputs.on_press(PadButton::A, gb.sprites.enable_animation(coin, coin_anim));
+    inputs.on_press(PadButton::B, gb.sprites.disable_animation(coin));
     gb.add_inputs(inputs);
     println!("{}", gb.build());
 }
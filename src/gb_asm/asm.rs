@@ -29,6 +29,12 @@ pub enum Chunk {
     Data,
 }
 
+impl Default for Asm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Asm {
     pub fn new() -> Self {
         Asm {
@@ -47,7 +53,7 @@ impl Asm {
     pub fn emit(&mut self, instr: Instr) -> &mut Self {
         self.chunks
             .entry(self.current_chunk)
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(instr);
         self
     }
@@ -56,7 +62,7 @@ impl Asm {
     pub fn emit_all(&mut self, instrs: Vec<Instr>) -> &mut Self {
         self.chunks
             .entry(self.current_chunk)
-            .or_insert_with(Vec::new)
+            .or_default()
             .extend(instrs);
         self
     }
@@ -243,6 +249,10 @@ impl Asm {
         )
     }
 
+    pub fn sbc(&mut self, dst: Operand, src: Operand) -> &mut Self {
+        self.emit(Instr::Sbc { dst, src })
+    }
+
     pub fn inc(&mut self, operand: Operand) -> &mut Self {
         self.emit(Instr::Inc { operand })
     }
@@ -327,6 +337,11 @@ impl Asm {
         self.swap(Operand::Label(register.to_string()))
     }
 
+    /// Rotate a right through the carry
+    pub fn rra(&mut self) -> &mut Self {
+        self.emit(Instr::Rra)
+    }
+
     // ============================================
     // Misc instructions
     // ============================================
@@ -335,6 +350,10 @@ impl Asm {
         self.emit(Instr::Daa)
     }
 
+    pub fn stop(&mut self) -> &mut Self {
+        self.emit(Instr::Stop)
+    }
+
     // ============================================
     // Jump instructions
     // ============================================
@@ -379,6 +398,14 @@ impl Asm {
         self.emit(Instr::RetCond { condition })
     }
 
+    pub fn push(&mut self, register: Register) -> &mut Self {
+        self.emit(Instr::Push { register })
+    }
+
+    pub fn pop(&mut self, register: Register) -> &mut Self {
+        self.emit(Instr::Pop { register })
+    }
+
     // ============================================
     // Assembler directives
     // ============================================
@@ -2,6 +2,19 @@ use super::asm::{Asm, Chunk};
 use super::instr::{Condition, Instr, JumpTarget, Operand, Register};
 use std::fmt;
 
+/// The order in which chunks appear in the generated output
+pub(crate) const CHUNK_ORDER: [Chunk; 9] = [
+    Chunk::Header,    // INCLUDE, SECTION Header
+    Chunk::Constants, // DEF statements
+    Chunk::Init,      // Initialization code
+    Chunk::MainLoop,  // Main game loop
+    Chunk::Main,      // Legacy (backwards compatibility)
+    Chunk::Functions, // Function definitions
+    Chunk::Tiles,     // Tile data
+    Chunk::Tilemap,   // Tilemap data
+    Chunk::Data,      // Variables (WRAM sections)
+];
+
 // Code generation implementation for Asm
 impl Asm {
     /// Get the instructions from the Main chunk as an owned vector
@@ -10,34 +23,29 @@ impl Asm {
         self.chunks.get(&Chunk::Main).cloned().unwrap_or_default()
     }
 
+    /// Iterate over all instructions in output order
+    pub fn instructions(&self) -> impl Iterator<Item = &Instr> {
+        CHUNK_ORDER
+            .iter()
+            .filter_map(|chunk| self.chunks.get(chunk))
+            .flatten()
+    }
+
     /// Generate assembly code from the instruction chunks
     pub fn to_asm(&self) -> String {
         let mut asm = String::new();
 
-        // Define the order in which chunks should appear in the output
-        let chunk_order = [
-            Chunk::Header,    // INCLUDE, SECTION Header
-            Chunk::Constants, // DEF statements
-            Chunk::Init,      // Initialization code
-            Chunk::MainLoop,  // Main game loop
-            Chunk::Main,      // Legacy (backwards compatibility)
-            Chunk::Functions, // Function definitions
-            Chunk::Tiles,     // Tile data
-            Chunk::Tilemap,   // Tilemap data
-            Chunk::Data,      // Variables (WRAM sections)
-        ];
-
-        for chunk in &chunk_order {
-            if let Some(instructions) = self.chunks.get(chunk) {
-                if !instructions.is_empty() {
-                    // Write instructions with indentation
-                    for instruction in instructions {
-                        asm.push_str(&format!("    {}\n", instruction));
-                    }
-
-                    // Add blank line between chunks
-                    asm.push('\n');
+        for chunk in &CHUNK_ORDER {
+            if let Some(instructions) = self.chunks.get(chunk)
+                && !instructions.is_empty()
+            {
+                // Write instructions with indentation
+                for instruction in instructions {
+                    asm.push_str(&format!("    {}\n", instruction));
                 }
+
+                // Add blank line between chunks
+                asm.push('\n');
             }
         }
 
@@ -116,6 +124,7 @@ impl fmt::Display for Instr {
             Instr::AdcA { operand } => write!(f, "adc {}", operand),
             Instr::Adc { dst, src } => write!(f, "adc {}, {}", dst, src),
             Instr::Sub { dst, src } => write!(f, "sub {}, {}", dst, src),
+            Instr::Sbc { dst, src } => write!(f, "sbc {}, {}", dst, src),
             Instr::Inc { operand } => write!(f, "inc {}", operand),
             Instr::Dec { operand } => write!(f, "dec {}", operand),
 
@@ -128,9 +137,11 @@ impl fmt::Display for Instr {
             // Bit shift instructions
             Instr::Srl { operand } => write!(f, "srl {}", operand),
             Instr::Swap { operand } => write!(f, "swap {}", operand),
+            Instr::Rra => write!(f, "rra"),
 
             // Misc instructions
             Instr::Daa => write!(f, "daa"),
+            Instr::Stop => write!(f, "stop"),
 
             // Jump instructions
             Instr::Jp { target } => write!(f, "jp {}", target),
@@ -140,6 +151,8 @@ impl fmt::Display for Instr {
             Instr::Call { target } => write!(f, "call {}", target),
             Instr::Ret => write!(f, "ret"),
             Instr::RetCond { condition } => write!(f, "ret {}", condition),
+            Instr::Push { register } => write!(f, "push {}", register),
+            Instr::Pop { register } => write!(f, "pop {}", register),
 
             // Assembler directives
             Instr::Ds {
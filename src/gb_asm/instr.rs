@@ -26,6 +26,10 @@ pub enum Instr {
         dst: Operand,
         src: Operand,
     },
+    Sbc {
+        dst: Operand,
+        src: Operand,
+    },
     Inc {
         operand: Operand,
     },
@@ -56,9 +60,11 @@ pub enum Instr {
     Swap {
         operand: Operand,
     },
+    Rra,
 
     // Misc instructions
     Daa,
+    Stop,
 
     // Jump instructions
     Jp {
@@ -83,6 +89,14 @@ pub enum Instr {
         condition: Condition,
     },
 
+    // Stack instructions
+    Push {
+        register: Register,
+    },
+    Pop {
+        register: Register,
+    },
+
     // Assembler directives
     Ds {
         num_bytes: String,
@@ -2,7 +2,9 @@
 pub mod asm;
 mod codegen;
 pub mod instr;
+pub mod symbols;
 
 // Re-export main types for convenience
 pub use asm::{Asm, Chunk};
 pub use instr::{Condition, Instr, JumpTarget, Operand, Register};
+pub use symbols::{SymbolError, SymbolTable};
@@ -0,0 +1,373 @@
+//! Symbol table pass over generated assembly
+//!
+//! Collects label definitions, jump/call targets and symbols used as memory
+//! operands following RGBDS scoping rules, so duplicate labels and references
+//! to undefined symbols can be reported before the output ever reaches rgbasm.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::asm::Asm;
+use super::instr::{Instr, JumpTarget, Operand};
+
+/// A label problem found in the generated assembly
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolError {
+    /// The same label is defined more than once
+    DuplicateLabel { name: String, count: usize },
+    /// A jump or call targets a label that is never defined
+    UndefinedLabel {
+        name: String,
+        /// Global label in scope where the reference appears
+        scope: Option<String>,
+    },
+    /// A memory operand (`ld a, [wFoo]`) uses a symbol that is never defined
+    ///
+    /// hardware.inc registers (`rLCDC`, `_OAMRAM`) are assumed defined.
+    UndefinedSymbol {
+        name: String,
+        /// Global label in scope where the reference appears
+        scope: Option<String>,
+    },
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::DuplicateLabel { name, count } => {
+                write!(f, "label '{}' is defined {} times", name, count)
+            }
+            SymbolError::UndefinedLabel { name, scope } => match scope {
+                Some(scope) => write!(
+                    f,
+                    "jump or call to undefined label '{}' (in '{}')",
+                    name, scope
+                ),
+                None => write!(f, "jump or call to undefined label '{}'", name),
+            },
+            SymbolError::UndefinedSymbol { name, scope } => match scope {
+                Some(scope) => write!(
+                    f,
+                    "memory operand uses undefined symbol '{}' (in '{}')",
+                    name, scope
+                ),
+                None => write!(f, "memory operand uses undefined symbol '{}'", name),
+            },
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+/// A jump or call target, or a symbol in a memory operand, fully qualified
+struct Reference {
+    name: String,
+    scope: Option<String>,
+    memory: bool,
+}
+
+/// Label definitions and references of a program
+///
+/// Local labels (`.loop`) are qualified with the global label that precedes
+/// them (`Main.loop`), exactly like rgbasm does.
+#[derive(Default)]
+pub struct SymbolTable {
+    /// Fully qualified label name -> number of definitions
+    definitions: HashMap<String, usize>,
+    /// Definition order, for deterministic reports
+    order: Vec<String>,
+    references: Vec<Reference>,
+}
+
+impl SymbolTable {
+    /// Build the symbol table for a whole program, in output order
+    pub fn from_asm(asm: &Asm) -> Self {
+        Self::from_instrs(asm.instructions())
+    }
+
+    /// Build the symbol table from a sequence of instructions
+    pub fn from_instrs<'a>(instrs: impl IntoIterator<Item = &'a Instr>) -> Self {
+        let mut table = Self::default();
+        let mut scope: Option<String> = None;
+
+        for instr in instrs {
+            match instr {
+                Instr::Label { name } => table.define(name, &mut scope),
+                Instr::Raw { line } => {
+                    if let Some(name) = raw_label(line) {
+                        table.define(name, &mut scope);
+                    } else if let Some(name) = raw_constant(line) {
+                        table.define_constant(name);
+                    }
+                }
+                Instr::Def { label, .. } => table.define_constant(label),
+                Instr::Jp { target }
+                | Instr::JpCond { target, .. }
+                | Instr::Jr { target }
+                | Instr::JrCond { target, .. }
+                | Instr::Call { target } => {
+                    if let JumpTarget::Label(name) = target
+                        && is_identifier(name)
+                    {
+                        table.references.push(Reference {
+                            name: qualify(name, scope.as_deref()),
+                            scope: scope.clone(),
+                            memory: false,
+                        });
+                    }
+                }
+                _ => {
+                    for operand in operands(instr) {
+                        if let Operand::AddrDef(expr) = operand {
+                            for name in symbols_in(expr).filter(|name| !is_hardware(name)) {
+                                table.references.push(Reference {
+                                    name: qualify(name, scope.as_deref()),
+                                    scope: scope.clone(),
+                                    memory: true,
+                                });
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        table
+    }
+
+    fn define(&mut self, name: &str, scope: &mut Option<String>) {
+        let qualified = qualify(name, scope.as_deref());
+        if !name.starts_with('.') {
+            // A global (or explicitly scoped) label opens a new scope
+            *scope = Some(qualified.split('.').next().unwrap_or_default().to_string());
+        }
+
+        self.count_definition(qualified);
+    }
+
+    /// Define a constant (`DEF NAME EQU value`), which does not open a scope
+    fn define_constant(&mut self, name: &str) {
+        self.count_definition(name.to_string());
+    }
+
+    fn count_definition(&mut self, name: String) {
+        let count = self.definitions.entry(name.clone()).or_insert(0);
+        if *count == 0 {
+            self.order.push(name);
+        }
+        *count += 1;
+    }
+
+    /// Check whether a fully qualified label is defined
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// Labels defined more than once
+    pub fn duplicates(&self) -> Vec<SymbolError> {
+        self.order
+            .iter()
+            .filter_map(|name| {
+                let count = self.definitions[name];
+                (count > 1).then(|| SymbolError::DuplicateLabel {
+                    name: name.clone(),
+                    count,
+                })
+            })
+            .collect()
+    }
+
+    /// Jump and call targets and memory operand symbols that are never
+    /// defined (each reported once)
+    pub fn undefined(&self) -> Vec<SymbolError> {
+        let mut errors: Vec<SymbolError> = Vec::new();
+        for reference in &self.references {
+            let reported = errors.iter().any(|e| match e {
+                SymbolError::UndefinedLabel { name, .. }
+                | SymbolError::UndefinedSymbol { name, .. } => *name == reference.name,
+                SymbolError::DuplicateLabel { .. } => false,
+            });
+            if self.is_defined(&reference.name) || reported {
+                continue;
+            }
+            let name = reference.name.clone();
+            let scope = reference.scope.clone();
+            errors.push(if reference.memory {
+                SymbolError::UndefinedSymbol { name, scope }
+            } else {
+                SymbolError::UndefinedLabel { name, scope }
+            });
+        }
+        errors
+    }
+
+    /// All duplicate label and undefined symbol errors
+    pub fn check(&self) -> Vec<SymbolError> {
+        let mut errors = self.duplicates();
+        errors.extend(self.undefined());
+        errors
+    }
+
+    /// Duplicate labels and undefined jump or call targets, for code
+    /// fragments whose variables are defined elsewhere
+    pub fn check_labels(&self) -> Vec<SymbolError> {
+        let mut errors = self.check();
+        errors.retain(|e| !matches!(e, SymbolError::UndefinedSymbol { .. }));
+        errors
+    }
+}
+
+/// Qualify a local label with its global scope
+fn qualify(name: &str, scope: Option<&str>) -> String {
+    match (name.starts_with('.'), scope) {
+        (true, Some(scope)) => format!("{}{}", scope, name),
+        _ => name.to_string(),
+    }
+}
+
+/// Extract the label defined at the start of a raw line (`Name:` or `Name::`)
+fn raw_label(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let end = line.find(':')?;
+    let name = &line[..end];
+    is_identifier(name).then_some(name)
+}
+
+/// Extract the constant defined by a raw `DEF NAME EQU value` line
+fn raw_constant(line: &str) -> Option<&str> {
+    let mut words = line.split_whitespace();
+    let (def, name, equ) = (words.next()?, words.next()?, words.next()?);
+    (def == "DEF" && matches!(equ, "EQU" | "EQUS" | "=") && is_identifier(name)).then_some(name)
+}
+
+/// Operands of an instruction
+fn operands(instr: &Instr) -> Vec<&Operand> {
+    match instr {
+        Instr::Ld { dst, src }
+        | Instr::Ldh { dst, src }
+        | Instr::Add { dst, src }
+        | Instr::Adc { dst, src }
+        | Instr::Sub { dst, src }
+        | Instr::Sbc { dst, src }
+        | Instr::Or { dst, src }
+        | Instr::Xor { dst, src } => vec![dst, src],
+        Instr::AdcA { operand }
+        | Instr::Inc { operand }
+        | Instr::Dec { operand }
+        | Instr::And { operand }
+        | Instr::Cp { operand }
+        | Instr::Srl { operand }
+        | Instr::Swap { operand } => vec![operand],
+        _ => Vec::new(),
+    }
+}
+
+/// Symbols used in an address expression (`wQueue + 2` uses `wQueue`)
+fn symbols_in(expr: &str) -> impl Iterator<Item = &str> {
+    expr.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'))
+        .filter(|word| is_identifier(word))
+}
+
+/// hardware.inc registers and memory areas (`rLCDC`, `_OAMRAM`)
+fn is_hardware(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some('_') => true,
+        Some('r') => chars
+            .next()
+            .is_some_and(|c| c.is_ascii_uppercase() || c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+/// Check that a target is a plain label and not an expression
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb_asm::{Chunk, Condition};
+
+    #[test]
+    fn test_local_labels_are_scoped() {
+        let mut asm = Asm::new();
+        asm.label("First")
+            .jr_cond(Condition::Z, ".done")
+            .label(".done")
+            .ret()
+            .label("Second")
+            .jr(".done")
+            .label(".done")
+            .ret();
+
+        assert!(SymbolTable::from_asm(&asm).check().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_and_undefined() {
+        let mut asm = Asm::new();
+        asm.chunk(Chunk::Init).label("Start").call("Missing");
+        asm.chunk(Chunk::Functions)
+            .label("Helper")
+            .ret()
+            .label("Helper")
+            .jr(".nowhere");
+
+        let errors = SymbolTable::from_asm(&asm).check();
+        assert_eq!(
+            errors,
+            vec![
+                SymbolError::DuplicateLabel {
+                    name: "Helper".to_string(),
+                    count: 2,
+                },
+                SymbolError::UndefinedLabel {
+                    name: "Missing".to_string(),
+                    scope: Some("Start".to_string()),
+                },
+                SymbolError::UndefinedLabel {
+                    name: "Helper.nowhere".to_string(),
+                    scope: Some("Helper".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_undefined_memory_operands() {
+        let mut asm = Asm::new();
+        asm.raw("DEF SCORE_ADDR EQU $9870")
+            .raw("wQueue: ds 4")
+            .label("Main")
+            .ld_a_addr_def("wQueue + 2")
+            .ld_addr_def_a("SCORE_ADDR")
+            .ld_addr_def_a("rLCDC")
+            .ld_a_addr_def("_OAMRAM+4")
+            .ld_a_addr_def("wMissing")
+            .ld_addr_def_a("wMissing + 1");
+
+        let table = SymbolTable::from_asm(&asm);
+        assert_eq!(
+            table.check(),
+            vec![SymbolError::UndefinedSymbol {
+                name: "wMissing".to_string(),
+                scope: Some("Main".to_string()),
+            }]
+        );
+        assert!(table.check_labels().is_empty());
+    }
+
+    #[test]
+    fn test_raw_labels_are_definitions() {
+        let mut asm = Asm::new();
+        asm.raw("wScore: db").raw("Entry::").jp("Entry");
+
+        let table = SymbolTable::from_asm(&asm);
+        assert!(table.is_defined("wScore"));
+        assert!(table.check().is_empty());
+    }
+}
@@ -39,6 +39,7 @@ impl ComparisonOp {
     }
 
     /// Check if this comparison requires special multi-check handling
+    #[allow(dead_code)]
     fn needs_special_handling(&self) -> bool {
         matches!(self, ComparisonOp::LE | ComparisonOp::GT)
     }
@@ -1001,7 +1002,7 @@ mod tests {
         let mut counter = 0;
         let result = if_stmt.emit(&mut counter);
 
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
         assert_eq!(counter, 1); // If increments counter
     }
 
@@ -1028,7 +1029,7 @@ mod tests {
         let mut counter = 0;
         let result = if_stmt.emit(&mut counter);
 
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
         assert_eq!(counter, 1);
     }
 
@@ -1062,7 +1063,7 @@ mod tests {
         let mut counter = 0;
         let result = outer_if.emit(&mut counter);
 
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
         assert_eq!(counter, 2); // Both ifs increment counter
     }
 
@@ -1,4 +1,4 @@
-use crate::gb_asm::{Asm, Condition, Instr, JumpTarget, Operand, Register};
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
 
 pub fn clear_objects_screen() -> Vec<Instr> {
     let mut asm = Asm::new();
@@ -47,6 +47,11 @@ impl SpriteManager {
         self.sprites.iter_mut().find(|s| s.id == id)
     }
 }
+impl Default for SpriteManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 pub struct Sprite {
     pub id: u8,
     pub x: u8,
@@ -180,7 +185,7 @@ impl Sprite {
 
     pub fn move_x_var(&mut self, var_name: &str) -> Vec<Instr> {
         let mut asm = Asm::new();
-        asm.ld_a_addr_def(&format!("{}", var_name))
+        asm.ld_a_addr_def(var_name)
             .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
             .ld_a_addr_def(&format!("_OAMRAM+{}", self.id * 4 + 1))
             .add(Operand::Reg(Register::A), Operand::Reg(Register::B))
@@ -191,7 +196,7 @@ impl Sprite {
 
     pub fn move_y_var(&mut self, var_name: &str) -> Vec<Instr> {
         let mut asm = Asm::new();
-        asm.ld_a_addr_def(&format!("{}", var_name))
+        asm.ld_a_addr_def(var_name)
             .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
             .ld_a_addr_def(&format!("_OAMRAM+{}", self.id * 4))
             .add(Operand::Reg(Register::A), Operand::Reg(Register::B))
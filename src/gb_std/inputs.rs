@@ -126,16 +126,150 @@ pub fn update_keys() -> Vec<Instr> {
 }
 //TODO check if it is ok, or we have to implement a big scope "check all keys"
 // and one is pressed we jump at the end of the block
+/// Run `pressed_func` while `button` is held.
+///
+/// Uses fixed `Check<Button>`/`Check<Button>End` labels, so each button can
+/// only be checked once per program. Use `check_key_unique` otherwise.
 pub fn check_key(button: PadButton, pressed_func: Vec<Instr>) -> Vec<Instr> {
+    emit_check_key(
+        button,
+        pressed_func,
+        button.label(),
+        &format!("{}End", button.label()),
+    )
+}
+
+/// Same as `check_key`, with labels suffixed by `id` so the same button can
+/// be checked several times.
+pub fn check_key_unique(button: PadButton, pressed_func: Vec<Instr>, id: usize) -> Vec<Instr> {
+    emit_check_key(
+        button,
+        pressed_func,
+        &format!("{}_{}", button.label(), id),
+        &format!("{}End_{}", button.label(), id),
+    )
+}
+
+fn emit_check_key(
+    button: PadButton,
+    pressed_func: Vec<Instr>,
+    start_label: &str,
+    end_label: &str,
+) -> Vec<Instr> {
     let mut asm = Asm::new();
-    asm.label(button.label());
+    asm.label(start_label);
     asm.ld(
         Operand::Reg(Register::A),
         Operand::AddrDef("wCurKeys".to_string()),
     );
     asm.and(Operand::Label(button.name().to_string()));
-    asm.jp_cond(Condition::Z, &format!("{}End", button.label()));
+    asm.jp_cond(Condition::Z, end_label);
     asm.emit_all(pressed_func);
-    asm.label(&format!("{}End", button.label()));
+    asm.label(end_label);
+    asm.get_main_instrs()
+}
+
+/// Run `pressed_func` on the frame `button` is pressed (`wNewKeys`).
+///
+/// Labels are suffixed by `id`, as in `check_key_unique`.
+pub fn check_key_pressed_unique(
+    button: PadButton,
+    pressed_func: Vec<Instr>,
+    id: usize,
+) -> Vec<Instr> {
+    let start_label = format!("{}Pressed_{}", button.label(), id);
+    let end_label = format!("{}PressedEnd_{}", button.label(), id);
+    let mut asm = Asm::new();
+    asm.label(&start_label);
+    asm.ld_a_addr_def("wNewKeys");
+    asm.and(Operand::Label(button.name().to_string()));
+    asm.jp_cond(Condition::Z, &end_label);
+    asm.emit_all(pressed_func);
+    asm.label(&end_label);
+    asm.get_main_instrs()
+}
+
+/// Run `released_func` on the frame `button` is released.
+///
+/// @requires wPrevKeys: 1 byte copy of wCurKeys from before UpdateKeys
+pub fn check_key_released_unique(
+    button: PadButton,
+    released_func: Vec<Instr>,
+    id: usize,
+) -> Vec<Instr> {
+    let start_label = format!("{}Released_{}", button.label(), id);
+    let end_label = format!("{}ReleasedEnd_{}", button.label(), id);
+    let mut asm = Asm::new();
+    asm.label(&start_label);
+    asm.ld_a_addr_def("wCurKeys");
+    asm.and(Operand::Label(button.name().to_string()));
+    asm.jp_cond(Condition::NZ, &end_label);
+    asm.ld_a_addr_def("wPrevKeys");
+    asm.and(Operand::Label(button.name().to_string()));
+    asm.jp_cond(Condition::Z, &end_label);
+    asm.emit_all(released_func);
+    asm.label(&end_label);
+    asm.get_main_instrs()
+}
+
+/// Run `func` when `button` is pressed, then every `rate` frames once it
+/// has been held for `delay` frames, like a keyboard's auto-repeat.
+///
+/// @requires counter: 1 byte variable counting the frames to the next repeat
+pub fn check_key_repeat_unique(
+    button: PadButton,
+    delay: u8,
+    rate: u8,
+    counter: &str,
+    func: Vec<Instr>,
+    id: usize,
+) -> Vec<Instr> {
+    let start_label = format!("{}Repeat_{}", button.label(), id);
+    let held_label = format!("{}RepeatHeld_{}", button.label(), id);
+    let fire_label = format!("{}RepeatFire_{}", button.label(), id);
+    let end_label = format!("{}RepeatEnd_{}", button.label(), id);
+    let mut asm = Asm::new();
+    asm.label(&start_label);
+    asm.ld_a_addr_def("wNewKeys");
+    asm.and(Operand::Label(button.name().to_string()));
+    asm.jr_cond(Condition::Z, &held_label);
+    asm.ld_a(delay.max(1));
+    asm.jr(&fire_label);
+    asm.label(&held_label);
+    asm.ld_a_addr_def("wCurKeys");
+    asm.and(Operand::Label(button.name().to_string()));
+    asm.jp_cond(Condition::Z, &end_label);
+    asm.ld_hl_label(counter);
+    asm.dec(Operand::AddrReg(Register::HL));
+    asm.jp_cond(Condition::NZ, &end_label);
+    asm.ld_a(rate.max(1));
+    asm.label(&fire_label);
+    asm.ld_addr_def_a(counter);
+    asm.emit_all(func);
+    asm.label(&end_label);
+    asm.get_main_instrs()
+}
+
+/// Run `func` on the frame the last of `buttons` is pressed while the
+/// others are held (e.g. Start+Select).
+pub fn check_combo_unique(buttons: &[PadButton], func: Vec<Instr>, id: usize) -> Vec<Instr> {
+    let mask = buttons
+        .iter()
+        .map(|button| button.name())
+        .collect::<Vec<_>>()
+        .join(" | ");
+    let start_label = format!("CheckCombo_{}", id);
+    let end_label = format!("CheckComboEnd_{}", id);
+    let mut asm = Asm::new();
+    asm.label(&start_label);
+    asm.ld_a_addr_def("wCurKeys");
+    asm.and(Operand::Label(mask.clone()));
+    asm.cp(Operand::Label(mask.clone()));
+    asm.jp_cond(Condition::NZ, &end_label);
+    asm.ld_a_addr_def("wNewKeys");
+    asm.and(Operand::Label(mask));
+    asm.jp_cond(Condition::Z, &end_label);
+    asm.emit_all(func);
+    asm.label(&end_label);
     asm.get_main_instrs()
 }
@@ -9,3 +9,31 @@ pub fn header_section() -> Vec<Instr> {
     // Entry point
     asm.get_main_instrs()
 }
+
+/// Header section with the CGB flag at $0143 (e.g. `CART_COMPATIBLE_DMG_GBC`)
+pub fn cgb_header_section(cgb_flag: &str) -> Vec<Instr> {
+    flagged_header_section(Some(cgb_flag), false)
+}
+
+/// Header section with optional CGB and SGB flags
+///
+/// The SGB flag at $0146 is only honored with the old licensee code $33
+/// at $014B.
+pub fn flagged_header_section(cgb_flag: Option<&str>, sgb: bool) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.section("Header", "ROM0[$100]");
+    asm.jp("EntryPoint");
+    if let Some(flag) = cgb_flag {
+        asm.ds("$143 - @", "0");
+        asm.db(flag);
+    }
+    if sgb {
+        asm.ds("$146 - @", "0");
+        asm.db("CART_INDICATOR_SGB");
+        asm.ds("$14B - @", "0");
+        asm.db("$33");
+    }
+    asm.ds("$150 - @", "0");
+
+    asm.get_main_instrs()
+}
@@ -0,0 +1,1037 @@
+//! Sound: APU setup and one-shot sound effects
+//!
+//! Sound effects are typed register settings for one of the four channels.
+//! They are compiled to a ROM table (`SfxTable`) and started with the
+//! `PlaySfx` builtin, so `Audio::play` can be bound to inputs or used
+//! anywhere in game logic. The APU is turned on at startup as soon as a
+//! sound effect or a song is added. Songs are played by the music driver
+//! (see `music`) and wave channel samples are uploaded from `WaveTable`
+//! (see `waveform`).
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::music::{MusicError, Song, generate_period_tables};
+use super::waveform::{MAX_WAVEFORMS, WaveId, Waveform};
+use crate::gb_asm::{Asm, Instr, Operand, Register};
+
+/// Most sound effects in the table (they are numbered with one byte)
+pub const MAX_SOUND_EFFECTS: usize = 256;
+
+/// Invalid audio settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioError {
+    /// Not a note name like `C4`, `F#5` or `A-3`
+    InvalidNote(String),
+    /// Tone channels cannot play below C2, and B8 is the highest note
+    OctaveOutOfRange(u8),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::InvalidNote(name) => write!(f, "invalid note name '{}'", name),
+            AudioError::OctaveOutOfRange(octave) => write!(
+                f,
+                "octave {} is out of range ({} to {})",
+                octave,
+                Note::MIN_OCTAVE,
+                Note::MAX_OCTAVE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+/// One of the four APU channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Channel {
+    /// Square wave with frequency sweep
+    Pulse1,
+    Pulse2,
+    /// 32 4-bit samples from wave RAM
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+
+    /// Low byte of the channel's first register (NRx0); $FF15 and $FF1F
+    /// are unused, so every channel has five registers
+    pub fn first_register(&self) -> u8 {
+        match self {
+            Channel::Pulse1 => 0x10,
+            Channel::Pulse2 => 0x15,
+            Channel::Wave => 0x1A,
+            Channel::Noise => 0x1F,
+        }
+    }
+
+    /// Channel bit in rNR51 (right output; shift left 4 for the left one)
+    pub fn mask(&self) -> u8 {
+        match self {
+            Channel::Pulse1 => 0x01,
+            Channel::Pulse2 => 0x02,
+            Channel::Wave => 0x04,
+            Channel::Noise => 0x08,
+        }
+    }
+}
+
+/// Note names within an octave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pitch {
+    C,
+    Cs,
+    D,
+    Ds,
+    E,
+    F,
+    Fs,
+    G,
+    Gs,
+    A,
+    As,
+    B,
+}
+
+impl Pitch {
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    const ALL: [Pitch; 12] = [
+        Pitch::C,
+        Pitch::Cs,
+        Pitch::D,
+        Pitch::Ds,
+        Pitch::E,
+        Pitch::F,
+        Pitch::Fs,
+        Pitch::G,
+        Pitch::Gs,
+        Pitch::A,
+        Pitch::As,
+        Pitch::B,
+    ];
+
+    /// Semitones above C
+    pub fn semitone(&self) -> u8 {
+        *self as u8
+    }
+
+    /// Pitch `semitone` (0-11) semitones above C
+    pub fn from_semitone(semitone: u8) -> Pitch {
+        Pitch::ALL[semitone as usize % 12]
+    }
+}
+
+/// A note from C2 to B8
+///
+/// Notes parse from names such as `"C4"`, `"F#5"`, `"Bb3"` or the tracker
+/// spelling `"A-3"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pitch: Pitch,
+    octave: u8,
+}
+
+impl Note {
+    pub const MIN_OCTAVE: u8 = 2;
+    pub const MAX_OCTAVE: u8 = 8;
+
+    pub fn new(pitch: Pitch, octave: u8) -> Result<Self, AudioError> {
+        if !(Self::MIN_OCTAVE..=Self::MAX_OCTAVE).contains(&octave) {
+            return Err(AudioError::OctaveOutOfRange(octave));
+        }
+        Ok(Note { pitch, octave })
+    }
+
+    pub fn pitch(&self) -> Pitch {
+        self.pitch
+    }
+
+    pub fn octave(&self) -> u8 {
+        self.octave
+    }
+
+    /// MIDI note number (A4 = 69)
+    pub fn midi(&self) -> u8 {
+        (self.octave + 1) * 12 + self.pitch.semitone()
+    }
+
+    /// Frequency in Hz, A4 = 440
+    pub fn frequency(&self) -> f64 {
+        440.0 * 2f64.powf((self.midi() as f64 - 69.0) / 12.0)
+    }
+
+    /// 11-bit period for the pulse channels (NRx3/NRx4)
+    pub fn pulse_period(&self) -> u16 {
+        2048 - (131072.0 / self.frequency()).round() as u16
+    }
+
+    /// 11-bit period for the wave channel, which runs at half the rate
+    pub fn wave_period(&self) -> u16 {
+        2048 - (65536.0 / self.frequency()).round() as u16
+    }
+}
+
+impl FromStr for Note {
+    type Err = AudioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AudioError::InvalidNote(s.to_string());
+        let mut chars = s.chars();
+        let letter = chars.next().ok_or_else(invalid)?.to_ascii_uppercase();
+        let base = match letter {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return Err(invalid()),
+        };
+        let rest = chars.as_str();
+        let (semitone, octave) = match rest.chars().next() {
+            Some('#') => (base + 1, &rest[1..]),
+            Some('b') => (base + 11, &rest[1..]),
+            Some('-') => (base, &rest[1..]),
+            _ => (base, rest),
+        };
+        let mut octave: u8 = octave.parse().map_err(|_| invalid())?;
+        // Cb and B# cross the octave boundary
+        match (base, semitone) {
+            (0, 11) => octave = octave.checked_sub(1).ok_or_else(invalid)?,
+            (11, 12) => octave += 1,
+            _ => {}
+        }
+        Note::new(Pitch::ALL[semitone % 12], octave)
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", Pitch::NAMES[self.pitch as usize], self.octave)
+    }
+}
+
+/// Share of each period the pulse wave is high
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Duty {
+    /// 12.5%
+    Eighth,
+    /// 25%
+    Quarter,
+    /// 50%, a plain square wave
+    #[default]
+    Half,
+    /// 75%
+    ThreeQuarters,
+}
+
+impl Duty {
+    /// Duty bits of NRx1
+    pub fn bits(&self) -> u8 {
+        (*self as u8) << 6
+    }
+}
+
+/// Volume envelope of the pulse and noise channels (NRx2)
+///
+/// Volume and pace are masked to 4 and 3 bits. A volume of 0 that does
+/// not rise turns the channel off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Envelope {
+    pub volume: u8,
+    /// Raise the volume instead of lowering it
+    pub increase: bool,
+    /// Frames of 1/64 s between volume steps, 0 keeps the volume constant
+    pub pace: u8,
+}
+
+impl Envelope {
+    /// Start at `volume` (0-15) and fade out one step every `pace` (1-7)
+    pub fn fade_out(volume: u8, pace: u8) -> Self {
+        Envelope {
+            volume,
+            increase: false,
+            pace,
+        }
+    }
+
+    /// Start at `volume` (0-15) and rise one step every `pace` (1-7)
+    pub fn fade_in(volume: u8, pace: u8) -> Self {
+        Envelope {
+            volume,
+            increase: true,
+            pace,
+        }
+    }
+
+    pub fn constant(volume: u8) -> Self {
+        Envelope {
+            volume,
+            increase: false,
+            pace: 0,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        (self.volume & 0x0F) << 4 | (self.increase as u8) << 3 | (self.pace & 0x07)
+    }
+}
+
+/// Frequency sweep of pulse channel 1 (rNR10)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sweep {
+    /// Steps of 1/128 s between changes (0-7), 0 turns the sweep off
+    pub pace: u8,
+    /// Lower the pitch instead of raising it
+    pub decrease: bool,
+    /// Each change adds `period >> step` (0-7)
+    pub step: u8,
+}
+
+impl Sweep {
+    pub const OFF: Sweep = Sweep {
+        pace: 0,
+        decrease: false,
+        step: 0,
+    };
+
+    pub fn up(pace: u8, step: u8) -> Self {
+        Sweep {
+            pace,
+            decrease: false,
+            step,
+        }
+    }
+
+    pub fn down(pace: u8, step: u8) -> Self {
+        Sweep {
+            pace,
+            decrease: true,
+            step,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        (self.pace & 0x07) << 4 | (self.decrease as u8) << 3 | (self.step & 0x07)
+    }
+}
+
+/// Output level of the wave channel (rNR32)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaveVolume {
+    Mute,
+    #[default]
+    Full,
+    Half,
+    Quarter,
+}
+
+impl WaveVolume {
+    pub fn byte(&self) -> u8 {
+        (*self as u8) << 5
+    }
+}
+
+/// Noise generator settings (rNR43)
+///
+/// The LFSR is clocked at 262144 / (divider or 0.5) / 2^shift Hz: higher
+/// shifts sound lower.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Noise {
+    /// 0-15
+    pub shift: u8,
+    /// 7-bit LFSR: a metallic, more tonal noise
+    pub short: bool,
+    /// 0-7
+    pub divider: u8,
+}
+
+impl Noise {
+    pub fn new(shift: u8, divider: u8) -> Self {
+        Noise {
+            shift,
+            short: false,
+            divider,
+        }
+    }
+
+    /// Use the 7-bit LFSR
+    pub fn short(self) -> Self {
+        Noise {
+            short: true,
+            ..self
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        (self.shift & 0x0F) << 4 | (self.short as u8) << 3 | (self.divider & 0x07)
+    }
+}
+
+/// A one-shot sound on one channel
+///
+/// `length` stops the sound after that many 1/256 s (1-64, or 1-255 for
+/// the wave channel); `None` plays until the envelope fades out or another
+/// sound takes the channel. A wave effect without a waveform plays the
+/// samples already in wave RAM.
+///
+/// # Example
+/// ```ignore
+/// let jump = SoundEffect::Pulse1 {
+///     sweep: Sweep::up(2, 3),
+///     duty: Duty::Half,
+///     envelope: Envelope::fade_out(12, 2),
+///     note: "C5".parse()?,
+///     length: None,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundEffect {
+    Pulse1 {
+        sweep: Sweep,
+        duty: Duty,
+        envelope: Envelope,
+        note: Note,
+        length: Option<u8>,
+    },
+    Pulse2 {
+        duty: Duty,
+        envelope: Envelope,
+        note: Note,
+        length: Option<u8>,
+    },
+    Wave {
+        volume: WaveVolume,
+        note: Note,
+        length: Option<u8>,
+        wave: Option<Waveform>,
+    },
+    Noise {
+        noise: Noise,
+        envelope: Envelope,
+        length: Option<u8>,
+    },
+}
+
+impl SoundEffect {
+    pub fn channel(&self) -> Channel {
+        match self {
+            SoundEffect::Pulse1 { .. } => Channel::Pulse1,
+            SoundEffect::Pulse2 { .. } => Channel::Pulse2,
+            SoundEffect::Wave { .. } => Channel::Wave,
+            SoundEffect::Noise { .. } => Channel::Noise,
+        }
+    }
+
+    /// Frames the effect keeps its channel from the music (at most 255)
+    ///
+    /// This is the length when there is one, else the time the envelope
+    /// takes to fade out. Sounds that never end hold the channel for 255
+    /// frames.
+    pub fn frames(&self) -> u8 {
+        let (length, envelope) = match *self {
+            SoundEffect::Pulse1 {
+                length, envelope, ..
+            }
+            | SoundEffect::Pulse2 {
+                length, envelope, ..
+            }
+            | SoundEffect::Noise {
+                length, envelope, ..
+            } => (length, Some(envelope)),
+            SoundEffect::Wave { length, .. } => (length, None),
+        };
+        // Lengths count 1/256 s and envelope steps 1/64 s
+        let frames = match (length, envelope) {
+            (Some(length), _) => (length as u32 * 60).div_ceil(256),
+            (None, Some(env)) if !env.increase && env.pace & 0x07 != 0 => {
+                ((env.volume & 0x0F) as u32 * (env.pace & 0x07) as u32 * 60).div_ceil(64)
+            }
+            _ => 255,
+        };
+        frames.clamp(1, 255) as u8
+    }
+
+    /// Values written to NRx0-NRx4, the last one triggering the channel
+    pub fn registers(&self) -> [u8; 5] {
+        match *self {
+            SoundEffect::Pulse1 {
+                sweep,
+                duty,
+                envelope,
+                note,
+                length,
+            } => {
+                let [low, high] = note.pulse_period().to_le_bytes();
+                [
+                    sweep.byte(),
+                    duty.bits() | pulse_length(length),
+                    envelope.byte(),
+                    low,
+                    trigger(high, length),
+                ]
+            }
+            SoundEffect::Pulse2 {
+                duty,
+                envelope,
+                note,
+                length,
+            } => {
+                let [low, high] = note.pulse_period().to_le_bytes();
+                [
+                    0,
+                    duty.bits() | pulse_length(length),
+                    envelope.byte(),
+                    low,
+                    trigger(high, length),
+                ]
+            }
+            SoundEffect::Wave {
+                volume,
+                note,
+                length,
+                ..
+            } => {
+                let [low, high] = note.wave_period().to_le_bytes();
+                // NR30 bit 7 turns the DAC on
+                [
+                    0x80,
+                    wave_length(length),
+                    volume.byte(),
+                    low,
+                    trigger(high, length),
+                ]
+            }
+            SoundEffect::Noise {
+                noise,
+                envelope,
+                length,
+            } => [
+                0,
+                pulse_length(length),
+                envelope.byte(),
+                noise.byte(),
+                trigger(0, length),
+            ],
+        }
+    }
+}
+
+/// Length timer bits of NRx1 (counts up to 64)
+pub(crate) fn pulse_length(length: Option<u8>) -> u8 {
+    length.map_or(0, |frames| 64 - frames.clamp(1, 64))
+}
+
+/// Length timer of NR31 (counts up to 256)
+pub(crate) fn wave_length(length: Option<u8>) -> u8 {
+    length.map_or(0, |frames| 0u8.wrapping_sub(frames.max(1)))
+}
+
+/// Length enable bit of NRx4
+pub(crate) fn length_flag(length: Option<u8>) -> u8 {
+    (length.is_some() as u8) << 6
+}
+
+/// NRx4: trigger, length enable and the period's high bits
+fn trigger(period_high: u8, length: Option<u8>) -> u8 {
+    0x80 | length_flag(length) | (period_high & 0x07)
+}
+
+/// Number of a sound effect, returned by `Audio::add_sfx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SfxId(u8);
+
+impl SfxId {
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Number of a song, returned by `Audio::add_song`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SongId(usize);
+
+impl SongId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    fn label(&self) -> String {
+        format!("Song{}", self.0)
+    }
+}
+
+/// Frames each channel is still used by a sound effect
+pub(crate) const SFX_TIMERS_VAR: &str = "wSfxTimers";
+
+/// Waveform number in wave RAM, $FF when unknown
+pub(crate) const WAVE_LOADED_VAR: &str = "wWaveLoaded";
+
+/// Music driver state, zeroed at startup (name, size)
+const MUSIC_VARS: [(&str, u16); 6] = [
+    // 0 stopped, 1 playing, 2 paused
+    ("wMusicState", 1),
+    ("wMusicSong", 2),
+    ("wMusicOrder", 2),
+    ("wMusicTimer", 1),
+    ("wMusicRow", 1),
+    // Read position in the current pattern of each channel
+    ("wMusicPointers", 8),
+];
+
+/// APU settings and sound effects, reached through `RustBoy::audio`
+///
+/// # Example
+/// ```ignore
+/// let coin = gb.audio.add_sfx("Coin", SoundEffect::Pulse2 {
+///     duty: Duty::Quarter,
+///     envelope: Envelope::fade_out(15, 1),
+///     note: "B5".parse()?,
+///     length: Some(32),
+/// });
+/// inputs.on_press(PadButton::A, gb.audio.play(coin));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Audio {
+    enabled: bool,
+    /// rNR50 volumes (0-7), left and right
+    master_volume: (u8, u8),
+    /// rNR51 channel outputs
+    panning: u8,
+    effects: Vec<(String, SoundEffect)>,
+    songs: Vec<Song>,
+    waves: Vec<Waveform>,
+}
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            master_volume: (7, 7),
+            panning: 0xFF,
+            effects: Vec::new(),
+            songs: Vec::new(),
+            waves: Vec::new(),
+        }
+    }
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn the APU on at startup even without sound effects
+    pub fn enable(&mut self) -> &mut Self {
+        self.enabled = true;
+        self
+    }
+
+    /// Whether the APU is turned on at startup
+    pub fn is_enabled(&self) -> bool {
+        self.enabled || !self.effects.is_empty() || self.has_music() || !self.waves.is_empty()
+    }
+
+    /// Whether songs were added, so the music driver runs every frame
+    pub fn has_music(&self) -> bool {
+        !self.songs.is_empty()
+    }
+
+    /// Master volume of each speaker (0-7)
+    pub fn set_master_volume(&mut self, left: u8, right: u8) -> &mut Self {
+        self.master_volume = (left & 0x07, right & 0x07);
+        self
+    }
+
+    /// Choose the speakers a channel plays on
+    pub fn set_panning(&mut self, channel: Channel, left: bool, right: bool) -> &mut Self {
+        let bits = channel.mask() | channel.mask() << 4;
+        let enabled = (right as u8 * channel.mask()) | (left as u8 * (channel.mask() << 4));
+        self.panning = (self.panning & !bits) | enabled;
+        self
+    }
+
+    /// Add a sound effect to the ROM table
+    ///
+    /// Effects beyond the 256th are reported when building.
+    pub fn add_sfx(&mut self, name: &str, effect: SoundEffect) -> SfxId {
+        if let SoundEffect::Wave {
+            wave: Some(wave), ..
+        } = effect
+        {
+            self.add_waveform(wave);
+        }
+        self.effects.push((name.to_string(), effect));
+        SfxId((self.effects.len() - 1) as u8)
+    }
+
+    pub fn sfx_count(&self) -> usize {
+        self.effects.len()
+    }
+
+    /// Add a song for the music driver
+    pub fn add_song(&mut self, song: Song) -> Result<SongId, MusicError> {
+        song.validate()?;
+        for wave in song.waveforms() {
+            self.add_waveform(wave);
+        }
+        self.songs.push(song);
+        Ok(SongId(self.songs.len() - 1))
+    }
+
+    /// Add a waveform to `WaveTable`, or find the same one already there
+    ///
+    /// Waveforms of wave sound effects and instruments are added
+    /// automatically. Waveforms beyond the 255th are reported when building.
+    pub fn add_waveform(&mut self, wave: Waveform) -> WaveId {
+        let index = match self.waves.iter().position(|w| *w == wave) {
+            Some(index) => index,
+            None => {
+                self.waves.push(wave);
+                self.waves.len() - 1
+            }
+        };
+        WaveId(index as u8)
+    }
+
+    pub fn waveform_count(&self) -> usize {
+        self.waves.len()
+    }
+
+    /// Whether `LoadWave` and `WaveTable` are needed: the music driver
+    /// loads the waveforms of wave instruments
+    fn uses_waveforms(&self) -> bool {
+        self.has_music() || !self.waves.is_empty()
+    }
+
+    /// Variables used by sound effects and music (name, size)
+    pub(crate) fn variables(&self) -> Vec<(&'static str, u16)> {
+        let mut vars = Vec::new();
+        if !self.effects.is_empty() || self.has_music() {
+            vars.push((SFX_TIMERS_VAR, 4));
+        }
+        if self.has_music() {
+            vars.extend(MUSIC_VARS);
+        }
+        if self.uses_waveforms() {
+            vars.push((WAVE_LOADED_VAR, 1));
+        }
+        vars
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Start a sound effect, cutting off whatever its channel was playing
+    ///
+    /// Wave effects with a waveform load it into wave RAM first.
+    pub fn play(&self, sfx: SfxId) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        if let Some((
+            _,
+            SoundEffect::Wave {
+                wave: Some(wave), ..
+            },
+        )) = self.effects.get(sfx.0 as usize)
+            && let Some(index) = self.waves.iter().position(|w| w == wave)
+        {
+            asm.emit_all(self.load_waveform(WaveId(index as u8)));
+        }
+        asm.ld_a(sfx.0).call("PlaySfx");
+        asm.get_main_instrs()
+    }
+
+    /// Copy a waveform to wave RAM, stopping the wave channel
+    ///
+    /// Nothing is copied if the waveform is already loaded. The next wave
+    /// sound or note turns the channel back on.
+    pub fn load_waveform(&self, wave: WaveId) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a(wave.0).call("LoadWave");
+        asm.get_main_instrs()
+    }
+
+    /// Start a song from its first order, replacing the current one
+    pub fn play_music(&self, song: SongId) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_hl_label(&song.label()).call("MusicPlay");
+        asm.get_main_instrs()
+    }
+
+    /// Stop the music and silence all channels
+    pub fn stop_music(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a(0).ld_addr_def_a("wMusicState");
+        asm.emit_all(silence_channels());
+        asm.get_main_instrs()
+    }
+
+    /// Pause the music and silence all channels
+    pub fn pause_music(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a(2).ld_addr_def_a("wMusicState");
+        asm.emit_all(silence_channels());
+        asm.get_main_instrs()
+    }
+
+    /// Resume paused music from the next note (does not start stopped music)
+    pub fn resume_music(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        // Any state but stopped becomes playing: the carry is set if not 0
+        asm.ld_a_addr_def("wMusicState")
+            .add(Operand::Reg(Register::A), Operand::Imm(0xFF))
+            .ld_a(0)
+            .adc(Operand::Reg(Register::A), Operand::Imm(0))
+            .ld_addr_def_a("wMusicState");
+        asm.get_main_instrs()
+    }
+
+    /// Silence a channel by turning its DAC off
+    ///
+    /// The next sound effect on the channel turns it back on.
+    pub fn stop(&self, channel: Channel) -> Vec<Instr> {
+        let register = match channel {
+            Channel::Pulse1 => "rNR12",
+            Channel::Pulse2 => "rNR22",
+            Channel::Wave => "rNR30",
+            Channel::Noise => "rNR42",
+        };
+        let mut asm = Asm::new();
+        asm.ld_a(0).ld_addr_def_a(register);
+        asm.get_main_instrs()
+    }
+
+    // ============================================
+    // Build-time generation
+    // ============================================
+
+    /// Turn the APU on and set the volume and panning
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        if !self.is_enabled() {
+            return asm.get_main_instrs();
+        }
+        let (left, right) = self.master_volume;
+        asm.ld_a_label("AUDENA_ON")
+            .ld_addr_def_a("rNR52")
+            .ld_a(left << 4 | right)
+            .ld_addr_def_a("rNR50")
+            .ld_a(self.panning)
+            .ld_addr_def_a("rNR51");
+        // Buffers are not initialized: the driver only needs these zeroed
+        let vars: Vec<_> = self
+            .variables()
+            .into_iter()
+            .filter(|(name, _)| [SFX_TIMERS_VAR, "wMusicState"].contains(name))
+            .collect();
+        if !vars.is_empty() {
+            asm.ld_a(0);
+        }
+        for (name, size) in vars {
+            for offset in 0..size {
+                match offset {
+                    0 => asm.ld_addr_def_a(name),
+                    _ => asm.ld_addr_def_a(&format!("{} + {}", name, offset)),
+                };
+            }
+        }
+        if self.uses_waveforms() {
+            // Wave RAM holds random samples at power on
+            asm.ld_a(0xFF).ld_addr_def_a(WAVE_LOADED_VAR);
+        }
+        asm.get_main_instrs()
+    }
+
+    /// `SfxTable` rows, songs, note periods and `WaveTable` for a ROM chunk
+    ///
+    /// Each sound effect row holds the channel number, the frames it
+    /// keeps the channel, its first register and the NRx0-NRx4 values.
+    /// `WaveTable` rows are the 16 bytes of wave RAM.
+    pub(crate) fn generate_data(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (index, song) in self.songs.iter().enumerate() {
+            asm.emit_all(song.generate_data(&SongId(index).label(), &self.waves));
+        }
+        if self.has_music() {
+            asm.emit_all(generate_period_tables());
+        }
+        if self.uses_waveforms() {
+            asm.label("WaveTable");
+            for wave in self.waves.iter().take(MAX_WAVEFORMS) {
+                let bytes: Vec<String> =
+                    wave.bytes().iter().map(|b| format!("${:02X}", b)).collect();
+                asm.db(&bytes.join(", "));
+            }
+        }
+        if self.effects.is_empty() {
+            return asm.get_main_instrs();
+        }
+        asm.label("SfxTable");
+        for (name, effect) in self.effects.iter().take(MAX_SOUND_EFFECTS) {
+            let channel = Channel::ALL.iter().position(|c| *c == effect.channel());
+            let mut row = vec![channel.unwrap_or(0) as u8, effect.frames()];
+            row.push(effect.channel().first_register());
+            row.extend(effect.registers());
+            let bytes: Vec<String> = row.iter().map(|b| format!("${:02X}", b)).collect();
+            asm.comment(name);
+            asm.db(&bytes.join(", "));
+        }
+        asm.get_main_instrs()
+    }
+}
+
+/// Write 0 to NRx2 of every channel: the pulse and noise DACs turn off
+/// and the wave channel is muted
+fn silence_channels() -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld_a(0);
+    for register in ["rNR12", "rNR22", "rNR32", "rNR42"] {
+        asm.ld_addr_def_a(register);
+    }
+    asm.get_main_instrs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes() {
+        let a4: Note = "A4".parse().unwrap();
+        assert_eq!(a4.midi(), 69);
+        assert_eq!(a4.pulse_period(), 1750);
+        assert_eq!(a4.wave_period(), 1899);
+        assert_eq!("A-4".parse::<Note>(), Ok(a4));
+        assert_eq!("Bb3".parse::<Note>(), Note::new(Pitch::As, 3));
+        assert_eq!("B#3".parse::<Note>(), Note::new(Pitch::C, 4));
+        assert_eq!("F#5".parse::<Note>().unwrap().to_string(), "F#5");
+        assert_eq!("C1".parse::<Note>(), Err(AudioError::OctaveOutOfRange(1)));
+        assert_eq!(
+            "H4".parse::<Note>(),
+            Err(AudioError::InvalidNote("H4".to_string()))
+        );
+        // Lowest and highest notes fit in 11 bits on every channel
+        let c2 = Note::new(Pitch::C, 2).unwrap();
+        let b8 = Note::new(Pitch::B, 8).unwrap();
+        assert!(c2.pulse_period() < b8.pulse_period());
+        assert!(b8.pulse_period() < 2048 && b8.wave_period() < 2048);
+    }
+
+    #[test]
+    fn test_registers() {
+        let sfx = SoundEffect::Pulse1 {
+            sweep: Sweep::down(2, 3),
+            duty: Duty::Quarter,
+            envelope: Envelope::fade_out(15, 3),
+            note: "A4".parse().unwrap(),
+            length: Some(16),
+        };
+        // 1750 = $6D6
+        assert_eq!(sfx.registers(), [0x2B, 0x70, 0xF3, 0xD6, 0xC6]);
+
+        let noise = SoundEffect::Noise {
+            noise: Noise::new(5, 1).short(),
+            envelope: Envelope::fade_in(0, 1),
+            length: None,
+        };
+        assert_eq!(noise.channel().first_register(), 0x1F);
+        assert_eq!(noise.registers(), [0, 0, 0x09, 0x59, 0x80]);
+
+        let wave = SoundEffect::Wave {
+            volume: WaveVolume::Half,
+            note: "C4".parse().unwrap(),
+            length: Some(1),
+            wave: None,
+        };
+        assert_eq!(wave.registers()[..3], [0x80, 0xFF, 0x40]);
+
+        // 16/256 s, a fade from 15 every 3/64 s, a held noise
+        assert_eq!(sfx.frames(), 4);
+        let fade = SoundEffect::Pulse2 {
+            duty: Duty::Half,
+            envelope: Envelope::fade_out(15, 3),
+            note: "A4".parse().unwrap(),
+            length: None,
+        };
+        assert_eq!(fade.frames(), 43);
+        assert_eq!(noise.frames(), 255);
+    }
+
+    #[test]
+    fn test_audio_data() {
+        let mut audio = Audio::new();
+        assert!(!audio.is_enabled());
+        assert!(audio.generate_init_code().is_empty());
+
+        audio
+            .set_master_volume(3, 7)
+            .set_panning(Channel::Noise, false, true);
+        let hit = audio.add_sfx(
+            "Hit",
+            SoundEffect::Noise {
+                noise: Noise::new(2, 0),
+                envelope: Envelope::fade_out(10, 1),
+                length: Some(64),
+            },
+        );
+        assert_eq!(hit.index(), 0);
+        assert!(audio.is_enabled());
+
+        let init: Vec<String> = audio
+            .generate_init_code()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(init.contains(&"ld a, 55".to_string()));
+        assert!(init.contains(&"ld a, 127".to_string()));
+
+        let data: Vec<String> = audio
+            .generate_data()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(data.contains(&"SfxTable:".to_string()));
+        assert!(data.contains(&"db $03, $0F, $1F, $00, $00, $A1, $20, $C0".to_string()));
+        assert!(!data.contains(&"WaveTable:".to_string()));
+
+        let beep = SoundEffect::Wave {
+            volume: WaveVolume::Full,
+            note: "E5".parse().unwrap(),
+            length: None,
+            wave: Some(Waveform::square()),
+        };
+        let beep = audio.add_sfx("Beep", beep);
+        assert_eq!(audio.add_waveform(Waveform::sine()).index(), 1);
+        assert_eq!(audio.add_waveform(Waveform::square()).index(), 0);
+        let play: Vec<String> = audio.play(beep).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            play,
+            ["ld a, 0", "call LoadWave", "ld a, 1", "call PlaySfx"]
+        );
+        assert!(audio.variables().contains(&(WAVE_LOADED_VAR, 1)));
+        let data: Vec<String> = audio
+            .generate_data()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(data.contains(&"WaveTable:".to_string()));
+        assert!(data.contains(&format!(
+            "db {}$00, $00, $00, $00, $00, $00, $00, $00",
+            "$FF, ".repeat(8)
+        )));
+    }
+}
@@ -0,0 +1,301 @@
+//! Background scrolling through a camera
+//!
+//! The camera position is kept in two 16-bit WRAM variables (`wCameraX` and
+//! `wCameraY`), in world pixels. Once per frame, right after VBlank starts,
+//! the low bytes are copied to SCX/SCY: the background map wraps every 256
+//! pixels, so this is all the hardware needs.
+
+use super::labels::LabelCounter;
+use super::sprites::{SpriteId, SpriteManager};
+use super::variables::Var;
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+
+/// Visible screen width in pixels
+pub const SCREEN_WIDTH: u8 = 160;
+/// Visible screen height in pixels
+pub const SCREEN_HEIGHT: u8 = 144;
+
+// OAM coordinates are offset from the screen origin
+const OAM_X_OFFSET: u8 = 8;
+const OAM_Y_OFFSET: u8 = 16;
+
+/// A handle to the camera, returned by `RustBoy::add_camera`
+///
+/// Like `Var`, every helper returns instructions to add to the init code,
+/// the main loop or a function.
+///
+/// # Example
+/// ```ignore
+/// let camera = gb.add_camera(0, 0);
+///
+/// // Scroll one pixel to the right every frame
+/// gb.add_to_main_loop(camera.scroll_by(1, 0));
+///
+/// // Or keep the player inside a 48x40 pixel margin
+/// gb.add_to_main_loop(camera.follow_sprite(&gb.sprites, player, 48, 40));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Camera {
+    x: Var,
+    y: Var,
+    labels: LabelCounter,
+}
+
+impl Camera {
+    pub(crate) fn new(x: Var, y: Var, labels: LabelCounter) -> Self {
+        Self { x, y, labels }
+    }
+
+    /// Camera X position variable (u16, world pixels)
+    pub fn x(&self) -> &Var {
+        &self.x
+    }
+
+    /// Camera Y position variable (u16, world pixels)
+    pub fn y(&self) -> &Var {
+        &self.y
+    }
+
+    /// Move the camera to a world position
+    pub fn set(&self, x: u16, y: u16) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (var, value) in [(&self.x, x), (&self.y, y)] {
+            asm.ld_a((value & 0xFF) as u8)
+                .ld_addr_def_a(var.name())
+                .ld_a((value >> 8) as u8)
+                .ld_addr_def_a(&format!("{}+1", var.name()));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Move the camera by a signed number of pixels on each axis
+    ///
+    /// The position wraps around at 0 and 65535.
+    pub fn scroll_by(&self, dx: i8, dy: i8) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (var, delta) in [(&self.x, dx), (&self.y, dy)] {
+            if delta == 0 {
+                continue;
+            }
+            // Sign-extend the delta into the high byte
+            let high = if delta < 0 { 0xFF } else { 0x00 };
+            asm.ld_a_addr_def(var.name())
+                .add(Operand::Reg(Register::A), Operand::Imm(delta as u8))
+                .ld_addr_def_a(var.name())
+                .ld_a_addr_def(&format!("{}+1", var.name()))
+                .adc(Operand::Reg(Register::A), Operand::Imm(high))
+                .ld_addr_def_a(&format!("{}+1", var.name()));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Scroll the camera so a sprite stays inside a margin of the screen
+    ///
+    /// When the sprite moves closer than `margin_x`/`margin_y` pixels to an
+    /// edge, it is pushed back and the camera moves by the same amount, so
+    /// the sprite keeps its world position. The camera stops at 0, after
+    /// which the sprite can reach the left and top edges. Assumes 8x16 sprites.
+    pub fn follow_sprite(
+        &self,
+        sprites: &SpriteManager,
+        id: SpriteId,
+        margin_x: u8,
+        margin_y: u8,
+    ) -> Vec<Instr> {
+        let Some(sprite) = sprites.lookup(id, "follow_sprite") else {
+            return Vec::new();
+        };
+        let oam_y = format!("_OAMRAM+{}", sprite.oam_index * 4);
+        let oam_x = format!("_OAMRAM+{}", sprite.oam_index * 4 + 1);
+
+        let mut asm = Asm::new();
+        asm.emit_all(self.follow_axis(
+            &oam_x,
+            self.x.name(),
+            OAM_X_OFFSET.saturating_add(margin_x),
+            OAM_X_OFFSET + SCREEN_WIDTH.saturating_sub(margin_x.saturating_add(8)),
+        ));
+        asm.emit_all(self.follow_axis(
+            &oam_y,
+            self.y.name(),
+            OAM_Y_OFFSET.saturating_add(margin_y),
+            OAM_Y_OFFSET + SCREEN_HEIGHT.saturating_sub(margin_y.saturating_add(16)),
+        ));
+        asm.get_main_instrs()
+    }
+
+    /// Keep an OAM coordinate between `min` and `max`, moving the camera instead
+    fn follow_axis(&self, oam: &str, var: &str, min: u8, max: u8) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        let high_byte = format!("{}+1", var);
+        let low_end = self.labels.unique("CameraFollowLowEnd");
+        let high_end = self.labels.unique("CameraFollowHighEnd");
+
+        // Sprite too close to the left/top edge
+        asm.ld_a_addr_def(oam)
+            .cp(Operand::Imm(min))
+            .jp_cond(Condition::NC, &low_end)
+            .ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+        // Camera already at 0: let the sprite move
+        asm.ld_a_addr_def(var)
+            .ld(Operand::Reg(Register::C), Operand::Reg(Register::A))
+            .ld_a_addr_def(&high_byte)
+            .or_label("a", "c")
+            .jp_cond(Condition::Z, &low_end);
+        // b = distance past the margin
+        asm.ld_a(min)
+            .sub(Operand::Reg(Register::A), Operand::Reg(Register::B))
+            .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+            .ld_a(min)
+            .ld_addr_def_a(oam);
+        // camera -= b, clamped at 0
+        asm.ld_a_addr_def(var)
+            .sub(Operand::Reg(Register::A), Operand::Reg(Register::B))
+            .ld_addr_def_a(var)
+            .ld_a_addr_def(&high_byte)
+            .sbc(Operand::Reg(Register::A), Operand::Imm(0))
+            .ld_addr_def_a(&high_byte)
+            .jp_cond(Condition::NC, &low_end)
+            .xor(Operand::Reg(Register::A), Operand::Reg(Register::A))
+            .ld_addr_def_a(var)
+            .ld_addr_def_a(&high_byte);
+        asm.label(&low_end);
+
+        // Sprite too close to the right/bottom edge
+        asm.ld_a_addr_def(oam)
+            .cp(Operand::Imm(max.saturating_add(1)))
+            .jp_cond(Condition::C, &high_end)
+            .sub(Operand::Reg(Register::A), Operand::Imm(max))
+            .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+            .ld_a(max)
+            .ld_addr_def_a(oam);
+        // camera += b
+        asm.ld_a_addr_def(var)
+            .add(Operand::Reg(Register::A), Operand::Reg(Register::B))
+            .ld_addr_def_a(var)
+            .ld_a_addr_def(&high_byte)
+            .adc(Operand::Reg(Register::A), Operand::Imm(0))
+            .ld_addr_def_a(&high_byte);
+        asm.label(&high_end);
+
+        asm.get_main_instrs()
+    }
+
+    /// Convert a screen pixel position to a background map position
+    ///
+    /// Adds the camera position to b (X) and c (Y), so the result of
+    /// `get_pivot` can be passed to `GetTileByPixel` while scrolled.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let mut setup = gb.sprites.get_pivot(ball, 0, 1);
+    /// setup.extend(camera.screen_to_map());
+    /// gb.call_args("GetTileByPixel", setup);
+    /// ```
+    pub fn screen_to_map(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(self.x.name())
+            .add(Operand::Reg(Register::A), Operand::Reg(Register::B))
+            .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+            .ld_a_addr_def(self.y.name())
+            .add(Operand::Reg(Register::A), Operand::Reg(Register::C))
+            .ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+        asm.get_main_instrs()
+    }
+
+    /// Place a sprite on screen at a world position held in two variables
+    ///
+    /// The sprite is drawn at `world - camera`; only the low bytes are used,
+    /// so the result is correct while the sprite is within the visible area.
+    pub fn place_sprite(
+        &self,
+        sprites: &SpriteManager,
+        id: SpriteId,
+        world_x: &Var,
+        world_y: &Var,
+    ) -> Vec<Instr> {
+        let Some(sprite) = sprites.lookup(id, "place_sprite") else {
+            return Vec::new();
+        };
+
+        let mut asm = Asm::new();
+        for (world, camera, oam_offset, bias) in [
+            (world_x, &self.x, sprite.oam_index * 4 + 1, OAM_X_OFFSET),
+            (world_y, &self.y, sprite.oam_index * 4, OAM_Y_OFFSET),
+        ] {
+            asm.ld_a_addr_def(camera.name())
+                .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+                .ld_a_addr_def(world.name())
+                .sub(Operand::Reg(Register::A), Operand::Reg(Register::B))
+                .add(Operand::Reg(Register::A), Operand::Imm(bias))
+                .ld_addr_def_a(&format!("_OAMRAM+{}", oam_offset));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Copy the camera position to SCX/SCY (emitted right after VBlank)
+    pub(crate) fn generate_vblank_update(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(self.x.name())
+            .ld_addr_def_a("rSCX")
+            .ld_a_addr_def(self.y.name())
+            .ld_addr_def_a("rSCY");
+        asm.get_main_instrs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb_asm::SymbolTable;
+    use crate::rust_boy::variables::VariableManager;
+
+    fn camera() -> Camera {
+        let mut vars = VariableManager::new();
+        Camera::new(
+            vars.create_u16("wCameraX", 0),
+            vars.create_u16("wCameraY", 0),
+            LabelCounter::new(),
+        )
+    }
+
+    fn render(instrs: &[Instr]) -> Vec<String> {
+        instrs.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_scroll_by_sign_extends() {
+        let lines = render(&camera().scroll_by(-2, 0));
+        assert_eq!(
+            lines,
+            vec![
+                "ld a, [wCameraX]",
+                "add a, 254",
+                "ld [wCameraX], a",
+                "ld a, [wCameraX+1]",
+                "adc a, 255",
+                "ld [wCameraX+1], a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_follow_sprite_labels_are_unique() {
+        let mut sprites = SpriteManager::new();
+        let player = sprites.add("Player", 80, 72, 0, 2);
+        let camera = camera();
+
+        let first = camera.follow_sprite(&sprites, player, 48, 40);
+        let second = camera.follow_sprite(&sprites, player, 48, 40);
+        assert!(!first.is_empty());
+        assert!(
+            SymbolTable::from_instrs(first.iter().chain(&second))
+                .check_labels()
+                .is_empty()
+        );
+
+        let missing = camera.follow_sprite(&sprites, SpriteId(7), 48, 40);
+        assert!(missing.is_empty());
+        assert_eq!(sprites.missing_sprites().len(), 1);
+    }
+}
@@ -0,0 +1,381 @@
+//! Game Boy Color support: RGB555 palettes, attributes and VRAM bank 1
+//!
+//! CGB mode is off by default. Once enabled with `CgbConfig::enable`, the
+//! header asks for CGB features, the palettes are uploaded at startup and
+//! the console type is stored in `wIsCgb`. On a DMG the CGB registers are
+//! simply ignored: the DMG palettes of `DisplayConfig` still apply and VRAM
+//! bank 1 is not loaded, so a `CgbMode::Compatible` game stays playable.
+
+use std::cell::Cell;
+
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+
+/// Number of background palettes and of object palettes
+pub const MAX_CGB_PALETTES: usize = 8;
+
+/// Variable set to 1 at startup on a CGB, 0 on a DMG
+pub(crate) const IS_CGB_VAR: &str = "wIsCgb";
+
+/// A color in the CGB RGB555 format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color(u16);
+
+impl Color {
+    pub const WHITE: Color = Color::rgb555(31, 31, 31);
+    pub const LIGHT_GRAY: Color = Color::rgb555(21, 21, 21);
+    pub const DARK_GRAY: Color = Color::rgb555(10, 10, 10);
+    pub const BLACK: Color = Color::rgb555(0, 0, 0);
+
+    /// Create a color from 5-bit channels (0-31)
+    pub const fn rgb555(r: u8, g: u8, b: u8) -> Self {
+        Color((r as u16 & 0x1F) | (g as u16 & 0x1F) << 5 | (b as u16 & 0x1F) << 10)
+    }
+
+    /// Create a color from 8-bit channels, as in image editors
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color::rgb555(r >> 3, g >> 3, b >> 3)
+    }
+
+    /// Raw value written to the palette registers (little-endian)
+    pub fn word(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Four colors used by a background or object palette
+///
+/// Color 0 of object palettes is transparent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CgbPalette(pub [Color; 4]);
+
+impl CgbPalette {
+    /// Matches the default DMG palette
+    pub const GRAYSCALE: CgbPalette = CgbPalette([
+        Color::WHITE,
+        Color::LIGHT_GRAY,
+        Color::DARK_GRAY,
+        Color::BLACK,
+    ]);
+
+    pub fn new(colors: [Color; 4]) -> Self {
+        CgbPalette(colors)
+    }
+
+    /// Palette data as written to rBCPD/rOCPD
+    pub fn bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        for (i, color) in self.0.iter().enumerate() {
+            bytes[i * 2..i * 2 + 2].copy_from_slice(&color.word().to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// How the cartridge header advertises CGB support
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbMode {
+    /// Runs on both DMG and CGB (`CART_COMPATIBLE_DMG_GBC`)
+    Compatible,
+    /// CGB only (`CART_COMPATIBLE_GBC`)
+    Exclusive,
+}
+
+impl CgbMode {
+    /// hardware.inc constant for the header byte at $0143
+    pub fn header_flag(&self) -> &'static str {
+        match self {
+            CgbMode::Compatible => "CART_COMPATIBLE_DMG_GBC",
+            CgbMode::Exclusive => "CART_COMPATIBLE_GBC",
+        }
+    }
+}
+
+/// CGB tile attributes, for OAM flags and background attribute maps
+///
+/// # Example
+/// ```ignore
+/// let flags = TileAttributes::new().palette(2).flip_x().byte();
+/// let player = gb.add_sprite("Player", source, 80, 72, flags);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TileAttributes(u8);
+
+impl TileAttributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// CGB palette (0-7)
+    pub fn palette(self, palette: u8) -> Self {
+        TileAttributes(self.0 & !0x07 | palette & 0x07)
+    }
+
+    /// Use the tile from VRAM bank 1
+    pub fn vram_bank1(self) -> Self {
+        TileAttributes(self.0 | 0x08)
+    }
+
+    /// Use OBP1 on a DMG (objects only)
+    pub fn dmg_palette1(self) -> Self {
+        TileAttributes(self.0 | 0x10)
+    }
+
+    pub fn flip_x(self) -> Self {
+        TileAttributes(self.0 | 0x20)
+    }
+
+    pub fn flip_y(self) -> Self {
+        TileAttributes(self.0 | 0x40)
+    }
+
+    /// Draw behind the background (objects) or above objects (background)
+    pub fn priority(self) -> Self {
+        TileAttributes(self.0 | 0x80)
+    }
+
+    /// Raw attribute byte
+    pub fn byte(&self) -> u8 {
+        self.0
+    }
+}
+
+impl From<TileAttributes> for u8 {
+    fn from(attributes: TileAttributes) -> u8 {
+        attributes.0
+    }
+}
+
+/// CGB settings and palettes, reached through `RustBoy::cgb`
+///
+/// # Example
+/// ```ignore
+/// gb.cgb.enable(CgbMode::Compatible);
+/// let sky = gb.cgb.add_bg_palette(CgbPalette::new([
+///     Color::rgb(0xE0, 0xF8, 0xFF), Color::rgb(0x88, 0xC0, 0x70),
+///     Color::rgb(0x34, 0x68, 0x56), Color::BLACK,
+/// ]));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CgbConfig {
+    mode: Option<CgbMode>,
+    bg_palettes: Vec<CgbPalette>,
+    obj_palettes: Vec<CgbPalette>,
+    double_speed: bool,
+    // Set by runtime helpers, which only borrow the config
+    uses_detection: Cell<bool>,
+}
+
+impl CgbConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn CGB mode on
+    pub fn enable(&mut self, mode: CgbMode) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn mode(&self) -> Option<CgbMode> {
+        self.mode
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode.is_some()
+    }
+
+    /// Switch to double speed at startup, while the screen is off
+    ///
+    /// The CPU and timers run twice as fast, the LCD and sound do not. A
+    /// DMG keeps running at normal speed.
+    pub fn set_double_speed(&mut self, enabled: bool) -> &mut Self {
+        self.double_speed = enabled;
+        self
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+
+    /// Add a background palette, returning its number for attributes
+    ///
+    /// Palettes beyond the eighth are reported when building.
+    pub fn add_bg_palette(&mut self, palette: CgbPalette) -> u8 {
+        self.bg_palettes.push(palette);
+        (self.bg_palettes.len() - 1) as u8
+    }
+
+    /// Add an object palette, returning its number for OAM flags
+    ///
+    /// Palettes beyond the eighth are reported when building.
+    pub fn add_obj_palette(&mut self, palette: CgbPalette) -> u8 {
+        self.obj_palettes.push(palette);
+        (self.obj_palettes.len() - 1) as u8
+    }
+
+    /// Number of background and object palettes
+    pub(crate) fn palette_counts(&self) -> (usize, usize) {
+        (self.bg_palettes.len(), self.obj_palettes.len())
+    }
+
+    /// Whether `wIsCgb` is needed
+    pub(crate) fn uses_detection(&self) -> bool {
+        self.uses_detection.get()
+    }
+
+    /// Record that generated code reads `wIsCgb`
+    pub(crate) fn use_detection(&self) {
+        self.uses_detection.set(true);
+    }
+
+    // ============================================
+    // Build-time generation
+    // ============================================
+
+    /// Store the console type, must run first at EntryPoint (reads `a`)
+    pub(crate) fn generate_detect_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.cp_label("BOOTUP_A_CGB")
+            .ld_a(0)
+            .jr_cond(Condition::NZ, "EntryPointDmg")
+            .inc_label("a");
+        asm.label("EntryPointDmg");
+        asm.ld_addr_def_a(IS_CGB_VAR);
+        asm.get_main_instrs()
+    }
+
+    /// Upload all palettes (ignored by a DMG)
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (palettes, index, data, label) in [
+            (&self.bg_palettes, "rBCPS", "rBCPD", "CgbBgPalettes"),
+            (&self.obj_palettes, "rOCPS", "rOCPD", "CgbObjPalettes"),
+        ] {
+            if palettes.is_empty() {
+                continue;
+            }
+            let count = palettes.len().min(MAX_CGB_PALETTES) * 8;
+            asm.ld_a(0x80) // Auto-increment from palette 0
+                .ld_addr_def_a(index)
+                .ld_hl_label(label)
+                .ld_b(count as u8);
+            asm.label(&format!("{}Load", label));
+            asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL))
+                .ld_addr_def_a(data)
+                .dec_label("b")
+                .jr_cond(Condition::NZ, &format!("{}Load", label));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Palette tables for a ROM chunk
+    pub(crate) fn generate_data(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (palettes, label) in [
+            (&self.bg_palettes, "CgbBgPalettes"),
+            (&self.obj_palettes, "CgbObjPalettes"),
+        ] {
+            if palettes.is_empty() {
+                continue;
+            }
+            asm.label(label);
+            for palette in palettes.iter().take(MAX_CGB_PALETTES) {
+                let colors: Vec<String> = palette
+                    .0
+                    .iter()
+                    .map(|c| format!("${:04X}", c.word()))
+                    .collect();
+                asm.dw(&colors.join(", "));
+            }
+        }
+        asm.get_main_instrs()
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Switch to double speed now (uses the `SwitchDoubleSpeed` builtin)
+    pub fn switch_double_speed(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.call("SwitchDoubleSpeed");
+        asm.get_main_instrs()
+    }
+
+    /// Clear the Z flag on a CGB, set it on a DMG
+    pub fn is_cgb(&self) -> Vec<Instr> {
+        self.use_detection();
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(IS_CGB_VAR).or_label("a", "a");
+        asm.get_main_instrs()
+    }
+
+    /// Replace a background palette (needs VRAM access on a CGB)
+    pub fn set_bg_palette(&self, index: u8, palette: &CgbPalette) -> Vec<Instr> {
+        write_palette("rBCPS", "rBCPD", index, palette)
+    }
+
+    /// Replace an object palette (needs VRAM access on a CGB)
+    pub fn set_obj_palette(&self, index: u8, palette: &CgbPalette) -> Vec<Instr> {
+        write_palette("rOCPS", "rOCPD", index, palette)
+    }
+}
+
+fn write_palette(index_reg: &str, data_reg: &str, index: u8, palette: &CgbPalette) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld_a(0x80 | ((index & 0x07) * 8))
+        .ld_addr_def_a(index_reg);
+    for byte in palette.bytes() {
+        asm.ld_a(byte).ld_addr_def_a(data_reg);
+    }
+    asm.get_main_instrs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors_and_palettes() {
+        assert_eq!(Color::rgb(0xFF, 0x00, 0x80).word(), 0x401F);
+        assert_eq!(Color::WHITE.word(), 0x7FFF);
+        assert_eq!(
+            CgbPalette::new([
+                Color::WHITE,
+                Color::BLACK,
+                Color::rgb555(1, 2, 3),
+                Color::BLACK
+            ])
+            .bytes(),
+            [0xFF, 0x7F, 0x00, 0x00, 0x41, 0x0C, 0x00, 0x00]
+        );
+        assert_eq!(
+            TileAttributes::new()
+                .palette(9)
+                .vram_bank1()
+                .flip_y()
+                .byte(),
+            0x49
+        );
+    }
+
+    #[test]
+    fn test_palette_upload() {
+        let mut cgb = CgbConfig::new();
+        cgb.enable(CgbMode::Compatible);
+        assert_eq!(cgb.add_bg_palette(CgbPalette::GRAYSCALE), 0);
+        assert_eq!(cgb.add_bg_palette(CgbPalette::GRAYSCALE), 1);
+
+        let lines: Vec<String> = cgb
+            .generate_init_code()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(lines.contains(&"ld b, 16".to_string()));
+        assert!(lines.contains(&"ld [rBCPD], a".to_string()));
+        assert!(!lines.iter().any(|l| l.contains("rOCPS")));
+
+        let data: Vec<String> = cgb.generate_data().iter().map(|i| i.to_string()).collect();
+        assert_eq!(data[1], "dw $7FFF, $56B5, $294A, $0000");
+    }
+}
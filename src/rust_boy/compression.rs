@@ -0,0 +1,192 @@
+//! Build-time compression of tile data
+//!
+//! Both formats are streams of packets that start with a header byte:
+//!
+//! - `$00` ends the stream
+//! - `$01`-`$7F`: that many literal bytes follow
+//! - `$80`-`$FF`: RLE repeats the next byte `(header & $7F) + 1` times;
+//!   LZ copies `(header & $7F) + 3` bytes already written, starting
+//!   `distance` bytes back (16-bit little-endian distance after the header)
+//!
+//! The matching decompressors are the `DecompressRle` and `DecompressLz`
+//! builtins, which write straight to VRAM while the screen is off.
+
+/// Longest literal run in one packet
+const MAX_LITERALS: usize = 0x7F;
+/// Longest RLE run in one packet
+const MAX_RUN: usize = 0x80;
+/// Shortest and longest LZ match in one packet
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0x7F + MIN_MATCH;
+/// How far back the LZ encoder looks for matches
+const LZ_WINDOW: usize = 4096;
+
+/// Compression format for a tileset or tilemap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Run-length encoding, best for large areas of one color or tile
+    Rle,
+    /// Back-references to earlier bytes, best for repeated patterns
+    Lz,
+}
+
+impl Compression {
+    /// Compress data, including the end marker
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::Rle => compress_rle(data),
+            Compression::Lz => compress_lz(data),
+        }
+    }
+
+    /// Builtin that decompresses this format
+    ///
+    /// Called with `de` pointing to the compressed data and `hl` to the
+    /// destination.
+    pub fn routine(&self) -> &'static str {
+        match self {
+            Compression::Rle => "DecompressRle",
+            Compression::Lz => "DecompressLz",
+        }
+    }
+}
+
+/// Append literal packets for `bytes`
+fn push_literals(out: &mut Vec<u8>, bytes: &[u8]) {
+    for chunk in bytes.chunks(MAX_LITERALS) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn compress_rle(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&b| b == data[i])
+            .count();
+        // Runs of two bytes are cheaper as literals
+        if run >= 3 {
+            push_literals(&mut out, &data[literal_start..i]);
+            out.push(0x80 | (run - 1) as u8);
+            out.push(data[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    push_literals(&mut out, &data[literal_start..]);
+    out.push(0);
+    out
+}
+
+fn compress_lz(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let (distance, length) = longest_match(data, i);
+        // A 3-byte match costs as much as the literals, so it only helps
+        // when it ends a literal run early
+        if length > MIN_MATCH || (length == MIN_MATCH && literal_start == i) {
+            push_literals(&mut out, &data[literal_start..i]);
+            out.push(0x80 | (length - MIN_MATCH) as u8);
+            out.extend_from_slice(&(distance as u16).to_le_bytes());
+            i += length;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+    push_literals(&mut out, &data[literal_start..]);
+    out.push(0);
+    out
+}
+
+/// Longest earlier match for the bytes at `pos`, as (distance, length)
+///
+/// Matches may overlap `pos`, since the decompressor copies byte by byte.
+fn longest_match(data: &[u8], pos: usize) -> (usize, usize) {
+    let mut best = (0, 0);
+    for start in pos.saturating_sub(LZ_WINDOW)..pos {
+        let length = (0..MAX_MATCH.min(data.len() - pos))
+            .take_while(|&k| data[start + k] == data[pos + k])
+            .count();
+        if length > best.1 {
+            best = (pos - start, length);
+            if length == MAX_MATCH {
+                break;
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reference decompressor, mirroring the builtin routines
+    fn decompress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut i = 0;
+        loop {
+            let header = data[i] as usize;
+            i += 1;
+            match header {
+                0 => return out,
+                1..=0x7F => {
+                    out.extend_from_slice(&data[i..i + header]);
+                    i += header;
+                }
+                _ => match compression {
+                    Compression::Rle => {
+                        out.extend(std::iter::repeat_n(data[i], (header & 0x7F) + 1));
+                        i += 1;
+                    }
+                    Compression::Lz => {
+                        let distance = u16::from_le_bytes([data[i], data[i + 1]]) as usize;
+                        i += 2;
+                        for _ in 0..(header & 0x7F) + MIN_MATCH {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut data = vec![0u8; 300];
+        data.extend((0..200).map(|i| (i % 7) as u8));
+        data.extend([1, 2, 2, 3, 3, 3, 9]);
+        data.extend(0..=255);
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = sample();
+        for compression in [Compression::Rle, Compression::Lz] {
+            let packed = compression.compress(&data);
+            assert_eq!(decompress(compression, &packed), data);
+            assert_eq!(packed.last(), Some(&0));
+        }
+        assert_eq!(Compression::Rle.compress(&[]), vec![0]);
+    }
+
+    #[test]
+    fn test_compression_shrinks_repeated_data() {
+        let data = sample();
+        assert!(Compression::Rle.compress(&data).len() < data.len());
+        assert!(Compression::Lz.compress(&data).len() < Compression::Rle.compress(&data).len());
+        assert_eq!(Compression::Rle.compress(&[7; 4]), vec![0x83, 7, 0]);
+    }
+}
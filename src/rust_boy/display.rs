@@ -0,0 +1,355 @@
+//! LCD configuration: LCDC flags and DMG palettes
+//!
+//! `DisplayConfig` describes the state the screen is turned on with at
+//! startup. The runtime helpers return instructions that change palettes and
+//! LCDC bits from game code.
+
+use crate::gb_asm::{Asm, Instr};
+
+/// One of the four DMG shades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shade {
+    White = 0,
+    LightGray = 1,
+    DarkGray = 2,
+    Black = 3,
+}
+
+impl Shade {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => Shade::White,
+            1 => Shade::LightGray,
+            2 => Shade::DarkGray,
+            _ => Shade::Black,
+        }
+    }
+}
+
+/// A DMG palette (BGP, OBP0 or OBP1), mapping color IDs 0-3 to shades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(u8);
+
+impl Palette {
+    /// Color 0 is white, color 3 is black
+    pub const DEFAULT: Palette = Palette(0b11100100);
+    /// Color 0 is black, color 3 is white
+    pub const INVERTED: Palette = Palette(0b00011011);
+
+    /// Create a palette from the shades of color IDs 0 to 3
+    pub fn new(shades: [Shade; 4]) -> Self {
+        let byte = shades
+            .iter()
+            .enumerate()
+            .fold(0u8, |acc, (i, shade)| acc | ((*shade as u8) << (i * 2)));
+        Palette(byte)
+    }
+
+    /// Create a palette from the raw register value
+    pub const fn from_byte(byte: u8) -> Self {
+        Palette(byte)
+    }
+
+    /// Raw register value
+    pub fn byte(&self) -> u8 {
+        self.0
+    }
+
+    /// Shade used for a color ID (0-3)
+    pub fn shade(&self, color: u8) -> Shade {
+        Shade::from_bits(self.0 >> ((color & 0b11) * 2))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::DEFAULT
+    }
+}
+
+/// Hardware sprite size (LCDC bit 2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SpriteSize {
+    Size8x8,
+    #[default]
+    Size8x16,
+}
+
+/// Tile data area used by the background and window (LCDC bit 4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileDataArea {
+    /// $8000-$8FFF, tile IDs 0-255 (shared with sprites)
+    Unsigned8000,
+    /// $8800-$97FF, tile IDs -128-127 relative to $9000
+    #[default]
+    Signed8800,
+}
+
+/// Tile map used by the background or the window (LCDC bits 3 and 6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileMapArea {
+    #[default]
+    Map9800,
+    Map9C00,
+}
+
+impl TileMapArea {
+    /// VRAM address of the first tile of the map
+    pub fn base_address(self) -> u16 {
+        match self {
+            TileMapArea::Map9800 => 0x9800,
+            TileMapArea::Map9C00 => 0x9C00,
+        }
+    }
+}
+
+/// Screen configuration applied when the LCD is turned on
+///
+/// The default matches what RustBoy has always used: background and 8x16
+/// sprites on, tile data at $8800, both maps at $9800, window off and the
+/// standard palette everywhere.
+///
+/// # Example
+/// ```ignore
+/// gb.display.sprite_size = SpriteSize::Size8x8;
+/// gb.display.window_enabled = true;
+/// gb.display.window_tile_map = TileMapArea::Map9C00;
+/// gb.display.obj_palette1 = Palette::INVERTED;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayConfig {
+    pub bg_enabled: bool,
+    pub sprites_enabled: bool,
+    pub sprite_size: SpriteSize,
+    pub bg_tile_data: TileDataArea,
+    pub bg_tile_map: TileMapArea,
+    pub window_enabled: bool,
+    pub window_tile_map: TileMapArea,
+    pub bg_palette: Palette,
+    pub obj_palette0: Palette,
+    pub obj_palette1: Palette,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        Self {
+            bg_enabled: true,
+            sprites_enabled: true,
+            sprite_size: SpriteSize::default(),
+            bg_tile_data: TileDataArea::default(),
+            bg_tile_map: TileMapArea::default(),
+            window_enabled: false,
+            window_tile_map: TileMapArea::default(),
+            bg_palette: Palette::DEFAULT,
+            obj_palette0: Palette::DEFAULT,
+            obj_palette1: Palette::DEFAULT,
+        }
+    }
+}
+
+impl DisplayConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// LCDC value as a hardware.inc expression (`LCDCF_ON | ...`)
+    pub fn lcdc_flags(&self) -> String {
+        let mut flags = vec!["LCDCF_ON"];
+        if self.bg_enabled {
+            flags.push("LCDCF_BGON");
+        }
+        if self.sprites_enabled {
+            flags.push("LCDCF_OBJON");
+        }
+        if self.sprite_size == SpriteSize::Size8x16 {
+            flags.push("LCDCF_OBJ16");
+        }
+        if self.bg_tile_data == TileDataArea::Unsigned8000 {
+            flags.push("LCDCF_BG8000");
+        }
+        if self.bg_tile_map == TileMapArea::Map9C00 {
+            flags.push("LCDCF_BG9C00");
+        }
+        if self.window_enabled {
+            flags.push("LCDCF_WINON");
+        }
+        if self.window_tile_map == TileMapArea::Map9C00 {
+            flags.push("LCDCF_WIN9C00");
+        }
+        flags.join(" | ")
+    }
+
+    /// Set the palettes and turn the LCD on
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        // Palettes first, so the first frame is drawn with the right colors
+        asm.ld_a(self.bg_palette.byte())
+            .ld_addr_def_a("rBGP")
+            .ld_a(self.obj_palette0.byte())
+            .ld_addr_def_a("rOBP0")
+            .ld_a(self.obj_palette1.byte())
+            .ld_addr_def_a("rOBP1");
+        asm.ld_a_label(&self.lcdc_flags()).ld_addr_def_a("rLCDC");
+        asm.get_main_instrs()
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Change the background palette
+    pub fn set_bg_palette(&self, palette: Palette) -> Vec<Instr> {
+        set_palette("rBGP", palette)
+    }
+
+    /// Change sprite palette 0
+    pub fn set_obj_palette0(&self, palette: Palette) -> Vec<Instr> {
+        set_palette("rOBP0", palette)
+    }
+
+    /// Change sprite palette 1
+    pub fn set_obj_palette1(&self, palette: Palette) -> Vec<Instr> {
+        set_palette("rOBP1", palette)
+    }
+
+    /// Set LCDC bits (e.g. `"LCDCF_WINON"`), keeping the others
+    pub fn set_lcdc_flags(&self, flags: &str) -> Vec<Instr> {
+        update_lcdc(None, Some(flags))
+    }
+
+    /// Clear LCDC bits (e.g. `"LCDCF_OBJON"`), keeping the others
+    pub fn clear_lcdc_flags(&self, flags: &str) -> Vec<Instr> {
+        update_lcdc(Some(flags), None)
+    }
+
+    pub fn show_window(&self) -> Vec<Instr> {
+        self.set_lcdc_flags("LCDCF_WINON")
+    }
+
+    pub fn hide_window(&self) -> Vec<Instr> {
+        self.clear_lcdc_flags("LCDCF_WINON")
+    }
+
+    pub fn show_sprites(&self) -> Vec<Instr> {
+        self.set_lcdc_flags("LCDCF_OBJON")
+    }
+
+    pub fn hide_sprites(&self) -> Vec<Instr> {
+        self.clear_lcdc_flags("LCDCF_OBJON")
+    }
+
+    pub fn show_background(&self) -> Vec<Instr> {
+        self.set_lcdc_flags("LCDCF_BGON")
+    }
+
+    pub fn hide_background(&self) -> Vec<Instr> {
+        self.clear_lcdc_flags("LCDCF_BGON")
+    }
+
+    /// Switch the hardware sprite size
+    pub fn set_sprite_size(&self, size: SpriteSize) -> Vec<Instr> {
+        match size {
+            SpriteSize::Size8x8 => self.clear_lcdc_flags("LCDCF_OBJ16"),
+            SpriteSize::Size8x16 => self.set_lcdc_flags("LCDCF_OBJ16"),
+        }
+    }
+
+    /// Switch the background tile data area
+    pub fn set_bg_tile_data(&self, area: TileDataArea) -> Vec<Instr> {
+        match area {
+            TileDataArea::Signed8800 => self.clear_lcdc_flags("LCDCF_BG8000"),
+            TileDataArea::Unsigned8000 => self.set_lcdc_flags("LCDCF_BG8000"),
+        }
+    }
+
+    /// Switch the background tile map
+    pub fn set_bg_tile_map(&self, map: TileMapArea) -> Vec<Instr> {
+        match map {
+            TileMapArea::Map9800 => self.clear_lcdc_flags("LCDCF_BG9C00"),
+            TileMapArea::Map9C00 => self.set_lcdc_flags("LCDCF_BG9C00"),
+        }
+    }
+
+    /// Switch the window tile map
+    pub fn set_window_tile_map(&self, map: TileMapArea) -> Vec<Instr> {
+        match map {
+            TileMapArea::Map9800 => self.clear_lcdc_flags("LCDCF_WIN9C00"),
+            TileMapArea::Map9C00 => self.set_lcdc_flags("LCDCF_WIN9C00"),
+        }
+    }
+}
+
+fn set_palette(register: &str, palette: Palette) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld_a(palette.byte()).ld_addr_def_a(register);
+    asm.get_main_instrs()
+}
+
+/// Read-modify-write of rLCDC
+fn update_lcdc(clear: Option<&str>, set: Option<&str>) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld_a_addr_def("rLCDC");
+    if let Some(flags) = clear {
+        asm.and_label(&format!("~({}) & $FF", flags));
+    }
+    if let Some(flags) = set {
+        asm.or_label("a", flags);
+    }
+    asm.ld_addr_def_a("rLCDC");
+    asm.get_main_instrs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(instrs: &[Instr]) -> Vec<String> {
+        instrs.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_lcdc_flags() {
+        let config = DisplayConfig::default();
+        assert_eq!(
+            config.lcdc_flags(),
+            "LCDCF_ON | LCDCF_BGON | LCDCF_OBJON | LCDCF_OBJ16"
+        );
+
+        let config = DisplayConfig {
+            sprite_size: SpriteSize::Size8x8,
+            bg_tile_data: TileDataArea::Unsigned8000,
+            window_enabled: true,
+            window_tile_map: TileMapArea::Map9C00,
+            ..DisplayConfig::default()
+        };
+        assert_eq!(
+            config.lcdc_flags(),
+            "LCDCF_ON | LCDCF_BGON | LCDCF_OBJON | LCDCF_BG8000 | LCDCF_WINON | LCDCF_WIN9C00"
+        );
+    }
+
+    #[test]
+    fn test_palette_shades() {
+        let palette = Palette::new([
+            Shade::White,
+            Shade::LightGray,
+            Shade::DarkGray,
+            Shade::Black,
+        ]);
+        assert_eq!(palette, Palette::DEFAULT);
+        assert_eq!(Palette::INVERTED.shade(0), Shade::Black);
+        assert_eq!(Palette::INVERTED.shade(3), Shade::White);
+    }
+
+    #[test]
+    fn test_runtime_lcdc_helpers() {
+        let config = DisplayConfig::default();
+        let hide = render(&config.hide_sprites());
+        assert_eq!(hide.len(), 3);
+        assert!(hide[1].contains("~(LCDCF_OBJON) & $FF"));
+
+        let show = render(&config.show_window());
+        assert!(show[1].contains("LCDCF_WINON"));
+        assert!(show[2].contains("rLCDC"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Errors reported when building a RustBoy program
+
+use std::fmt;
+
+use super::audio::MAX_SOUND_EFFECTS;
+use super::display::{TileDataArea, TileMapArea};
+use super::memory::{MemoryError, MemoryUsage};
+use super::sprites::{CompositeSpriteId, SpriteId};
+use super::waveform::MAX_WAVEFORMS;
+use crate::gb_asm::SymbolError;
+
+/// A single problem found while building the program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// A tileset, sprite or variable did not fit in memory
+    Memory(MemoryError),
+    /// `RustBoy::call` targeted a function that is never defined
+    UnknownFunction {
+        name: String,
+        available: Vec<String>,
+    },
+    /// A sprite helper was given an ID the SpriteManager does not know
+    MissingSprite { sprite: SpriteId, context: String },
+    /// A composite sprite helper was given an unknown ID
+    MissingCompositeSprite {
+        sprite: CompositeSpriteId,
+        context: String,
+    },
+    /// Two functions were defined with the same label
+    DuplicateFunction { name: String },
+    /// Two variables were created with the same label
+    DuplicateVariable { name: String },
+    /// A duplicate or undefined label in the generated assembly
+    Symbol(SymbolError),
+    /// A streamed map has inconsistent or unsupported dimensions
+    InvalidMap { name: String, reason: String },
+    /// A string or number used a character the font does not have
+    UnknownCharacter { font: String, character: char },
+    /// A font character would use tile $FF (`STRING_END`) or wrap past it
+    FontTileOverflow { font: String, character: char },
+    /// Compression was requested for tiles that are not known at build time
+    Uncompressible { name: String },
+    /// A tileset was uploaded into a tile slot that is too small for it
+    SlotOverflow {
+        tileset: String,
+        slot: String,
+        requested: u16,
+        capacity: u8,
+    },
+    /// Background tiles were allocated for another LCDC tile data mode
+    /// than the one the display uses
+    TileDataAreaMismatch {
+        display: TileDataArea,
+        tiles: TileDataArea,
+    },
+    /// More than eight CGB palettes of one kind were added
+    TooManyCgbPalettes { kind: String, count: usize },
+    /// A CGB feature was used without enabling CGB mode
+    CgbRequired { feature: String },
+    /// A SGB feature was used without enabling SGB support
+    SgbRequired { feature: String },
+    /// More sound effects than `PlaySfx` can number
+    TooManySoundEffects { count: usize },
+    /// More waveforms than `LoadWave` can number
+    TooManyWaveforms { count: usize },
+    /// The window was given the tilemap the background uses
+    WindowMapClash { map: TileMapArea },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Memory(err) => write!(f, "{}", err),
+            Diagnostic::UnknownFunction { name, available } => write!(
+                f,
+                "unknown function '{}' (available: {})",
+                name,
+                available.join(", ")
+            ),
+            Diagnostic::MissingSprite { sprite, context } => {
+                write!(
+                    f,
+                    "sprite #{} does not exist (used in {})",
+                    sprite.0, context
+                )
+            }
+            Diagnostic::MissingCompositeSprite { sprite, context } => write!(
+                f,
+                "composite sprite #{} does not exist (used in {})",
+                sprite.0, context
+            ),
+            Diagnostic::DuplicateFunction { name } => {
+                write!(f, "function '{}' is defined more than once", name)
+            }
+            Diagnostic::DuplicateVariable { name } => {
+                write!(f, "variable '{}' is defined more than once", name)
+            }
+            Diagnostic::Symbol(err) => write!(f, "{}", err),
+            Diagnostic::InvalidMap { name, reason } => {
+                write!(f, "streamed map '{}' is invalid: {}", name, reason)
+            }
+            Diagnostic::UnknownCharacter { font, character } => write!(
+                f,
+                "font '{}' has no glyph for {:?} (digits must follow '0' in order)",
+                font, character
+            ),
+            Diagnostic::FontTileOverflow { font, character } => write!(
+                f,
+                "font '{}' puts {:?} at tile $FF or beyond, which ends strings \
+                 (use fewer characters or add the font earlier)",
+                font, character
+            ),
+            Diagnostic::Uncompressible { name } => write!(
+                f,
+                "tileset '{}' cannot be compressed (only TileSource::Bytes and tilemaps can)",
+                name
+            ),
+            Diagnostic::SlotOverflow {
+                tileset,
+                slot,
+                requested,
+                capacity,
+            } => write!(
+                f,
+                "tileset '{}' has {} tiles but slot '{}' only holds {}",
+                tileset, requested, slot, capacity
+            ),
+            Diagnostic::TileDataAreaMismatch { display, tiles } => write!(
+                f,
+                "background tiles were allocated for {:?} but the display uses {:?} \
+                 (use RustBoy::set_tile_data_area before adding tiles)",
+                tiles, display
+            ),
+            Diagnostic::TooManyCgbPalettes { kind, count } => write!(
+                f,
+                "{} {} palettes were added but the CGB only has 8",
+                count, kind
+            ),
+            Diagnostic::CgbRequired { feature } => write!(
+                f,
+                "{} used without CGB mode (enable it with RustBoy::cgb.enable)",
+                feature
+            ),
+            Diagnostic::SgbRequired { feature } => write!(
+                f,
+                "{} used without SGB support (enable it with RustBoy::sgb.enable)",
+                feature
+            ),
+            Diagnostic::TooManySoundEffects { count } => write!(
+                f,
+                "{} sound effects were added but at most {} can be played",
+                count, MAX_SOUND_EFFECTS
+            ),
+            Diagnostic::TooManyWaveforms { count } => write!(
+                f,
+                "{} waveforms were added but at most {} can be loaded",
+                count, MAX_WAVEFORMS
+            ),
+            Diagnostic::WindowMapClash { map } => write!(
+                f,
+                "the window and the background both use the {:?} tilemap \
+                 (give the window the other one with RustBoy::window.set_map)",
+                map
+            ),
+        }
+    }
+}
+
+impl From<MemoryError> for Diagnostic {
+    fn from(err: MemoryError) -> Self {
+        Diagnostic::Memory(err)
+    }
+}
+
+impl From<SymbolError> for Diagnostic {
+    fn from(err: SymbolError) -> Self {
+        Diagnostic::Symbol(err)
+    }
+}
+
+/// Error returned when a RustBoy program cannot be built
+///
+/// Collects every diagnostic found during the build together with a
+/// summary of the memory usage, so all problems can be fixed at once.
+#[derive(Debug, Clone)]
+pub struct BuildError {
+    diagnostics: Vec<Diagnostic>,
+    memory: MemoryUsage,
+}
+
+impl BuildError {
+    pub(crate) fn new(diagnostics: Vec<Diagnostic>, memory: MemoryUsage) -> Self {
+        Self {
+            diagnostics,
+            memory,
+        }
+    }
+
+    /// All problems found during the build, in the order they were detected
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Memory usage at the time the build failed
+    pub fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "build failed with {} error(s):", self.diagnostics.len())?;
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "  - {}", diagnostic)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Memory usage:")?;
+        write!(f, "{}", self.memory)
+    }
+}
+
+impl std::error::Error for BuildError {}
@@ -2,7 +2,8 @@
 
 use std::collections::{HashMap, HashSet};
 
-use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+use super::text::STRING_END;
+use crate::gb_asm::{Asm, Condition, Instr, JumpTarget, Operand, Register};
 
 /// Builtin functions that can be auto-included
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -19,9 +20,76 @@ pub enum BuiltinFunction {
     GetTileByPixel,
     /// Delay loop using BC as counter
     Delay,
+    /// Copy a string terminated by STRING_END to the tilemap
+    PrintString,
+    /// Print A as three decimal digits
+    PrintDecimal,
+    /// Print packed BCD bytes, most significant first
+    PrintBcd,
+    /// Compare two packed BCD numbers
+    CompareBcd,
+    /// Copy a packed BCD number over another one if it is greater
+    UpdateHighScore,
+    /// Decompress RLE data (see `Compression::Rle`)
+    DecompressRle,
+    /// Decompress LZ data (see `Compression::Lz`)
+    DecompressLz,
+    /// Add a tile upload to the VBlank queue
+    QueueTiles,
+    /// Copy queued tiles, a bounded number per frame
+    ProcessTileQueue,
+    /// Switch a CGB to double speed (no-op on a DMG)
+    SwitchDoubleSpeed,
+    /// Copy to VRAM with general-purpose HDMA, or `Memcopy` on a DMG
+    HdmaCopy,
+    /// Send SGB command packets through the joypad register
+    SgbTransfer,
+    /// Send 4 KB of data to the SGB through the screen (CHR_TRN, PCT_TRN)
+    SgbVramTransfer,
+    /// Start a sound effect from `SfxTable`
+    PlaySfx,
+    /// Start a song from its first order
+    MusicPlay,
+    /// Advance the music by one frame
+    MusicTick,
+    /// Copy a waveform from `WaveTable` to wave RAM
+    LoadWave,
+    /// Next random byte (xorshift16)
+    Random,
+    /// Random number below a bound
+    RandomBelow,
 }
 
 impl BuiltinFunction {
+    /// Every builtin function
+    pub const ALL: [BuiltinFunction; 25] = [
+        BuiltinFunction::Memcopy,
+        BuiltinFunction::WaitVBlank,
+        BuiltinFunction::WaitNotVBlank,
+        BuiltinFunction::UpdateKeys,
+        BuiltinFunction::GetTileByPixel,
+        BuiltinFunction::Delay,
+        BuiltinFunction::PrintString,
+        BuiltinFunction::PrintDecimal,
+        BuiltinFunction::PrintBcd,
+        BuiltinFunction::CompareBcd,
+        BuiltinFunction::UpdateHighScore,
+        BuiltinFunction::DecompressRle,
+        BuiltinFunction::DecompressLz,
+        BuiltinFunction::QueueTiles,
+        BuiltinFunction::ProcessTileQueue,
+        BuiltinFunction::SwitchDoubleSpeed,
+        BuiltinFunction::HdmaCopy,
+        BuiltinFunction::SgbTransfer,
+        BuiltinFunction::SgbVramTransfer,
+        BuiltinFunction::PlaySfx,
+        BuiltinFunction::MusicPlay,
+        BuiltinFunction::MusicTick,
+        BuiltinFunction::LoadWave,
+        BuiltinFunction::Random,
+        BuiltinFunction::RandomBelow,
+    ];
+
     /// Get the label name for this function
     pub fn label(&self) -> &'static str {
         match self {
@@ -31,6 +99,25 @@ impl BuiltinFunction {
             BuiltinFunction::UpdateKeys => "UpdateKeys",
             BuiltinFunction::GetTileByPixel => "GetTileByPixel",
             BuiltinFunction::Delay => "Delay",
+            BuiltinFunction::PrintString => "PrintString",
+            BuiltinFunction::PrintDecimal => "PrintDecimal",
+            BuiltinFunction::PrintBcd => "PrintBcd",
+            BuiltinFunction::CompareBcd => "CompareBcd",
+            BuiltinFunction::UpdateHighScore => "UpdateHighScore",
+            BuiltinFunction::DecompressRle => "DecompressRle",
+            BuiltinFunction::DecompressLz => "DecompressLz",
+            BuiltinFunction::QueueTiles => "QueueTiles",
+            BuiltinFunction::ProcessTileQueue => "ProcessTileQueue",
+            BuiltinFunction::SwitchDoubleSpeed => "SwitchDoubleSpeed",
+            BuiltinFunction::HdmaCopy => "HdmaCopy",
+            BuiltinFunction::SgbTransfer => "SgbTransfer",
+            BuiltinFunction::SgbVramTransfer => "SgbVramTransfer",
+            BuiltinFunction::PlaySfx => "PlaySfx",
+            BuiltinFunction::MusicPlay => "MusicPlay",
+            BuiltinFunction::MusicTick => "MusicTick",
+            BuiltinFunction::LoadWave => "LoadWave",
+            BuiltinFunction::Random => "Random",
+            BuiltinFunction::RandomBelow => "RandomBelow",
         }
     }
 
@@ -43,6 +130,25 @@ impl BuiltinFunction {
             "UpdateKeys" => Some(BuiltinFunction::UpdateKeys),
             "GetTileByPixel" => Some(BuiltinFunction::GetTileByPixel),
             "Delay" => Some(BuiltinFunction::Delay),
+            "PrintString" => Some(BuiltinFunction::PrintString),
+            "PrintDecimal" => Some(BuiltinFunction::PrintDecimal),
+            "PrintBcd" => Some(BuiltinFunction::PrintBcd),
+            "CompareBcd" => Some(BuiltinFunction::CompareBcd),
+            "UpdateHighScore" => Some(BuiltinFunction::UpdateHighScore),
+            "DecompressRle" => Some(BuiltinFunction::DecompressRle),
+            "DecompressLz" => Some(BuiltinFunction::DecompressLz),
+            "QueueTiles" => Some(BuiltinFunction::QueueTiles),
+            "ProcessTileQueue" => Some(BuiltinFunction::ProcessTileQueue),
+            "SwitchDoubleSpeed" => Some(BuiltinFunction::SwitchDoubleSpeed),
+            "HdmaCopy" => Some(BuiltinFunction::HdmaCopy),
+            "SgbTransfer" => Some(BuiltinFunction::SgbTransfer),
+            "SgbVramTransfer" => Some(BuiltinFunction::SgbVramTransfer),
+            "PlaySfx" => Some(BuiltinFunction::PlaySfx),
+            "MusicPlay" => Some(BuiltinFunction::MusicPlay),
+            "MusicTick" => Some(BuiltinFunction::MusicTick),
+            "LoadWave" => Some(BuiltinFunction::LoadWave),
+            "Random" => Some(BuiltinFunction::Random),
+            "RandomBelow" => Some(BuiltinFunction::RandomBelow),
             _ => None,
         }
     }
@@ -56,6 +162,43 @@ impl BuiltinFunction {
             BuiltinFunction::UpdateKeys => generate_update_keys(),
             BuiltinFunction::GetTileByPixel => generate_get_tile_by_pixel(),
             BuiltinFunction::Delay => generate_delay(),
+            BuiltinFunction::PrintString => generate_print_string(),
+            BuiltinFunction::PrintDecimal => generate_print_decimal(),
+            BuiltinFunction::PrintBcd => generate_print_bcd(),
+            BuiltinFunction::CompareBcd => generate_compare_bcd(),
+            BuiltinFunction::UpdateHighScore => generate_update_high_score(),
+            BuiltinFunction::DecompressRle => generate_decompress_rle(),
+            BuiltinFunction::DecompressLz => generate_decompress_lz(),
+            BuiltinFunction::QueueTiles => generate_queue_tiles(),
+            BuiltinFunction::ProcessTileQueue => generate_process_tile_queue(),
+            BuiltinFunction::SwitchDoubleSpeed => generate_switch_double_speed(),
+            BuiltinFunction::HdmaCopy => generate_hdma_copy(),
+            BuiltinFunction::SgbTransfer => generate_sgb_transfer(),
+            BuiltinFunction::SgbVramTransfer => generate_sgb_vram_transfer(),
+            BuiltinFunction::PlaySfx => generate_play_sfx(),
+            BuiltinFunction::MusicPlay => generate_music_play(),
+            BuiltinFunction::MusicTick => generate_music_tick(),
+            BuiltinFunction::LoadWave => generate_load_wave(),
+            BuiltinFunction::Random => generate_random(),
+            BuiltinFunction::RandomBelow => generate_random_below(),
+        }
+    }
+
+    /// Other builtins this one calls or jumps to
+    ///
+    /// Builtin bodies are not scanned for calls, so they are included
+    /// along with the builtin.
+    pub fn dependencies(&self) -> &'static [BuiltinFunction] {
+        match self {
+            BuiltinFunction::HdmaCopy => &[BuiltinFunction::Memcopy],
+            BuiltinFunction::SgbVramTransfer => &[
+                BuiltinFunction::WaitVBlank,
+                BuiltinFunction::Memcopy,
+                BuiltinFunction::SgbTransfer,
+            ],
+            BuiltinFunction::MusicTick => &[BuiltinFunction::LoadWave],
+            BuiltinFunction::RandomBelow => &[BuiltinFunction::Random],
+            _ => &[],
         }
     }
 }
@@ -69,6 +212,10 @@ pub struct FunctionRegistry {
     user_functions: HashMap<String, Vec<Instr>>,
     /// User functions that have been called (for validation)
     used_user_functions: HashSet<String>,
+    /// Calls to functions that were not defined yet at call time
+    unresolved_calls: Vec<String>,
+    /// Names registered more than once
+    duplicate_functions: Vec<String>,
 }
 
 impl FunctionRegistry {
@@ -76,18 +223,30 @@ impl FunctionRegistry {
         Self::default()
     }
 
-    /// Mark a builtin function as used
+    /// Mark a builtin function (and the builtins it calls) as used
     pub fn use_function(&mut self, func: BuiltinFunction) {
-        self.used_builtins.insert(func);
+        if self.used_builtins.insert(func) {
+            for &dependency in func.dependencies() {
+                self.use_function(dependency);
+            }
+        }
     }
 
     /// Check if a builtin function is used
+    #[allow(dead_code)]
     pub fn is_used(&self, func: BuiltinFunction) -> bool {
         self.used_builtins.contains(&func)
     }
 
     /// Register a user-defined function
+    ///
+    /// Registering a name twice (or reusing a builtin name) keeps the first
+    /// definition and records the name as a duplicate.
     pub fn register_user_function(&mut self, name: &str, body: Vec<Instr>) {
+        if self.function_exists(name) {
+            self.duplicate_functions.push(name.to_string());
+            return;
+        }
         self.user_functions.insert(name.to_string(), body);
     }
 
@@ -97,11 +256,13 @@ impl FunctionRegistry {
     }
 
     /// Mark a function as called and auto-register if builtin
-    /// Returns true if the function exists, false otherwise
+    /// Returns true if the function exists, false otherwise.
+    /// Unknown names are remembered so they can be checked again at build
+    /// time, once every function has been defined.
     pub fn call_function(&mut self, name: &str) -> bool {
         // Check if it's a builtin function
         if let Some(builtin) = BuiltinFunction::from_name(name) {
-            self.used_builtins.insert(builtin);
+            self.use_function(builtin);
             return true;
         }
 
@@ -111,19 +272,57 @@ impl FunctionRegistry {
             return true;
         }
 
+        self.unresolved_calls.push(name.to_string());
         false
     }
 
+    /// Mark the builtins called by generated code as used
+    ///
+    /// Helpers that only borrow their manager (fonts, for instance) cannot
+    /// register the builtins they call, so calls are also picked up from the
+    /// program and from the user functions.
+    pub fn use_called_builtins<'a>(&mut self, instrs: impl IntoIterator<Item = &'a Instr>) {
+        let mut called: Vec<BuiltinFunction> =
+            instrs.into_iter().filter_map(called_builtin).collect();
+        called.extend(
+            self.user_functions
+                .values()
+                .flatten()
+                .filter_map(called_builtin),
+        );
+        for func in called {
+            self.use_function(func);
+        }
+    }
+
+    /// Check if the program or a user function calls a function
+    pub fn is_called<'a>(&self, name: &str, instrs: impl IntoIterator<Item = &'a Instr>) -> bool {
+        let calls = |instr: &Instr| matches!(instr, Instr::Call { target: JumpTarget::Label(target) } if target == name);
+        instrs.into_iter().any(calls) || self.user_functions.values().flatten().any(calls)
+    }
+
+    /// Called functions that are still undefined
+    pub fn unknown_calls(&self) -> Vec<String> {
+        let mut unknown: Vec<String> = Vec::new();
+        for name in &self.unresolved_calls {
+            if !self.function_exists(name) && !unknown.contains(name) {
+                unknown.push(name.clone());
+            }
+        }
+        unknown
+    }
+
+    /// Function names that were registered more than once
+    pub fn duplicate_functions(&self) -> &[String] {
+        &self.duplicate_functions
+    }
+
     /// Get list of all registered function names (for error messages)
     pub fn available_functions(&self) -> Vec<String> {
-        let mut names: Vec<String> = vec![
-            "Memcopy".to_string(),
-            "WaitVBlank".to_string(),
-            "WaitNotVBlank".to_string(),
-            "UpdateKeys".to_string(),
-            "GetTileByPixel".to_string(),
-            "Delay".to_string(),
-        ];
+        let mut names: Vec<String> = BuiltinFunction::ALL
+            .iter()
+            .map(|func| func.label().to_string())
+            .collect();
         names.extend(self.user_functions.keys().cloned());
         names.sort();
         names
@@ -139,7 +338,7 @@ impl FunctionRegistry {
         }
 
         // Generate user-defined functions
-        for (_, body) in &self.user_functions {
+        for body in self.user_functions.values() {
             all_instrs.extend(body.clone());
         }
 
@@ -147,6 +346,16 @@ impl FunctionRegistry {
     }
 }
 
+/// Builtin targeted by a call instruction, if any
+fn called_builtin(instr: &Instr) -> Option<BuiltinFunction> {
+    match instr {
+        Instr::Call {
+            target: JumpTarget::Label(name),
+        } => BuiltinFunction::from_name(name),
+        _ => None,
+    }
+}
+
 // Function implementations
 
 fn generate_memcopy() -> Vec<Instr> {
@@ -324,3 +533,946 @@ fn generate_delay() -> Vec<Instr> {
 
     asm.get_main_instrs()
 }
+
+fn generate_print_string() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Copy a string to the tilemap");
+    asm.comment("@param de: string, terminated by STRING_END ($FF)");
+    asm.comment("@param hl: tilemap address");
+    asm.label("PrintString");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.cp_imm(STRING_END);
+    asm.ret_cond(Condition::Z);
+    asm.ld_hli_label("a");
+    asm.inc_label("de");
+    asm.jr("PrintString");
+
+    asm.get_main_instrs()
+}
+
+fn generate_print_decimal() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Print a number as three decimal digits");
+    asm.comment("@param a: number");
+    asm.comment("@param d: tile of the digit 0");
+    asm.comment("@param hl: tilemap address");
+    asm.label("PrintDecimal");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld_b(100);
+    asm.call(".digit");
+    asm.ld_b(10);
+    asm.call(".digit");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_hli_label("a");
+    asm.ret();
+
+    // Divide e by b: write the quotient as a digit, keep the remainder in e
+    asm.label(".digit");
+    asm.ld_c(0);
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.label(".loop");
+    asm.cp(Operand::Reg(Register::B));
+    asm.jr_cond(Condition::C, ".done");
+    asm.sub(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.inc_label("c");
+    asm.jr(".loop");
+    asm.label(".done");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_hli_label("a");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_print_bcd() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Print packed BCD bytes, two digits per byte");
+    asm.comment("@param de: most significant byte (the bytes before it follow)");
+    asm.comment("@param b: number of bytes");
+    asm.comment("@param c: tile of the digit 0");
+    asm.comment("@param hl: tilemap address");
+    asm.label("PrintBcd");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.swap(Operand::Reg(Register::A));
+    asm.and(Operand::Imm(0x0F));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.ld_hli_label("a");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.and(Operand::Imm(0x0F));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.ld_hli_label("a");
+    asm.dec_label("de");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, "PrintBcd");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_compare_bcd() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Compare two packed BCD numbers, from the most significant byte");
+    asm.comment("@param de: most significant byte of the first number");
+    asm.comment("@param hl: most significant byte of the second number");
+    asm.comment("@param b: number of bytes");
+    asm.comment("@return z: equal, c: first is lower");
+    asm.label("CompareBcd");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.cp(Operand::AddrReg(Register::HL));
+    asm.ret_cond(Condition::NZ);
+    asm.dec_label("de");
+    asm.dec_label("hl");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, "CompareBcd");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_update_high_score() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Copy a packed BCD number over another one if it is greater");
+    asm.comment("@param de: most significant byte of the score");
+    asm.comment("@param hl: most significant byte of the high score");
+    asm.comment("@param b: number of bytes");
+    asm.label("UpdateHighScore");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.cp(Operand::AddrReg(Register::HL));
+    asm.ret_cond(Condition::C);
+    asm.jr_cond(Condition::NZ, ".copy");
+    asm.dec_label("de");
+    asm.dec_label("hl");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, "UpdateHighScore");
+    asm.ret();
+
+    // The bytes above are equal: copy this one and the lower ones
+    asm.label(".copy");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+    asm.dec_label("de");
+    asm.dec_label("hl");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".copy");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+/// Header byte of a compressed packet: return at the end marker, jump to
+/// `.repeat` for repeat packets and fall through to `.literal` otherwise
+fn decompress_header(asm: &mut Asm, label: &str) {
+    asm.label(label);
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    asm.cp_imm(0x80);
+    asm.jr_cond(Condition::NC, ".repeat");
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.label(".literal");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.ld_hli_label("a");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".literal");
+    asm.jr(label);
+}
+
+fn generate_decompress_rle() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Decompress RLE data");
+    asm.comment("@param de: compressed data");
+    asm.comment("@param hl: destination");
+    decompress_header(&mut asm, "DecompressRle");
+
+    // Repeat the next byte (header & $7F) + 1 times
+    asm.label(".repeat");
+    asm.and(Operand::Imm(0x7F));
+    asm.inc_label("a");
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.label(".fill");
+    asm.ld_hli_label("a");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".fill");
+    asm.jr("DecompressRle");
+
+    asm.get_main_instrs()
+}
+
+fn generate_decompress_lz() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Decompress LZ data");
+    asm.comment("@param de: compressed data");
+    asm.comment("@param hl: destination");
+    decompress_header(&mut asm, "DecompressLz");
+
+    // Copy (header & $7F) + 3 bytes from `distance` bytes back
+    asm.label(".repeat");
+    asm.and(Operand::Imm(0x7F));
+    asm.add(Operand::Reg(Register::A), Operand::Imm(3));
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.push(Register::DE);
+    // de = hl - distance
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.sub(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.sbc(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.label(".copy");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.ld_hli_label("a");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".copy");
+    asm.pop(Register::DE);
+    asm.jr("DecompressLz");
+
+    asm.get_main_instrs()
+}
+
+fn generate_queue_tiles() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Queue a tile upload, copied during the next VBlanks");
+    asm.comment("Does nothing if the queue is full");
+    asm.comment("@param de: source");
+    asm.comment("@param hl: VRAM destination");
+    asm.comment("@param b: number of tiles");
+    asm.label("QueueTiles");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    asm.ld_a_addr_def("wTileQueueCount");
+    asm.cp_label("TILE_QUEUE_SIZE");
+    asm.ret_cond(Condition::NC);
+    asm.push(Register::HL);
+    // hl = wTileQueue + count * 5
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.ld_hl_label("wTileQueue");
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.adc(Operand::Reg(Register::A), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    // Source, destination, tile count
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.ld_hli_label("a");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_hli_label("a");
+    asm.pop(Register::DE);
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.ld_hli_label("a");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_hli_label("a");
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::B));
+    asm.ld_hl_label("wTileQueueCount");
+    asm.inc(Operand::AddrReg(Register::HL));
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_process_tile_queue() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Copy up to TILE_QUEUE_BUDGET tiles of the first queued upload");
+    asm.comment("Must be called during VBlank");
+    asm.label("ProcessTileQueue");
+    asm.ld_a_addr_def("wTileQueueCount");
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    // b = tiles copied this frame, taken from the remaining count
+    asm.ld_hl_label("wTileQueue+4");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.cp_label("TILE_QUEUE_BUDGET");
+    asm.jr_cond(Condition::C, ".batch");
+    asm.ld_a_label("TILE_QUEUE_BUDGET");
+    asm.label(".batch");
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.sub(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+    // de = source, hl = destination
+    asm.ld_a_addr_def("wTileQueue");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wTileQueue+1");
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wTileQueue+2");
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wTileQueue+3");
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.label(".tile");
+    asm.ld_c(16);
+    asm.label(".byte");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.ld_hli_label("a");
+    asm.dec_label("c");
+    asm.jr_cond(Condition::NZ, ".byte");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".tile");
+    // Save the progress for the next frame
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.ld_addr_def_a("wTileQueue");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_addr_def_a("wTileQueue+1");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.ld_addr_def_a("wTileQueue+2");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.ld_addr_def_a("wTileQueue+3");
+    asm.ld_a_addr_def("wTileQueue+4");
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::NZ);
+    // Upload done: move the other entries up
+    asm.ld_hl_label("wTileQueueCount");
+    asm.dec(Operand::AddrReg(Register::HL));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+    asm.ld_de_label("wTileQueue+5");
+    asm.ld_hl_label("wTileQueue");
+    asm.label(".shift");
+    asm.ld_a_addr_reg(Register::DE);
+    asm.inc_label("de");
+    asm.ld_hli_label("a");
+    asm.dec_label("c");
+    asm.jr_cond(Condition::NZ, ".shift");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_switch_double_speed() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Switch the CPU to double speed");
+    asm.comment("Does nothing if already switched, or on a DMG (KEY1 reads $FF)");
+    asm.label("SwitchDoubleSpeed");
+    asm.ld_a_addr_def("rKEY1");
+    asm.and(Operand::Imm(0x80));
+    asm.ret_cond(Condition::NZ);
+    // No interrupt or joypad line may wake STOP early
+    asm.ld_a_addr_def("rIE");
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld_a(0);
+    asm.ld_addr_def_a("rIE");
+    asm.ld_a_label("P1F_GET_NONE");
+    asm.ld_addr_def_a("rP1");
+    asm.ld_a_label("KEY1F_PREPARE");
+    asm.ld_addr_def_a("rKEY1");
+    asm.stop();
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.ld_addr_def_a("rIE");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_hdma_copy() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Copy to VRAM with general-purpose HDMA, 2048 bytes at a time");
+    asm.comment("Falls back to Memcopy on a DMG");
+    asm.comment("@param de: source, aligned to 16 bytes");
+    asm.comment("@param hl: VRAM destination, aligned to 16 bytes");
+    asm.comment("@param bc: length, a multiple of 16");
+    asm.label("HdmaCopy");
+    asm.ld_a_addr_def("wIsCgb");
+    asm.or_label("a", "a");
+    asm.jp_cond(Condition::Z, "Memcopy");
+    asm.label(".chunk");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.ld_addr_def_a("rHDMA1");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.ld_addr_def_a("rHDMA2");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.ld_addr_def_a("rHDMA3");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.ld_addr_def_a("rHDMA4");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.cp_imm(8);
+    asm.jr_cond(Condition::C, ".last");
+    // Full 2048-byte block: 128 blocks of 16 bytes
+    asm.ld_a(0x7F);
+    asm.ld_addr_def_a("rHDMA5");
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.sub(Operand::Reg(Register::A), Operand::Imm(8));
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.add(Operand::Reg(Register::A), Operand::Imm(8));
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.add(Operand::Reg(Register::A), Operand::Imm(8));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.jr(".chunk");
+    // Remaining blocks: bc / 16, less than 128
+    asm.label(".last");
+    asm.swap(Operand::Reg(Register::A));
+    asm.and(Operand::Imm(0xF0));
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.swap(Operand::Reg(Register::A));
+    asm.and(Operand::Imm(0x0F));
+    asm.or_label("a", "b");
+    asm.ret_cond(Condition::Z);
+    asm.dec_label("a");
+    asm.ld_addr_def_a("rHDMA5");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_sgb_transfer() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Send SGB command packets through the joypad register");
+    asm.comment("Does nothing on other consoles");
+    asm.comment("@param hl: packets, the packet count in the low 3 bits of the first byte");
+    asm.label("SgbTransfer");
+    asm.ld_a_addr_def("wIsSgb");
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.and(Operand::Imm(0x07));
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.label(".packet");
+    asm.push(Register::BC);
+    // Reset pulse: both lines low, then high
+    asm.ld_a(0x00);
+    asm.ld_addr_def_a("rP1");
+    asm.ld_a(0x30);
+    asm.ld_addr_def_a("rP1");
+    asm.ld_b(16);
+    asm.label(".byte");
+    asm.ld(Operand::Reg(Register::E), Operand::Imm(8));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    // Bits go out least significant first: P15 low for a 1, P14 low for a 0
+    asm.label(".bit");
+    asm.srl(Operand::Reg(Register::D));
+    asm.ld_a(0x10);
+    asm.jr_cond(Condition::C, ".send");
+    asm.ld_a(0x20);
+    asm.label(".send");
+    asm.ld_addr_def_a("rP1");
+    asm.ld_a(0x30);
+    asm.ld_addr_def_a("rP1");
+    asm.dec_label("e");
+    asm.jr_cond(Condition::NZ, ".bit");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".byte");
+    // Stop bit
+    asm.ld_a(0x20);
+    asm.ld_addr_def_a("rP1");
+    asm.ld_a(0x30);
+    asm.ld_addr_def_a("rP1");
+    // The SGB needs about 4 frames between packets
+    asm.ld_bc(10000);
+    asm.label(".wait");
+    asm.dec_label("bc");
+    asm.ld_a_label("b");
+    asm.or_label("a", "c");
+    asm.jr_cond(Condition::NZ, ".wait");
+    asm.pop(Register::BC);
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".packet");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_sgb_vram_transfer() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Show 4 KB of data on screen and send a packet that reads it");
+    asm.comment("Leaves the LCD off, with $8000-$8FFF and the $9800 map overwritten");
+    asm.comment("@param hl: packet (CHR_TRN or PCT_TRN)");
+    asm.comment("@param de: data");
+    asm.label("SgbVramTransfer");
+    asm.ld_a_addr_def("wIsSgb");
+    asm.or_label("a", "a");
+    asm.ret_cond(Condition::Z);
+    asm.push(Register::HL);
+    // LY stays at 0 while the LCD is off, so only wait when it is on
+    asm.ld_a_addr_def("rLCDC");
+    asm.and_label("LCDCF_ON");
+    asm.jr_cond(Condition::Z, ".lcdOff");
+    asm.call("WaitVBlank");
+    asm.ld_a(0);
+    asm.ld_addr_def_a("rLCDC");
+    asm.label(".lcdOff");
+    asm.ld_hl_label("$8000");
+    asm.ld_bc(0x1000);
+    asm.call("Memcopy");
+    // Tiles 0-255 in order over the 20x13 visible tiles
+    asm.ld_hl_label("$9800");
+    asm.ld(Operand::Reg(Register::C), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::E), Operand::Imm(13));
+    asm.label(".row");
+    asm.ld_b(20);
+    asm.label(".tile");
+    asm.ld_a_label("c");
+    asm.ld_hli_label("a");
+    asm.inc_label("c");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".tile");
+    asm.ld_a_label("l");
+    asm.add(Operand::Reg(Register::A), Operand::Imm(12));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_label("h");
+    asm.adc(Operand::Reg(Register::A), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.dec_label("e");
+    asm.jr_cond(Condition::NZ, ".row");
+    asm.ld_a(0);
+    asm.ld_addr_def_a("rSCX");
+    asm.ld_addr_def_a("rSCY");
+    asm.ld_a(0xE4);
+    asm.ld_addr_def_a("rBGP");
+    asm.ld_a_label("LCDCF_ON | LCDCF_BGON | LCDCF_BG8000");
+    asm.ld_addr_def_a("rLCDC");
+    asm.pop(Register::HL);
+    asm.call("SgbTransfer");
+    asm.call("WaitVBlank");
+    asm.ld_a(0);
+    asm.ld_addr_def_a("rLCDC");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_play_sfx() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Start a sound effect: copy its row of SfxTable to NRx0-NRx4");
+    asm.comment("@param a: sound effect number");
+    asm.label("PlaySfx");
+    // hl = SfxTable + a * 8
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::H), Operand::Imm(0));
+    for _ in 0..3 {
+        asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::HL));
+    }
+    asm.ld_de_label("SfxTable");
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    // Keep the music off the channel while the effect plays
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.push(Register::HL);
+    asm.ld_hl_label("wSfxTimers");
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::B));
+    asm.pop(Register::HL);
+    // First register of the channel, then its five values
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+    asm.ld_b(5);
+    asm.label(".register");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.inc_label("c");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".register");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_music_play() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Start a song from its first order");
+    asm.comment("@param hl: song");
+    asm.label("MusicPlay");
+    asm.ld_a_label("l");
+    asm.ld_addr_def_a("wMusicSong");
+    asm.ld_a_label("h");
+    asm.ld_addr_def_a("wMusicSong + 1");
+    // Orders follow the tempo, the row count and the instruments pointer
+    asm.ld(Operand::Reg(Register::DE), Operand::Imm16(4));
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    asm.ld_a_label("l");
+    asm.ld_addr_def_a("wMusicOrder");
+    asm.ld_a_label("h");
+    asm.ld_addr_def_a("wMusicOrder + 1");
+    asm.ld_a(0);
+    asm.ld_addr_def_a("wMusicRow");
+    asm.ld_a(1);
+    asm.ld_addr_def_a("wMusicTimer");
+    asm.ld_addr_def_a("wMusicState");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_music_tick() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Advance the music by one frame, call once per frame");
+    asm.comment("Channels used by a sound effect are skipped until it is over");
+    asm.label("MusicTick");
+    asm.ld_hl_label("wSfxTimers");
+    asm.ld_b(4);
+    asm.label(".sfxTimer");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.or_label("a", "a");
+    asm.jr_cond(Condition::Z, ".sfxIdle");
+    asm.dec(Operand::AddrReg(Register::HL));
+    asm.label(".sfxIdle");
+    asm.inc_label("hl");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".sfxTimer");
+    asm.ld_a_addr_def("wMusicState");
+    asm.cp_imm(1);
+    asm.ret_cond(Condition::NZ);
+    asm.ld_hl_label("wMusicTimer");
+    asm.dec(Operand::AddrReg(Register::HL));
+    asm.ret_cond(Condition::NZ);
+    // Next row: reload the timer with the tempo
+    load_music_song(&mut asm);
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.ld_addr_def_a("wMusicTimer");
+    asm.ld_a_addr_def("wMusicRow");
+    asm.or_label("a", "a");
+    asm.jr_cond(Condition::NZ, ".row");
+    asm.call(".nextOrder");
+    asm.label(".row");
+    asm.ld_hl_label("wMusicRow");
+    asm.dec(Operand::AddrReg(Register::HL));
+    asm.ld(Operand::Reg(Register::C), Operand::Imm(0));
+    asm.label(".channel");
+    asm.push(Register::BC);
+    asm.call(".step");
+    asm.pop(Register::BC);
+    asm.inc_label("c");
+    asm.ld_a_label("c");
+    asm.cp_imm(4);
+    asm.jr_cond(Condition::NZ, ".channel");
+    asm.ret();
+
+    // Copy the next order's pattern pointers, looping after the last one
+    asm.label(".nextOrder");
+    asm.ld_a_addr_def("wMusicOrder");
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wMusicOrder + 1");
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.or(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.dec_label("hl");
+    asm.jr_cond(Condition::NZ, ".copyOrder");
+    asm.ld_a_addr_def("wMusicSong");
+    asm.add(Operand::Reg(Register::A), Operand::Imm(4));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wMusicSong + 1");
+    asm.adc(Operand::Reg(Register::A), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.label(".copyOrder");
+    asm.ld_de_label("wMusicPointers");
+    asm.ld_b(8);
+    asm.label(".copyPointer");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::AddrReg(Register::DE), Operand::Reg(Register::A));
+    asm.inc_label("de");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".copyPointer");
+    asm.ld_a_label("l");
+    asm.ld_addr_def_a("wMusicOrder");
+    asm.ld_a_label("h");
+    asm.ld_addr_def_a("wMusicOrder + 1");
+    load_music_song(&mut asm);
+    asm.inc_label("hl");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.ld_addr_def_a("wMusicRow");
+    asm.ret();
+
+    // Read one pattern step of channel c
+    asm.label(".step");
+    asm.ld_hl_label("wMusicPointers");
+    asm.ld_a_label("c");
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(0));
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.push(Register::HL);
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::DE));
+    asm.inc_label("de");
+    asm.or_label("a", "a");
+    asm.jr_cond(Condition::Z, ".stepDone");
+    asm.cp_imm(0xFF);
+    asm.jr_cond(Condition::Z, ".stepOff");
+    // Note byte 1 is C2, the first entry of the period tables
+    asm.dec_label("a");
+    asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::DE));
+    asm.inc_label("de");
+    asm.push(Register::DE);
+    asm.call(".playNote");
+    asm.pop(Register::DE);
+    asm.jr(".stepDone");
+    asm.label(".stepOff");
+    asm.push(Register::DE);
+    asm.call(".noteOff");
+    asm.pop(Register::DE);
+    asm.label(".stepDone");
+    asm.pop(Register::HL);
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::D));
+    asm.dec_label("hl");
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::E));
+    asm.ret();
+
+    // Play note b with instrument a on channel c
+    asm.label(".playNote");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.call(".channelBusy");
+    asm.ret_cond(Condition::NZ);
+    load_music_song(&mut asm);
+    asm.inc_label("hl");
+    asm.inc_label("hl");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::Reg(Register::H), Operand::AddrReg(Register::HL));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    // Instrument rows are 5 bytes
+    asm.ld_a_label("e");
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::E));
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(0));
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    // Wave instruments hold their waveform number in the fourth byte
+    asm.ld_a_label("c");
+    asm.cp_imm(2);
+    asm.jr_cond(Condition::NZ, ".registers");
+    asm.push(Register::HL);
+    asm.push(Register::BC);
+    asm.inc_label("hl");
+    asm.inc_label("hl");
+    asm.inc_label("hl");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.call("LoadWave");
+    asm.pop(Register::BC);
+    asm.pop(Register::HL);
+    asm.label(".registers");
+    channel_register(&mut asm, 0x10);
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(3));
+    asm.label(".instrumentRegister");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.inc_label("c");
+    asm.dec_label("d");
+    asm.jr_cond(Condition::NZ, ".instrumentRegister");
+    // Noise takes NR43 from the instrument, the other channels a period
+    asm.ld_a_label("c");
+    asm.cp_imm(0x22);
+    asm.jr_cond(Condition::NZ, ".tone");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.inc_label("c");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.or(Operand::Reg(Register::A), Operand::Imm(0x80));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.ret();
+    asm.label(".tone");
+    asm.inc_label("hl");
+    asm.ld(Operand::Reg(Register::D), Operand::AddrReg(Register::HL));
+    asm.ld_hl_label("MusicPulsePeriods");
+    asm.ld_a_label("c");
+    asm.cp_imm(0x1D);
+    asm.jr_cond(Condition::NZ, ".period");
+    asm.ld_hl_label("MusicWavePeriods");
+    asm.label(".period");
+    asm.ld_a_label("b");
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_label("h");
+    asm.adc(Operand::Reg(Register::A), Operand::Imm(0));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.inc_label("c");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.or(Operand::Reg(Register::A), Operand::Reg(Register::D));
+    asm.or(Operand::Reg(Register::A), Operand::Imm(0x80));
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.ret();
+
+    // Silence channel c: NRx2 = 0 turns the DAC off, or mutes the wave
+    asm.label(".noteOff");
+    asm.call(".channelBusy");
+    asm.ret_cond(Condition::NZ);
+    channel_register(&mut asm, 0x12);
+    asm.ld_a(0);
+    asm.ldh(Operand::AddrReg(Register::C), Operand::Reg(Register::A));
+    asm.ret();
+
+    // NZ if a sound effect holds channel c, keeps bc and de
+    asm.label(".channelBusy");
+    asm.push(Register::DE);
+    asm.ld_hl_label("wSfxTimers");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::C));
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(0));
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    asm.ld(Operand::Reg(Register::A), Operand::AddrReg(Register::HL));
+    asm.or_label("a", "a");
+    asm.pop(Register::DE);
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_load_wave() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Copy a waveform from WaveTable to wave RAM, unless it is loaded");
+    asm.comment("The wave channel is stopped: its next trigger restarts it");
+    asm.comment("@param a: waveform number, $FF keeps wave RAM as is");
+    asm.label("LoadWave");
+    asm.cp_imm(0xFF);
+    asm.ret_cond(Condition::Z);
+    asm.ld_hl_label("wWaveLoaded");
+    asm.cp(Operand::AddrReg(Register::HL));
+    asm.ret_cond(Condition::Z);
+    asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+    // hl = WaveTable + a * 16
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::H), Operand::Imm(0));
+    for _ in 0..4 {
+        asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::HL));
+    }
+    asm.ld_de_label("WaveTable");
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    // Wave RAM can only be written safely with the DAC off
+    asm.ld_a(0);
+    asm.ld_addr_def_a("rNR30");
+    asm.ld_de_label("_AUD3WAVERAM");
+    asm.ld_b(16);
+    asm.label(".copy");
+    asm.ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL));
+    asm.ld(Operand::AddrReg(Register::DE), Operand::Reg(Register::A));
+    asm.inc_label("de");
+    asm.dec_label("b");
+    asm.jr_cond(Condition::NZ, ".copy");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_random() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Advance the xorshift16 generator in wRandom (shifts 7, 9, 8)");
+    asm.comment("@return a: random byte");
+    asm.label("Random");
+    asm.ld_a_addr_def("wRandom");
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wRandom + 1");
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    // A zero state would stay zero
+    asm.or(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.jr_cond(Condition::NZ, ".shift");
+    asm.inc_label("l");
+    asm.label(".shift");
+    asm.ld_a_label("h");
+    asm.rra();
+    asm.ld_a_label("l");
+    asm.rra();
+    asm.xor(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.ld_a_label("l");
+    asm.rra();
+    asm.ld_a_label("h");
+    asm.rra();
+    asm.xor(Operand::Reg(Register::A), Operand::Reg(Register::L));
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.xor(Operand::Reg(Register::A), Operand::Reg(Register::H));
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.ld_a_label("l");
+    asm.ld_addr_def_a("wRandom");
+    asm.ld_a_label("h");
+    asm.ld_addr_def_a("wRandom + 1");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+fn generate_random_below() -> Vec<Instr> {
+    let mut asm = Asm::new();
+
+    asm.comment("Random number from 0 to b - 1: the high byte of Random * b");
+    asm.comment("@param b: bound (1-255)");
+    asm.comment("@return a: random number");
+    asm.label("RandomBelow");
+    asm.call("Random");
+    asm.ld(Operand::Reg(Register::E), Operand::Reg(Register::A));
+    asm.ld(Operand::Reg(Register::D), Operand::Imm(0));
+    asm.ld_hl(0);
+    asm.ld_a_label("b");
+    asm.ld(Operand::Reg(Register::C), Operand::Imm(8));
+    // Shift-and-add multiply, from the top bit of b
+    asm.label(".bit");
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::HL));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.jr_cond(Condition::NC, ".next");
+    asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::DE));
+    asm.label(".next");
+    asm.dec_label("c");
+    asm.jr_cond(Condition::NZ, ".bit");
+    asm.ld_a_label("h");
+    asm.ret();
+
+    asm.get_main_instrs()
+}
+
+/// hl = wMusicSong
+fn load_music_song(asm: &mut Asm) {
+    asm.ld_a_addr_def("wMusicSong");
+    asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+    asm.ld_a_addr_def("wMusicSong + 1");
+    asm.ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+}
+
+/// c = low byte of register `first + channel * 5` for channel c
+fn channel_register(asm: &mut Asm, first: u8) {
+    asm.ld_a_label("c");
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::A));
+    asm.add(Operand::Reg(Register::A), Operand::Reg(Register::C));
+    asm.add(Operand::Reg(Register::A), Operand::Imm(first));
+    asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+}
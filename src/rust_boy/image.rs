@@ -0,0 +1,405 @@
+//! Image import: PNG files to 2bpp tiles at build time
+//!
+//! Images are decoded by the small built-in PNG decoder, sliced into 8x8 or
+//! 8x16 tiles and converted to the Game Boy 2bpp format, so artists can hand
+//! over PNGs instead of running rgbgfx first.
+
+use std::fmt;
+
+use super::display::SpriteSize;
+use super::png::{self, DecodedImage, Pixel};
+use super::streaming::StreamedMap;
+use super::tiles::TileId;
+
+/// Bytes of one 8x8 tile in 2bpp format
+pub(crate) type TileBytes = [u8; 16];
+
+/// Pixels below this alpha are transparent and use color 0
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// How image colors are converted to the four color indices of a tile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteMap {
+    /// Nearest of four gray levels: white is 0 and black is 3
+    ///
+    /// Colored pixels are converted to gray by luminance, so this works for
+    /// any image drawn with four shades, like the ones rgbgfx accepts.
+    Gray,
+    /// Palette index of an indexed PNG, which must be 0 to 3
+    Indexed,
+    /// Exact RGB colors for the indices 0 to 3
+    Colors([[u8; 3]; 4]),
+}
+
+impl PaletteMap {
+    /// Color index of a pixel, or None if the map has no entry for it
+    fn color(&self, pixel: Pixel) -> Option<u8> {
+        let [r, g, b, alpha] = pixel.rgba;
+        match self {
+            PaletteMap::Indexed => pixel.index.filter(|&index| index < 4),
+            _ if alpha < ALPHA_THRESHOLD => Some(0),
+            PaletteMap::Gray => {
+                let luminance = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+                Some(((255 - luminance + 42) / 85) as u8)
+            }
+            PaletteMap::Colors(colors) => colors
+                .iter()
+                .position(|&color| color == [r, g, b])
+                .map(|index| index as u8),
+        }
+    }
+}
+
+/// Which tiles count as duplicates when importing a background image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileDedup {
+    /// Only identical tiles are shared
+    #[default]
+    Exact,
+    /// Mirrored tiles are shared too, using the CGB flip attributes
+    ///
+    /// The flips are registered as the `{name}Attributes` attribute map next
+    /// to the tilemap. The DMG cannot flip background tiles, so only use
+    /// this for CGB games; streamed images lose their flips.
+    WithFlips,
+}
+
+/// CGB background attribute: tile is flipped horizontally
+pub const ATTR_X_FLIP: u8 = 0x20;
+/// CGB background attribute: tile is flipped vertically
+pub const ATTR_Y_FLIP: u8 = 0x40;
+
+/// A background image imported with `TileManager::add_background_image`
+///
+/// # Example
+/// ```ignore
+/// let title = gb.tiles.add_background_image("Title", "title.png", PaletteMap::Gray, TileDedup::Exact)?;
+/// println!("{} unique tiles", title.unique_tiles());
+///
+/// // Images larger than 32x32 tiles are not registered as a tilemap
+/// let level = gb.tiles.add_background_image("Level", "level.png", PaletteMap::Gray, TileDedup::Exact)?;
+/// gb.add_streamed_map(level.to_streamed_map("LevelMap"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BackgroundImage {
+    pub(crate) tiles: TileId,
+    pub(crate) tilemap: Option<TileId>,
+    pub(crate) attribute_map: Option<TileId>,
+    pub(crate) unique_tiles: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    /// Tile indices, row-major
+    pub(crate) map: Vec<u8>,
+    /// CGB attributes (flip bits), row-major
+    pub(crate) attributes: Vec<u8>,
+}
+
+impl BackgroundImage {
+    /// Tileset holding the unique tiles
+    pub fn tiles(&self) -> TileId {
+        self.tiles
+    }
+
+    /// Tilemap registered for images up to 32x32 tiles
+    pub fn tilemap(&self) -> Option<TileId> {
+        self.tilemap
+    }
+
+    /// Attribute map registered with the tilemap when flips are shared
+    pub fn attribute_map(&self) -> Option<TileId> {
+        self.attribute_map
+    }
+
+    /// Number of unique tiles, allocated in the background bank
+    pub fn unique_tiles(&self) -> usize {
+        self.unique_tiles
+    }
+
+    /// Width in tiles
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height in tiles
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Tile index of each map cell, row-major
+    pub fn map(&self) -> &[u8] {
+        &self.map
+    }
+
+    /// CGB flip attributes of each map cell, row-major (all 0 without flips)
+    pub fn attributes(&self) -> &[u8] {
+        &self.attributes
+    }
+
+    /// Attributes as rows for `TileManager::add_attribute_map`
+    ///
+    /// Rows are cut or padded to 32 cells, like the registered tilemap.
+    pub fn attribute_rows(&self) -> Vec<[u8; 32]> {
+        self.attributes
+            .chunks(self.width.max(1))
+            .map(|row| {
+                let mut padded = [0; 32];
+                let len = row.len().min(32);
+                padded[..len].copy_from_slice(&row[..len]);
+                padded
+            })
+            .collect()
+    }
+
+    /// Map of the image, to stream images larger than the background
+    pub fn to_streamed_map(&self, name: &str) -> StreamedMap {
+        StreamedMap::new(name, self.width, self.height, self.map.clone())
+    }
+}
+
+/// Error while importing an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageError {
+    /// The file could not be read
+    Io { path: String, message: String },
+    /// The file is not a valid PNG
+    Corrupt(String),
+    /// A valid PNG feature the decoder does not handle
+    Unsupported(String),
+    /// The image size is not a multiple of the tile size
+    Size {
+        width: usize,
+        height: usize,
+        tile_height: usize,
+    },
+    /// A pixel color that the palette map does not convert
+    UnmappedColor { x: usize, y: usize, rgba: [u8; 4] },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io { path, message } => write!(f, "cannot read '{}': {}", path, message),
+            ImageError::Corrupt(reason) => write!(f, "invalid PNG: {}", reason),
+            ImageError::Unsupported(feature) => write!(f, "unsupported PNG: {}", feature),
+            ImageError::Size {
+                width,
+                height,
+                tile_height,
+            } => write!(
+                f,
+                "image is {}x{} pixels, not a multiple of 8x{} tiles",
+                width, height, tile_height
+            ),
+            ImageError::UnmappedColor { x, y, rgba } => write!(
+                f,
+                "pixel ({}, {}) has color #{:02X}{:02X}{:02X}{:02X}, which is not in the palette map",
+                x, y, rgba[0], rgba[1], rgba[2], rgba[3]
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+/// Read and decode a PNG file
+pub(crate) fn load_png(path: &str) -> Result<DecodedImage, ImageError> {
+    let data = std::fs::read(path).map_err(|err| ImageError::Io {
+        path: path.to_string(),
+        message: err.to_string(),
+    })?;
+    png::decode(&data)
+}
+
+/// Convert an image to 2bpp tiles
+///
+/// 8x8 tiles are read left to right, top to bottom. With 8x16 tiles, each
+/// 8x16 block gives its top tile then its bottom tile, the order the PPU
+/// expects for tall sprites.
+pub(crate) fn image_to_tiles(
+    image: &DecodedImage,
+    palette: PaletteMap,
+    size: SpriteSize,
+) -> Result<Vec<TileBytes>, ImageError> {
+    let tile_height = match size {
+        SpriteSize::Size8x8 => 8,
+        SpriteSize::Size8x16 => 16,
+    };
+    if !image.width.is_multiple_of(8) || !image.height.is_multiple_of(tile_height) {
+        return Err(ImageError::Size {
+            width: image.width,
+            height: image.height,
+            tile_height,
+        });
+    }
+
+    let mut tiles = Vec::new();
+    for block_y in (0..image.height).step_by(tile_height) {
+        for x in (0..image.width).step_by(8) {
+            for y in (block_y..block_y + tile_height).step_by(8) {
+                tiles.push(encode_tile(image, palette, x, y)?);
+            }
+        }
+    }
+    Ok(tiles)
+}
+
+/// Encode the 8x8 tile whose top-left pixel is (x, y)
+fn encode_tile(
+    image: &DecodedImage,
+    palette: PaletteMap,
+    x: usize,
+    y: usize,
+) -> Result<TileBytes, ImageError> {
+    let mut tile = [0u8; 16];
+    for row in 0..8 {
+        for col in 0..8 {
+            let pixel = image.pixel(x + col, y + row);
+            let color = palette.color(pixel).ok_or(ImageError::UnmappedColor {
+                x: x + col,
+                y: y + row,
+                rgba: pixel.rgba,
+            })?;
+            // Low bits in the first byte of the row, high bits in the second
+            let bit = 7 - col;
+            tile[row * 2] |= (color & 1) << bit;
+            tile[row * 2 + 1] |= (color >> 1) << bit;
+        }
+    }
+    Ok(tile)
+}
+
+/// Share identical tiles
+///
+/// Returns the unique tiles and, for each input tile, its index in the
+/// unique tiles and its CGB flip attributes.
+pub(crate) fn dedup_tiles(
+    tiles: &[TileBytes],
+    dedup: TileDedup,
+) -> (Vec<TileBytes>, Vec<(usize, u8)>) {
+    let mut unique: Vec<TileBytes> = Vec::new();
+    let mut cells = Vec::with_capacity(tiles.len());
+
+    for tile in tiles {
+        let variants: &[u8] = match dedup {
+            TileDedup::Exact => &[0],
+            TileDedup::WithFlips => &[0, ATTR_X_FLIP, ATTR_Y_FLIP, ATTR_X_FLIP | ATTR_Y_FLIP],
+        };
+        // A cell showing `tile` can use a unique tile equal to `tile` flipped back
+        let found = variants.iter().find_map(|&attr| {
+            let flipped = flip_tile(tile, attr);
+            unique
+                .iter()
+                .position(|u| *u == flipped)
+                .map(|index| (index, attr))
+        });
+        match found {
+            Some(cell) => cells.push(cell),
+            None => {
+                unique.push(*tile);
+                cells.push((unique.len() - 1, 0));
+            }
+        }
+    }
+    (unique, cells)
+}
+
+/// Mirror a tile according to CGB flip attributes
+pub(crate) fn flip_tile(tile: &TileBytes, attr: u8) -> TileBytes {
+    let mut out = [0u8; 16];
+    for row in 0..8 {
+        let src = if attr & ATTR_Y_FLIP != 0 {
+            7 - row
+        } else {
+            row
+        };
+        for plane in 0..2 {
+            let byte = tile[src * 2 + plane];
+            out[row * 2 + plane] = if attr & ATTR_X_FLIP != 0 {
+                byte.reverse_bits()
+            } else {
+                byte
+            };
+        }
+    }
+    out
+}
+
+/// Rows of a tile as RGBDS graphics literals (`` `01233210 ``)
+pub(crate) fn tile_to_rows(tile: &TileBytes) -> [String; 8] {
+    std::array::from_fn(|row| {
+        let (low, high) = (tile[row * 2], tile[row * 2 + 1]);
+        let pixels: String = (0..8)
+            .rev()
+            .map(|bit| char::from(b'0' + ((low >> bit) & 1) + (((high >> bit) & 1) << 1)))
+            .collect();
+        format!("`{}", pixels)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_matches_rgbgfx_output() {
+        let image = load_png("examples/fosdem/char-dx.png").unwrap();
+        let tiles = image_to_tiles(&image, PaletteMap::Gray, SpriteSize::Size8x8).unwrap();
+        let expected = std::fs::read("examples/fosdem/char-dx.2bpp").unwrap();
+
+        assert_eq!(tiles.len(), 64);
+        assert_eq!(tiles.concat(), expected);
+    }
+
+    #[test]
+    fn test_tile_order_and_errors() {
+        // 16x16 image: left half color 3, right half color 0
+        let black = Pixel {
+            rgba: [0, 0, 0, 255],
+            index: None,
+        };
+        let white = Pixel {
+            rgba: [255, 255, 255, 255],
+            index: None,
+        };
+        let image = DecodedImage {
+            width: 16,
+            height: 16,
+            pixels: (0..256)
+                .map(|i| if i % 16 < 8 { black } else { white })
+                .collect(),
+        };
+
+        let tall = image_to_tiles(&image, PaletteMap::Gray, SpriteSize::Size8x16).unwrap();
+        assert_eq!(tall[0], [0xFF; 16]);
+        assert_eq!(tall[1], [0xFF; 16]);
+        assert_eq!(tall[2], [0x00; 16]);
+        assert_eq!(tile_to_rows(&tall[0])[0], "`33333333");
+
+        let colors = PaletteMap::Colors([[255, 255, 255], [1, 1, 1], [2, 2, 2], [3, 3, 3]]);
+        assert_eq!(
+            image_to_tiles(&image, colors, SpriteSize::Size8x8),
+            Err(ImageError::UnmappedColor {
+                x: 0,
+                y: 0,
+                rgba: [0, 0, 0, 255],
+            })
+        );
+    }
+
+    #[test]
+    fn test_dedup_tiles() {
+        let mut arrow = [0u8; 16];
+        arrow[0] = 0x80; // top-left pixel, color 1
+        let mirrored = flip_tile(&arrow, ATTR_X_FLIP);
+        assert_eq!(mirrored[0], 0x01);
+        let tiles = [[0; 16], arrow, arrow, mirrored];
+
+        let (unique, cells) = dedup_tiles(&tiles, TileDedup::Exact);
+        assert_eq!(unique.len(), 3);
+        assert_eq!(cells, vec![(0, 0), (1, 0), (1, 0), (2, 0)]);
+
+        let (unique, cells) = dedup_tiles(&tiles, TileDedup::WithFlips);
+        assert_eq!(unique.len(), 2);
+        assert_eq!(cells[3], (1, ATTR_X_FLIP));
+    }
+}
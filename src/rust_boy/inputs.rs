@@ -1,16 +1,43 @@
 //! Input manager for handling Game Boy controller input
 //!
-//! Provides a high-level API for binding button presses to actions.
+//! Provides a high-level API for binding button presses, releases,
+//! auto-repeat and button combinations to actions.
 
+use super::labels::LabelCounter;
 use crate::gb_asm::Instr;
-use crate::gb_std::inputs::{PadButton, check_key};
+use crate::gb_std::inputs::{
+    PadButton, check_combo_unique, check_key_pressed_unique, check_key_released_unique,
+    check_key_repeat_unique, check_key_unique,
+};
+
+/// When a binding's action runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    /// Every frame the button is held
+    Held,
+    /// On the frame the button is pressed
+    Pressed,
+    /// On the frame the button is released
+    Released,
+    /// When pressed, then every `rate` frames after `delay` frames
+    Repeat { delay: u8, rate: u8 },
+    /// When all the buttons are down, on the frame the last one is pressed
+    Combo,
+}
 
 /// A registered input binding
 struct InputBinding {
-    button: PadButton,
+    buttons: Vec<PadButton>,
+    trigger: Trigger,
     action: Vec<Instr>,
 }
 
+/// Input handling code and the repeat counters it needs (1 byte each)
+pub(crate) struct InputCode {
+    pub code: Vec<Instr>,
+    pub counters: Vec<String>,
+}
+
 /// Manages input handling for the game
 ///
 /// The InputManager provides a clean API for registering button actions.
@@ -22,8 +49,11 @@ struct InputBinding {
 /// # Example
 /// ```ignore
 /// let mut inputs = InputManager::new();
-/// inputs.on_press(PadButton::Left, gb.sprites.move_left_limit(paddle, 1, 15));
-/// inputs.on_press(PadButton::Right, gb.sprites.move_right_limit(paddle, 1, 105));
+/// inputs.on_held(PadButton::Left, gb.sprites.move_left_limit(paddle, 1, 15));
+/// inputs.on_held(PadButton::Right, gb.sprites.move_right_limit(paddle, 1, 105));
+/// inputs.on_pressed(PadButton::A, gb.audio.play(jump));
+/// inputs.on_repeat(PadButton::Down, 20, 6, gb.call("NextMenuItem"));
+/// inputs.on_combo(&[PadButton::Start, PadButton::Select], gb.call("SoftReset"));
 /// gb.add_inputs(inputs);
 /// ```
 #[derive(Default)]
@@ -37,13 +67,63 @@ impl InputManager {
         Self::default()
     }
 
-    /// Register an action to execute when a button is pressed
+    /// Register an action to execute while a button is held
+    ///
+    /// Same as `on_held`.
     ///
     /// # Arguments
     /// * `button` - The button to check
     /// * `action` - Instructions to execute when the button is pressed
     pub fn on_press(&mut self, button: PadButton, action: Vec<Instr>) -> &mut Self {
-        self.bindings.push(InputBinding { button, action });
+        self.on_held(button, action)
+    }
+
+    /// Register an action to execute every frame a button is held
+    pub fn on_held(&mut self, button: PadButton, action: Vec<Instr>) -> &mut Self {
+        self.bind(vec![button], Trigger::Held, action)
+    }
+
+    /// Register an action to execute once, on the frame a button is pressed
+    pub fn on_pressed(&mut self, button: PadButton, action: Vec<Instr>) -> &mut Self {
+        self.bind(vec![button], Trigger::Pressed, action)
+    }
+
+    /// Register an action to execute once, on the frame a button is released
+    pub fn on_released(&mut self, button: PadButton, action: Vec<Instr>) -> &mut Self {
+        self.bind(vec![button], Trigger::Released, action)
+    }
+
+    /// Register an action to execute when a button is pressed, then every
+    /// `rate` frames once it has been held for `delay` frames
+    ///
+    /// A delay or rate of 0 is treated as 1.
+    pub fn on_repeat(
+        &mut self,
+        button: PadButton,
+        delay: u8,
+        rate: u8,
+        action: Vec<Instr>,
+    ) -> &mut Self {
+        self.bind(vec![button], Trigger::Repeat { delay, rate }, action)
+    }
+
+    /// Register an action to execute once when all `buttons` are down,
+    /// whichever order they were pressed in
+    ///
+    /// An empty combination is ignored.
+    pub fn on_combo(&mut self, buttons: &[PadButton], action: Vec<Instr>) -> &mut Self {
+        if buttons.is_empty() {
+            return self;
+        }
+        self.bind(buttons.to_vec(), Trigger::Combo, action)
+    }
+
+    fn bind(&mut self, buttons: Vec<PadButton>, trigger: Trigger, action: Vec<Instr>) -> &mut Self {
+        self.bindings.push(InputBinding {
+            buttons,
+            trigger,
+            action,
+        });
         self
     }
 
@@ -52,18 +132,41 @@ impl InputManager {
         self.bindings.is_empty()
     }
 
+    /// Whether a binding needs the keys of the previous frame (`wPrevKeys`)
+    pub(crate) fn needs_previous_keys(&self) -> bool {
+        self.bindings
+            .iter()
+            .any(|binding| binding.trigger == Trigger::Released)
+    }
+
     /// Generate the input handling code
     ///
     /// This does NOT include the UpdateKeys call - that is handled by RustBoy
     /// to ensure the function is properly registered as used.
-    pub(crate) fn generate_code(&self) -> Vec<Instr> {
-        let mut instrs = Vec::new();
+    /// Labels are numbered with `labels`, so a button may be bound more than once.
+    pub(crate) fn generate_code(&self, labels: &LabelCounter) -> InputCode {
+        let mut code = Vec::new();
+        let mut counters = Vec::new();
 
         for binding in &self.bindings {
-            instrs.extend(check_key(binding.button, binding.action.clone()));
+            let id = labels.next();
+            let button = binding.buttons[0];
+            let action = binding.action.clone();
+            code.extend(match binding.trigger {
+                Trigger::Held => check_key_unique(button, action, id),
+                Trigger::Pressed => check_key_pressed_unique(button, action, id),
+                Trigger::Released => check_key_released_unique(button, action, id),
+                Trigger::Repeat { delay, rate } => {
+                    let counter = format!("wKeyRepeat_{}", id);
+                    let check = check_key_repeat_unique(button, delay, rate, &counter, action, id);
+                    counters.push(counter);
+                    check
+                }
+                Trigger::Combo => check_combo_unique(&binding.buttons, action, id),
+            });
         }
 
-        instrs
+        InputCode { code, counters }
     }
 }
 
@@ -95,9 +198,58 @@ mod tests {
         asm.ret();
 
         inputs.on_press(PadButton::A, asm.get_main_instrs());
-        let code = inputs.generate_code();
+        let code = inputs.generate_code(&LabelCounter::new()).code;
 
         // Should contain check_key generated code
         assert!(!code.is_empty());
     }
+
+    #[test]
+    fn test_triggers() {
+        let mut inputs = InputManager::new();
+        inputs.on_pressed(PadButton::A, Vec::new());
+        assert!(!inputs.needs_previous_keys());
+        inputs.on_released(PadButton::A, Vec::new());
+        inputs.on_repeat(PadButton::Up, 0, 4, Vec::new());
+        inputs.on_combo(&[], Vec::new());
+        inputs.on_combo(&[PadButton::A, PadButton::B], Vec::new());
+        assert!(inputs.needs_previous_keys());
+
+        let input = inputs.generate_code(&LabelCounter::new());
+        assert_eq!(input.counters, ["wKeyRepeat_2"]);
+        let labels: Vec<String> = input
+            .code
+            .iter()
+            .filter(|instr| matches!(instr, Instr::Label { .. }))
+            .map(|instr| instr.to_string())
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                "CheckAPressed_0:",
+                "CheckAPressedEnd_0:",
+                "CheckAReleased_1:",
+                "CheckAReleasedEnd_1:",
+                "CheckUpRepeat_2:",
+                "CheckUpRepeatHeld_2:",
+                "CheckUpRepeatFire_2:",
+                "CheckUpRepeatEnd_2:",
+                "CheckCombo_3:",
+                "CheckComboEnd_3:",
+            ]
+        );
+        let errors = crate::gb_asm::SymbolTable::from_instrs(&input.code).check_labels();
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_same_button_twice() {
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::A, Vec::new());
+        inputs.on_press(PadButton::A, Vec::new());
+
+        let code = inputs.generate_code(&LabelCounter::new()).code;
+        let errors = crate::gb_asm::SymbolTable::from_instrs(&code).check_labels();
+        assert!(errors.is_empty());
+    }
 }
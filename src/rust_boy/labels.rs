@@ -0,0 +1,30 @@
+//! Shared counter for generating unique labels
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Counter shared by RustBoy and its managers so every generated label is unique
+///
+/// Sprite helpers only borrow their manager (they are typically used as
+/// arguments to `RustBoy` methods), so the counter is shared through an
+/// `Rc<Cell>` instead of being passed around mutably.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LabelCounter(Rc<Cell<usize>>);
+
+impl LabelCounter {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the next counter value (auto-increments)
+    pub(crate) fn next(&self) -> usize {
+        let c = self.0.get();
+        self.0.set(c + 1);
+        c
+    }
+
+    /// Generate a unique label with prefix
+    pub(crate) fn unique(&self, prefix: &str) -> String {
+        format!("{}_{}", prefix, self.next())
+    }
+}
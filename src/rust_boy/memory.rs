@@ -1,5 +1,7 @@
 //! Memory allocation for Game Boy memory regions
 
+use std::fmt;
+
 /// Memory regions on the Game Boy
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MemoryRegion {
@@ -31,19 +33,37 @@ impl MemoryRegion {
     }
 }
 
+/// Size of one 8x8 tile in VRAM (2 bits per pixel)
+pub(crate) const TILE_BYTES: u16 = 16;
+
+/// Size of one OAM entry (Y, X, tile, flags)
+pub(crate) const OAM_ENTRY_BYTES: u16 = 4;
+
 /// Allocator for tracking memory usage in a region
+///
+/// Allocations normally grow up from the start. `allocate_top` grows down
+/// from the end instead, so two kinds of data can share one region.
 #[derive(Debug)]
 pub struct MemoryAllocator {
-    region: MemoryRegion,
+    start_address: u16,
+    end_address: u16,
     next_address: u16,
+    top_address: u16,
 }
 
 impl MemoryAllocator {
     /// Create a new allocator for the given region
     pub fn new(region: MemoryRegion) -> Self {
+        Self::with_bounds(region.start_address(), region.end_address())
+    }
+
+    /// Create an allocator for an address range inside a region (end is exclusive)
+    pub fn with_bounds(start: u16, end: u16) -> Self {
         Self {
-            region,
-            next_address: region.start_address(),
+            start_address: start,
+            end_address: end,
+            next_address: start,
+            top_address: end,
         }
     }
 
@@ -53,7 +73,7 @@ impl MemoryAllocator {
         let addr = self.next_address;
         let new_next = self.next_address.checked_add(size)?;
 
-        if new_next > self.region.end_address() {
+        if new_next > self.top_address {
             return None;
         }
 
@@ -61,19 +81,57 @@ impl MemoryAllocator {
         Some(addr)
     }
 
+    /// Allocate bytes from the end of the region and return the start address
+    /// Returns None if allocation would run into the bytes allocated from the start
+    pub fn allocate_top(&mut self, size: u16) -> Option<u16> {
+        let addr = self.top_address.checked_sub(size)?;
+
+        if addr < self.next_address {
+            return None;
+        }
+
+        self.top_address = addr;
+        Some(addr)
+    }
+
     /// Get the current allocation pointer
     pub fn current_address(&self) -> u16 {
         self.next_address
     }
 
+    /// Get the first address managed by this allocator
+    pub fn start_address(&self) -> u16 {
+        self.start_address
+    }
+
+    /// Get the lowest address allocated from the end of the region
+    pub fn top_address(&self) -> u16 {
+        self.top_address
+    }
+
     /// Get how many bytes have been allocated
     pub fn bytes_allocated(&self) -> u16 {
-        self.next_address - self.region.start_address()
+        self.bytes_allocated_bottom() + self.bytes_allocated_top()
+    }
+
+    /// Get how many bytes have been allocated from the start
+    pub fn bytes_allocated_bottom(&self) -> u16 {
+        self.next_address - self.start_address
+    }
+
+    /// Get how many bytes have been allocated from the end
+    pub fn bytes_allocated_top(&self) -> u16 {
+        self.end_address - self.top_address
     }
 
     /// Get how many bytes remain available
     pub fn bytes_remaining(&self) -> u16 {
-        self.region.end_address() - self.next_address
+        self.top_address - self.next_address
+    }
+
+    /// Get the total number of bytes managed by this allocator
+    pub fn capacity(&self) -> u16 {
+        self.end_address - self.start_address
     }
 
     /// Format address as hex string for assembly
@@ -82,6 +140,123 @@ impl MemoryAllocator {
     }
 }
 
+/// VRAM tile bank a tileset is allocated from
+///
+/// The $8800-$8FFF block is shared: sprite tiles fill it from the bottom
+/// and background tiles that do not fit their own block from the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileBank {
+    /// Object tiles ($8000-$8FFF, 256 tiles)
+    Sprite,
+    /// Background tiles ($9000-$97FF then $8800-$8FFF with signed
+    /// addressing, $8000-$8FFF with unsigned addressing)
+    Background,
+}
+
+impl fmt::Display for TileBank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileBank::Sprite => write!(f, "sprite"),
+            TileBank::Background => write!(f, "background"),
+        }
+    }
+}
+
+/// An allocation that did not fit in its memory region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    /// Not enough free tiles in a VRAM tile bank
+    TileBankFull {
+        name: String,
+        bank: TileBank,
+        requested: usize,
+        available: u16,
+    },
+    /// All 40 OAM entries are already in use
+    OamExhausted { name: String },
+    /// Not enough free bytes in WRAM
+    WramExhausted {
+        name: String,
+        requested: u16,
+        available: u16,
+    },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::TileBankFull {
+                name,
+                bank,
+                requested,
+                available,
+            } => write!(
+                f,
+                "{} tile bank full: '{}' needs {} tiles but only {} are free",
+                bank, name, requested, available
+            ),
+            MemoryError::OamExhausted { name } => write!(
+                f,
+                "OAM exhausted: no entry left for sprite '{}' (max 40)",
+                name
+            ),
+            MemoryError::WramExhausted {
+                name,
+                requested,
+                available,
+            } => write!(
+                f,
+                "WRAM exhausted: '{}' needs {} bytes but only {} are free",
+                name, requested, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+/// Used and total capacity of a single memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionUsage {
+    pub used: u16,
+    pub capacity: u16,
+}
+
+impl RegionUsage {
+    /// Check whether the region has been filled completely
+    pub fn is_full(&self) -> bool {
+        self.used >= self.capacity
+    }
+}
+
+impl fmt::Display for RegionUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.used, self.capacity)
+    }
+}
+
+/// Summary of how much of each managed memory region is in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Tiles allocated in the sprite bank
+    pub sprite_tiles: RegionUsage,
+    /// Tiles allocated in the background bank
+    pub background_tiles: RegionUsage,
+    /// OAM entries in use
+    pub oam_entries: RegionUsage,
+    /// Bytes of WRAM used by variables
+    pub wram_bytes: RegionUsage,
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "VRAM sprite tiles:     {}", self.sprite_tiles)?;
+        writeln!(f, "VRAM background tiles: {}", self.background_tiles)?;
+        writeln!(f, "OAM entries:           {}", self.oam_entries)?;
+        write!(f, "WRAM bytes:            {}", self.wram_bytes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -100,6 +275,20 @@ mod tests {
         assert_eq!(alloc.bytes_allocated(), 32);
     }
 
+    #[test]
+    fn test_allocation_from_both_ends() {
+        let mut alloc = MemoryAllocator::with_bounds(0x8000, 0x8040);
+
+        assert_eq!(alloc.allocate(16), Some(0x8000));
+        assert_eq!(alloc.allocate_top(32), Some(0x8020));
+        assert_eq!(alloc.bytes_remaining(), 16);
+        assert_eq!(alloc.allocate_top(32), None);
+        assert_eq!(alloc.allocate(32), None);
+        assert_eq!(alloc.allocate(16), Some(0x8010));
+        assert_eq!(alloc.bytes_allocated(), 64);
+        assert_eq!(alloc.bytes_allocated_top(), 32);
+    }
+
     #[test]
     fn test_wram_allocation() {
         let mut alloc = MemoryAllocator::new(MemoryRegion::Wram);
@@ -111,6 +300,17 @@ mod tests {
         assert_eq!(addr2, 0xC001);
     }
 
+    #[test]
+    fn test_allocation_overflow() {
+        let mut alloc = MemoryAllocator::with_bounds(0xFE00, 0xFE08);
+
+        assert_eq!(alloc.allocate(4), Some(0xFE00));
+        assert_eq!(alloc.allocate(4), Some(0xFE04));
+        assert_eq!(alloc.allocate(4), None);
+        assert_eq!(alloc.bytes_remaining(), 0);
+        assert_eq!(alloc.capacity(), 8);
+    }
+
     #[test]
     fn test_format_address() {
         assert_eq!(MemoryAllocator::format_address(0x8000), "$8000");
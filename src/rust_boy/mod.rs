@@ -4,19 +4,56 @@
 //! hiding all low-level details from the developer.
 
 mod animations;
+mod audio;
+mod camera;
+mod cgb;
+mod compression;
+mod display;
+mod error;
 mod functions;
+mod image;
 mod inputs;
+mod labels;
 mod memory;
+mod music;
+mod png;
 mod rustboy;
+mod sgb;
 mod sprites;
+mod streaming;
+mod text;
 mod tiles;
+mod timers;
+mod tracker;
 mod variables;
+mod waveform;
+mod window;
 
 pub use animations::AnimationType;
+pub use audio::{
+    Audio, AudioError, Channel, Duty, Envelope, MAX_SOUND_EFFECTS, Noise, Note, Pitch, SfxId,
+    SongId, SoundEffect, Sweep, WaveVolume,
+};
+pub use camera::{Camera, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use cgb::{CgbConfig, CgbMode, CgbPalette, Color, MAX_CGB_PALETTES, TileAttributes};
+pub use compression::Compression;
+pub use display::{DisplayConfig, Palette, Shade, SpriteSize, TileDataArea, TileMapArea};
+pub use error::{BuildError, Diagnostic};
 pub use functions::BuiltinFunction;
+pub use image::{ATTR_X_FLIP, ATTR_Y_FLIP, BackgroundImage, ImageError, PaletteMap, TileDedup};
 pub use inputs::InputManager;
-pub use memory::MemoryRegion;
-pub use rustboy::RustBoy;
+pub use memory::{MemoryError, MemoryRegion, MemoryUsage, RegionUsage, TileBank};
+pub use music::{Instrument, MAX_INSTRUMENTS, MusicError, PatternId, Song, Step};
+pub use rustboy::{BuildOutput, RustBoy};
+pub use sgb::{
+    AttrBlock, MAX_ATTR_BLOCKS, SgbBorder, SgbConfig, SgbError, SgbMask, SgbPacket, SgbPalettePair,
+};
 pub use sprites::{ANIM_DISABLED, CompositeSpriteId, SpriteId, SpriteManager};
-pub use tiles::{TileId, TileManager, TileSource};
-pub use variables::{VarId, VarType, VariableManager};
+pub use streaming::{MAX_STREAMED_MAP_SIZE, StreamedMap};
+pub use text::{Font, STRING_END};
+pub use tiles::{TILE_QUEUE_SIZE, TileError, TileId, TileManager, TileSlot, TileSource};
+pub use timers::{CooldownId, HardwareTimer, TaskId, TimerClock, Timers};
+pub use tracker::{ModImport, TrackerError, UnsupportedEffect};
+pub use variables::{BCD_MAX_DIGITS, BCD_MIN_DIGITS, BcdCounter, VarId, VarType, VariableManager};
+pub use waveform::{MAX_WAVEFORMS, WAVE_SAMPLES, WaveId, Waveform};
+pub use window::WindowManager;
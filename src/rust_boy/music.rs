@@ -0,0 +1,523 @@
+//! Background music: songs made of patterns, orders and instruments
+//!
+//! A song is compiled to compact ROM data and played by the `MusicTick`
+//! builtin, which RustBoy calls once per frame. Each order plays one
+//! pattern per channel, all patterns having the song's row count, and the
+//! song loops after the last order. Sound effects take priority: a channel
+//! playing one is left alone by the music until the effect is over.
+
+use std::fmt;
+
+use super::audio::{
+    Channel, Duty, Envelope, Noise, Note, Sweep, WaveVolume, length_flag, pulse_length, wave_length,
+};
+use super::waveform::Waveform;
+use crate::gb_asm::{Asm, Instr};
+
+/// Most instruments in one song
+pub const MAX_INSTRUMENTS: usize = 32;
+
+/// Pattern byte for a note: `midi - NOTE_OFFSET` (C2 is 1)
+const NOTE_OFFSET: u8 = 35;
+/// Number of notes in the period tables (C2 to B8)
+pub(crate) const NOTE_COUNT: u8 = 84;
+/// Pattern byte that silences the channel
+const NOTE_OFF: u8 = 0xFF;
+
+/// Invalid song data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MusicError {
+    /// Tempo and row count must be at least 1
+    InvalidTiming {
+        tempo: u8,
+        rows: u8,
+    },
+    /// A pattern does not have the song's row count
+    PatternLength {
+        pattern: usize,
+        rows: usize,
+        expected: u8,
+    },
+    /// An order uses a pattern that was not added
+    UnknownPattern {
+        order: usize,
+        pattern: usize,
+    },
+    /// A step uses an instrument that was not added
+    UnknownInstrument {
+        pattern: usize,
+        instrument: u8,
+    },
+    /// A step uses an instrument made for another channel
+    WrongChannel {
+        pattern: usize,
+        instrument: u8,
+        channel: Channel,
+    },
+    TooManyInstruments(usize),
+    /// A song needs at least one order
+    NoOrders,
+}
+
+impl fmt::Display for MusicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MusicError::InvalidTiming { tempo, rows } => write!(
+                f,
+                "tempo ({}) and rows per pattern ({}) must be at least 1",
+                tempo, rows
+            ),
+            MusicError::PatternLength {
+                pattern,
+                rows,
+                expected,
+            } => write!(
+                f,
+                "pattern {} has {} rows, the song uses {}",
+                pattern, rows, expected
+            ),
+            MusicError::UnknownPattern { order, pattern } => {
+                write!(f, "order {} uses unknown pattern {}", order, pattern)
+            }
+            MusicError::UnknownInstrument {
+                pattern,
+                instrument,
+            } => write!(
+                f,
+                "pattern {} uses unknown instrument {}",
+                pattern, instrument
+            ),
+            MusicError::WrongChannel {
+                pattern,
+                instrument,
+                channel,
+            } => write!(
+                f,
+                "pattern {} plays instrument {} on {:?}, which it was not made for",
+                pattern, instrument, channel
+            ),
+            MusicError::TooManyInstruments(count) => write!(
+                f,
+                "song has {} instruments, at most {} are supported",
+                count, MAX_INSTRUMENTS
+            ),
+            MusicError::NoOrders => write!(f, "song has no orders"),
+        }
+    }
+}
+
+impl std::error::Error for MusicError {}
+
+/// Channel settings applied to every note played with an instrument
+///
+/// Lengths work as in `SoundEffect`. Pulse instruments play on both pulse
+/// channels; the sweep only applies to pulse 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instrument {
+    Pulse {
+        duty: Duty,
+        envelope: Envelope,
+        sweep: Sweep,
+        length: Option<u8>,
+    },
+    /// Loads `wave` before each note if another waveform is in wave RAM;
+    /// without one, plays the samples already there
+    Wave {
+        volume: WaveVolume,
+        length: Option<u8>,
+        wave: Option<Waveform>,
+    },
+    /// Noise ignores the step's note: use one instrument per drum sound
+    Noise {
+        noise: Noise,
+        envelope: Envelope,
+        length: Option<u8>,
+    },
+}
+
+impl Instrument {
+    fn plays_on(&self, channel: Channel) -> bool {
+        matches!(
+            (self, channel),
+            (Instrument::Pulse { .. }, Channel::Pulse1 | Channel::Pulse2)
+                | (Instrument::Wave { .. }, Channel::Wave)
+                | (Instrument::Noise { .. }, Channel::Noise)
+        )
+    }
+
+    /// NRx0-NRx2, NR43 for noise or the `waves` index of the waveform
+    /// ($FF for none), and the NRx4 length flag
+    fn bytes(&self, waves: &[Waveform]) -> [u8; 5] {
+        match *self {
+            Instrument::Pulse {
+                duty,
+                envelope,
+                sweep,
+                length,
+            } => [
+                sweep.byte(),
+                duty.bits() | pulse_length(length),
+                envelope.byte(),
+                0,
+                length_flag(length),
+            ],
+            Instrument::Wave {
+                volume,
+                length,
+                wave,
+            } => [
+                0x80,
+                wave_length(length),
+                volume.byte(),
+                wave.and_then(|wave| waves.iter().position(|w| *w == wave))
+                    .map_or(0xFF, |index| index as u8),
+                length_flag(length),
+            ],
+            Instrument::Noise {
+                noise,
+                envelope,
+                length,
+            } => [
+                0,
+                pulse_length(length),
+                envelope.byte(),
+                noise.byte(),
+                length_flag(length),
+            ],
+        }
+    }
+}
+
+/// One row of a pattern on one channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Keep playing the current note
+    Empty,
+    /// Play a note with an instrument
+    Note(Note, u8),
+    /// Silence the channel
+    Off,
+}
+
+impl Step {
+    fn bytes(&self) -> Vec<u8> {
+        match self {
+            Step::Empty => vec![0],
+            Step::Note(note, instrument) => vec![note.midi() - NOTE_OFFSET, *instrument],
+            Step::Off => vec![NOTE_OFF],
+        }
+    }
+}
+
+/// Number of a pattern in its song
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PatternId(usize);
+
+/// A song for the music driver
+///
+/// # Example
+/// ```ignore
+/// let mut song = Song::new(6, 4);
+/// let lead = song.add_instrument(Instrument::Pulse {
+///     duty: Duty::Half,
+///     envelope: Envelope::fade_out(12, 3),
+///     sweep: Sweep::OFF,
+///     length: None,
+/// });
+/// let melody = song.add_pattern(vec![
+///     Step::Note("C4".parse()?, lead), Step::Empty,
+///     Step::Note("E4".parse()?, lead), Step::Off,
+/// ])?;
+/// song.add_order([Some(melody), None, None, None]);
+/// let theme = gb.audio.add_song(song)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Song {
+    tempo: u8,
+    rows: u8,
+    instruments: Vec<Instrument>,
+    patterns: Vec<Vec<Step>>,
+    orders: Vec<[Option<PatternId>; 4]>,
+}
+
+impl Song {
+    /// Create a song advancing one row every `tempo` frames, with
+    /// `rows` rows per pattern
+    pub fn new(tempo: u8, rows: u8) -> Self {
+        Song {
+            tempo,
+            rows,
+            instruments: Vec::new(),
+            patterns: Vec::new(),
+            orders: Vec::new(),
+        }
+    }
+
+    pub fn tempo(&self) -> u8 {
+        self.tempo
+    }
+
+    /// Change the frames per row
+    pub fn set_tempo(&mut self, tempo: u8) -> &mut Self {
+        self.tempo = tempo;
+        self
+    }
+
+    pub fn rows(&self) -> u8 {
+        self.rows
+    }
+
+    /// Add an instrument, returning its number for steps
+    pub fn add_instrument(&mut self, instrument: Instrument) -> u8 {
+        self.instruments.push(instrument);
+        (self.instruments.len() - 1) as u8
+    }
+
+    /// Add a pattern of exactly `rows` steps
+    pub fn add_pattern(&mut self, steps: Vec<Step>) -> Result<PatternId, MusicError> {
+        if steps.len() != self.rows as usize {
+            return Err(MusicError::PatternLength {
+                pattern: self.patterns.len(),
+                rows: steps.len(),
+                expected: self.rows,
+            });
+        }
+        self.patterns.push(steps);
+        Ok(PatternId(self.patterns.len() - 1))
+    }
+
+    /// Play one pattern per channel (pulse 1, pulse 2, wave, noise);
+    /// `None` leaves the channel silent
+    pub fn add_order(&mut self, patterns: [Option<PatternId>; 4]) -> &mut Self {
+        self.orders.push(patterns);
+        self
+    }
+
+    /// Check the song before it is compiled
+    pub(crate) fn validate(&self) -> Result<(), MusicError> {
+        if self.tempo == 0 || self.rows == 0 {
+            return Err(MusicError::InvalidTiming {
+                tempo: self.tempo,
+                rows: self.rows,
+            });
+        }
+        if self.instruments.len() > MAX_INSTRUMENTS {
+            return Err(MusicError::TooManyInstruments(self.instruments.len()));
+        }
+        if self.orders.is_empty() {
+            return Err(MusicError::NoOrders);
+        }
+        for (order, patterns) in self.orders.iter().enumerate() {
+            for (channel, pattern) in Channel::ALL.into_iter().zip(patterns) {
+                let Some(PatternId(pattern)) = *pattern else {
+                    continue;
+                };
+                let steps = self
+                    .patterns
+                    .get(pattern)
+                    .ok_or(MusicError::UnknownPattern { order, pattern })?;
+                for step in steps {
+                    let Step::Note(_, instrument) = *step else {
+                        continue;
+                    };
+                    let found = self.instruments.get(instrument as usize).ok_or(
+                        MusicError::UnknownInstrument {
+                            pattern,
+                            instrument,
+                        },
+                    )?;
+                    if !found.plays_on(channel) {
+                        return Err(MusicError::WrongChannel {
+                            pattern,
+                            instrument,
+                            channel,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waveforms of the wave instruments
+    pub(crate) fn waveforms(&self) -> impl Iterator<Item = Waveform> + '_ {
+        self.instruments
+            .iter()
+            .filter_map(|instrument| match instrument {
+                Instrument::Wave { wave, .. } => *wave,
+                _ => None,
+            })
+    }
+
+    /// Header, orders, instruments and patterns under `label`
+    ///
+    /// Wave instruments refer to their waveform by its index in `waves`.
+    pub(crate) fn generate_data(&self, label: &str, waves: &[Waveform]) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        let rest = format!("{}Rest", label);
+        asm.label(label);
+        asm.db(&format!("{}, {}", self.tempo, self.rows));
+        asm.dw(&format!("{}Instruments", label));
+        for patterns in &self.orders {
+            let pointers: Vec<String> = patterns
+                .iter()
+                .map(|pattern| match pattern {
+                    Some(PatternId(index)) => format!("{}Pattern{}", label, index),
+                    None => rest.clone(),
+                })
+                .collect();
+            asm.dw(&pointers.join(", "));
+        }
+        // End of the orders: loop back to the first one
+        asm.dw("0");
+
+        asm.label(&format!("{}Instruments", label));
+        for instrument in &self.instruments {
+            asm.db(&hex_bytes(&instrument.bytes(waves)));
+        }
+        for (index, steps) in self.patterns.iter().enumerate() {
+            asm.label(&format!("{}Pattern{}", label, index));
+            let bytes: Vec<u8> = steps.iter().flat_map(Step::bytes).collect();
+            for row in bytes.chunks(16) {
+                asm.db(&hex_bytes(row));
+            }
+        }
+        if self.orders.iter().flatten().any(Option::is_none) {
+            asm.label(&rest);
+            asm.ds(&self.rows.to_string(), "0");
+        }
+        asm.get_main_instrs()
+    }
+}
+
+/// Note periods used by `MusicTick`, pulse then wave, from C2 to B8
+pub(crate) fn generate_period_tables() -> Vec<Instr> {
+    let mut asm = Asm::new();
+    let notes: Vec<Note> = (0..NOTE_COUNT)
+        .map(|i| {
+            let midi = NOTE_OFFSET + 1 + i;
+            let pitch = super::audio::Pitch::from_semitone(midi % 12);
+            Note::new(pitch, midi / 12 - 1).expect("C2 to B8")
+        })
+        .collect();
+    for (label, period) in [
+        ("MusicPulsePeriods", Note::pulse_period as fn(&Note) -> u16),
+        ("MusicWavePeriods", Note::wave_period),
+    ] {
+        asm.label(label);
+        for octave in notes.chunks(12) {
+            let words: Vec<String> = octave.iter().map(|n| period(n).to_string()).collect();
+            asm.dw(&words.join(", "));
+        }
+    }
+    asm.get_main_instrs()
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    values.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lead() -> Instrument {
+        Instrument::Pulse {
+            duty: Duty::Half,
+            envelope: Envelope::fade_out(12, 3),
+            sweep: Sweep::OFF,
+            length: Some(32),
+        }
+    }
+
+    #[test]
+    fn test_song_data() {
+        let mut song = Song::new(6, 4);
+        let lead = song.add_instrument(lead());
+        song.add_instrument(Instrument::Wave {
+            volume: WaveVolume::Full,
+            length: None,
+            wave: Some(Waveform::saw()),
+        });
+        let c4 = "C4".parse().unwrap();
+        let melody = song
+            .add_pattern(vec![
+                Step::Note(c4, lead),
+                Step::Empty,
+                Step::Empty,
+                Step::Off,
+            ])
+            .unwrap();
+        song.add_order([Some(melody), Some(melody), None, None]);
+        assert_eq!(song.validate(), Ok(()));
+
+        let data: Vec<String> = song
+            .generate_data("Song0", &[Waveform::square(), Waveform::saw()])
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(data.contains(&"db 6, 4".to_string()));
+        assert!(
+            data.contains(&"dw Song0Pattern0, Song0Pattern0, Song0Rest, Song0Rest".to_string())
+        );
+        assert!(data.contains(&"dw 0".to_string()));
+        assert!(data.contains(&"db $00, $A0, $C3, $00, $40".to_string()));
+        assert!(data.contains(&"db $80, $00, $20, $01, $00".to_string()));
+        assert_eq!(song.waveforms().collect::<Vec<_>>(), [Waveform::saw()]);
+        // C4 is MIDI 60
+        assert!(data.contains(&"db $19, $00, $00, $00, $FF".to_string()));
+        assert!(data.contains(&"ds 4, 0".to_string()));
+    }
+
+    #[test]
+    fn test_song_errors() {
+        let mut song = Song::new(6, 2);
+        assert_eq!(
+            song.add_pattern(vec![Step::Empty]),
+            Err(MusicError::PatternLength {
+                pattern: 0,
+                rows: 1,
+                expected: 2,
+            })
+        );
+        assert_eq!(song.validate(), Err(MusicError::NoOrders));
+
+        let lead = song.add_instrument(lead());
+        let note = Step::Note("A4".parse().unwrap(), lead);
+        let pattern = song.add_pattern(vec![note, Step::Empty]).unwrap();
+        song.add_order([None, None, None, Some(pattern)]);
+        assert_eq!(
+            song.validate(),
+            Err(MusicError::WrongChannel {
+                pattern: 0,
+                instrument: 0,
+                channel: Channel::Noise,
+            })
+        );
+
+        let mut song = Song::new(6, 1);
+        let pattern = song
+            .add_pattern(vec![Step::Note("A4".parse().unwrap(), 3)])
+            .unwrap();
+        song.add_order([Some(pattern), None, None, None]);
+        assert_eq!(
+            song.validate(),
+            Err(MusicError::UnknownInstrument {
+                pattern: 0,
+                instrument: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn test_period_tables() {
+        let tables: Vec<String> = generate_period_tables()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(tables.iter().filter(|l| l.starts_with("dw ")).count(), 14);
+        // C2, the lowest note
+        assert!(tables[1].starts_with("dw 44, "));
+    }
+}
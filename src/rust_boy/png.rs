@@ -0,0 +1,640 @@
+//! Minimal PNG decoder for the image importer
+//!
+//! Decodes non-interlaced images of every color type, at any bit depth.
+//! Ancillary chunks other than `tRNS` are skipped and checksums are not
+//! verified: the images come from the project, not from the network.
+
+use super::image::ImageError;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// A decoded pixel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Pixel {
+    pub rgba: [u8; 4],
+    /// Palette index, for indexed images
+    pub index: Option<u8>,
+}
+
+/// A decoded image, pixels in row-major order
+#[derive(Debug)]
+pub(crate) struct DecodedImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Pixel>,
+}
+
+impl DecodedImage {
+    pub fn pixel(&self, x: usize, y: usize) -> Pixel {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Image header fields used by the decoder
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes per scanline, without the filter byte
+    fn stride(&self) -> usize {
+        (self.width * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+/// Decode a PNG file
+pub(crate) fn decode(data: &[u8]) -> Result<DecodedImage, ImageError> {
+    if data.len() < SIGNATURE.len() || data[..SIGNATURE.len()] != SIGNATURE {
+        return Err(corrupt("missing PNG signature"));
+    }
+
+    let mut header = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed = Vec::new();
+
+    let mut pos = SIGNATURE.len();
+    loop {
+        let length = read_u32(data, pos)? as usize;
+        let kind = data
+            .get(pos + 4..pos + 8)
+            .ok_or_else(|| corrupt("truncated chunk"))?;
+        let body = data
+            .get(pos + 8..pos + 8 + length)
+            .ok_or_else(|| corrupt("truncated chunk"))?;
+        // Chunk header, body and CRC
+        pos += 12 + length;
+
+        match kind {
+            b"IHDR" => header = Some(parse_header(body)?),
+            b"PLTE" => palette = body.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            b"tRNS" => transparency = body.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = header.ok_or_else(|| corrupt("missing IHDR chunk"))?;
+    if header.color_type == 3 && palette.is_empty() {
+        return Err(corrupt("indexed image without PLTE chunk"));
+    }
+
+    let raw = zlib_decompress(&compressed)?;
+    let scanlines = unfilter(&header, &raw)?;
+
+    let mut pixels = Vec::with_capacity(header.width * header.height);
+    for line in scanlines.chunks_exact(header.stride()) {
+        for x in 0..header.width {
+            pixels.push(read_pixel(&header, line, x, &palette, &transparency)?);
+        }
+    }
+
+    Ok(DecodedImage {
+        width: header.width,
+        height: header.height,
+        pixels,
+    })
+}
+
+fn corrupt(reason: &str) -> ImageError {
+    ImageError::Corrupt(reason.to_string())
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, ImageError> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| corrupt("truncated chunk"))
+}
+
+fn parse_header(body: &[u8]) -> Result<Header, ImageError> {
+    if body.len() != 13 {
+        return Err(corrupt("invalid IHDR chunk"));
+    }
+    let header = Header {
+        width: read_u32(body, 0)? as usize,
+        height: read_u32(body, 4)? as usize,
+        bit_depth: body[8],
+        color_type: body[9],
+    };
+
+    let valid_depth = match header.color_type {
+        0 => matches!(header.bit_depth, 1 | 2 | 4 | 8 | 16),
+        3 => matches!(header.bit_depth, 1 | 2 | 4 | 8),
+        2 | 4 | 6 => matches!(header.bit_depth, 8 | 16),
+        _ => false,
+    };
+    if !valid_depth {
+        return Err(ImageError::Unsupported(format!(
+            "color type {} with bit depth {}",
+            header.color_type, header.bit_depth
+        )));
+    }
+    if body[12] != 0 {
+        return Err(ImageError::Unsupported("interlaced image".to_string()));
+    }
+    if header.width == 0 || header.height == 0 {
+        return Err(corrupt("zero width or height"));
+    }
+    if header.width.checked_mul(header.bits_per_pixel()).is_none() {
+        return Err(corrupt("image too large"));
+    }
+    Ok(header)
+}
+
+/// Undo the per-scanline filters, returning the scanlines without filter bytes
+fn unfilter(header: &Header, raw: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let stride = header.stride();
+    let size = (stride + 1)
+        .checked_mul(header.height)
+        .ok_or_else(|| corrupt("image too large"))?;
+    if raw.len() < size {
+        return Err(corrupt("not enough image data"));
+    }
+    // Distance to the corresponding byte of the previous pixel
+    let bpp = header.bits_per_pixel().div_ceil(8);
+
+    let mut out = vec![0u8; stride * header.height];
+    for y in 0..header.height {
+        let filter = raw[y * (stride + 1)];
+        let input = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prev = if y == 0 {
+            None
+        } else {
+            Some(&done[(y - 1) * stride..])
+        };
+        let line = &mut rest[..stride];
+
+        for x in 0..stride {
+            let a = if x >= bpp { line[x - bpp] } else { 0 };
+            let b = prev.map_or(0, |p| p[x]);
+            let c = match prev {
+                Some(p) if x >= bpp => p[x - bpp],
+                _ => 0,
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(corrupt("unknown scanline filter")),
+            };
+            line[x] = input[x].wrapping_add(predictor);
+        }
+    }
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Read the `index`th sample of a scanline, keeping the top 8 bits of 16-bit samples
+fn sample(header: &Header, line: &[u8], index: usize) -> u8 {
+    match header.bit_depth {
+        8 => line[index],
+        16 => line[index * 2],
+        depth => {
+            let depth = depth as usize;
+            let bit = index * depth;
+            let mask = (1u16 << depth) - 1;
+            ((line[bit / 8] as u16 >> (8 - depth - bit % 8)) & mask) as u8
+        }
+    }
+}
+
+/// Full 16-bit sample, for comparing against `tRNS` colors
+fn sample16(header: &Header, line: &[u8], index: usize) -> u16 {
+    if header.bit_depth == 16 {
+        u16::from_be_bytes([line[index * 2], line[index * 2 + 1]])
+    } else {
+        sample(header, line, index) as u16
+    }
+}
+
+fn read_pixel(
+    header: &Header,
+    line: &[u8],
+    x: usize,
+    palette: &[[u8; 3]],
+    transparency: &[u8],
+) -> Result<Pixel, ImageError> {
+    let first = x * header.channels();
+    let pixel = match header.color_type {
+        // Grayscale
+        0 => {
+            let max = (1u16 << header.bit_depth.min(8)) - 1;
+            let gray = (sample(header, line, first) as u16 * 255 / max) as u8;
+            let key = transparency
+                .get(..2)
+                .map(|t| u16::from_be_bytes([t[0], t[1]]));
+            let alpha = if key == Some(sample16(header, line, first)) {
+                0
+            } else {
+                255
+            };
+            Pixel {
+                rgba: [gray, gray, gray, alpha],
+                index: None,
+            }
+        }
+        // RGB
+        2 => {
+            let rgb: Vec<u16> = (0..3).map(|i| sample16(header, line, first + i)).collect();
+            let key: Option<Vec<u16>> = (transparency.len() >= 6).then(|| {
+                transparency[..6]
+                    .chunks_exact(2)
+                    .map(|t| u16::from_be_bytes([t[0], t[1]]))
+                    .collect()
+            });
+            let alpha = if key.as_ref() == Some(&rgb) { 0 } else { 255 };
+            Pixel {
+                rgba: [
+                    sample(header, line, first),
+                    sample(header, line, first + 1),
+                    sample(header, line, first + 2),
+                    alpha,
+                ],
+                index: None,
+            }
+        }
+        // Indexed
+        3 => {
+            let index = sample(header, line, first);
+            let [r, g, b] = *palette
+                .get(index as usize)
+                .ok_or_else(|| corrupt("palette index out of range"))?;
+            let alpha = transparency.get(index as usize).copied().unwrap_or(255);
+            Pixel {
+                rgba: [r, g, b, alpha],
+                index: Some(index),
+            }
+        }
+        // Grayscale with alpha
+        4 => {
+            let gray = sample(header, line, first);
+            Pixel {
+                rgba: [gray, gray, gray, sample(header, line, first + 1)],
+                index: None,
+            }
+        }
+        // RGBA
+        _ => Pixel {
+            rgba: [
+                sample(header, line, first),
+                sample(header, line, first + 1),
+                sample(header, line, first + 2),
+                sample(header, line, first + 3),
+            ],
+            index: None,
+        },
+    };
+    Ok(pixel)
+}
+
+// ============================================
+// zlib / DEFLATE
+// ============================================
+
+/// Reads DEFLATE bits, least significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u8,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit: 0,
+        }
+    }
+
+    fn bit(&mut self) -> Result<u32, ImageError> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| corrupt("truncated compressed data"))?;
+        let value = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, ImageError> {
+        let mut value = 0;
+        for i in 0..count {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, ImageError> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| corrupt("truncated compressed data"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, ImageError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bit()? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order of the code length code lengths in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompress a zlib stream (the concatenated IDAT chunks)
+pub(crate) fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    if data.len() < 2
+        || data[0] & 0x0F != 8
+        || !(data[0] as u16 * 256 + data[1] as u16).is_multiple_of(31)
+    {
+        return Err(corrupt("invalid zlib header"));
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(ImageError::Unsupported(
+            "zlib preset dictionary".to_string(),
+        ));
+    }
+    inflate(&data[2..])
+}
+
+/// Decompress raw DEFLATE data
+fn inflate(data: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.bit()? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let len = reader.byte()? as u16 | (reader.byte()? as u16) << 8;
+                let nlen = reader.byte()? as u16 | (reader.byte()? as u16) << 8;
+                if len != !nlen {
+                    return Err(corrupt("invalid stored block length"));
+                }
+                for _ in 0..len {
+                    out.push(reader.byte()?);
+                }
+            }
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            }
+            _ => return Err(corrupt("invalid DEFLATE block type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), ImageError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_count] {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| corrupt("repeat without a previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(corrupt("too many code lengths"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<(), ImageError> {
+    loop {
+        let symbol = lengths.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let length =
+                    LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(reader)? as usize;
+                if d >= DIST_BASE.len() {
+                    return Err(corrupt("invalid distance code"));
+                }
+                let distance = DIST_BASE[d] as usize + reader.bits(DIST_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(corrupt("distance past the start of the data"));
+                }
+                // Byte by byte: the copy may overlap the bytes it produces
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err(corrupt("invalid literal/length code")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PNG with an IHDR for an 8-bit RGBA image and an empty IDAT
+    fn empty_png(width: u32, height: u32) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, 6, 0, 0, 0]);
+        // zlib stream with one empty stored block
+        let idat = [0x78, 0x01, 0x01, 0x00, 0x00, 0xFF, 0xFF, 0, 0, 0, 1];
+
+        let mut data = SIGNATURE.to_vec();
+        for (kind, body) in [(b"IHDR", &ihdr[..]), (b"IDAT", &idat), (b"IEND", &[])] {
+            data.extend((body.len() as u32).to_be_bytes());
+            data.extend(kind);
+            data.extend(body);
+            data.extend([0; 4]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_invalid_dimensions() {
+        assert!(matches!(
+            decode(&empty_png(0, 8)),
+            Err(ImageError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decode(&empty_png(8, 0)),
+            Err(ImageError::Corrupt(_))
+        ));
+        // The scanline buffer size does not fit in memory
+        assert!(matches!(
+            decode(&empty_png(u32::MAX, u32::MAX)),
+            Err(ImageError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decode(&empty_png(1, 1)),
+            Err(ImageError::Corrupt(reason)) if reason == "not enough image data"
+        ));
+    }
+
+    #[test]
+    fn test_inflate_stored_and_fixed_blocks() {
+        // Stored block: "hi"
+        assert_eq!(
+            inflate(&[0x01, 0x02, 0x00, 0xFD, 0xFF, b'h', b'i']).unwrap(),
+            b"hi"
+        );
+        // Fixed Huffman block with a back-reference: "abcabcabc"
+        let data = zlib_decompress(&[
+            0x78, 0xDA, 0x4B, 0x4C, 0x4A, 0x4E, 0x04, 0x23, 0x00, 0x11, 0x3D, 0x03, 0x73,
+        ])
+        .unwrap();
+        assert_eq!(data, b"abcabcabc");
+    }
+}
@@ -1,13 +1,30 @@
 //! Main RustBoy struct - the high-level Game Boy development API
 
-use crate::gb_asm::{Asm, Chunk, Instr, JumpTarget};
+use crate::gb_asm::{
+    Asm, Chunk, Condition, Instr, JumpTarget, Operand, Register, SymbolError, SymbolTable,
+};
 use crate::gb_std::flow::Emittable;
 
+use super::audio::{Audio, MAX_SOUND_EFFECTS};
+use super::camera::Camera;
+use super::cgb::{CgbConfig, IS_CGB_VAR, MAX_CGB_PALETTES};
+use super::display::{DisplayConfig, TileDataArea};
+use super::error::{BuildError, Diagnostic};
 use super::functions::{BuiltinFunction, FunctionRegistry};
 use super::inputs::InputManager;
+use super::labels::LabelCounter;
+use super::memory::{MemoryUsage, TileBank};
+use super::sgb::{IS_SGB_VAR, SgbConfig};
 use super::sprites::SpriteManager;
-use super::tiles::TileManager;
-use super::variables::VariableManager;
+use super::streaming::StreamedMap;
+use super::text::Font;
+use super::tiles::{
+    TILE_QUEUE_ENTRY, TILE_QUEUE_SIZE, TileManager, TileSlot, TileSource, slot_variable,
+};
+use super::timers::Timers;
+use super::variables::{RANDOM_VAR, VariableManager};
+use super::waveform::MAX_WAVEFORMS;
+use super::window::WindowManager;
 
 /// High-level Game Boy development API
 ///
@@ -40,6 +57,33 @@ pub struct RustBoy {
     /// Sprite manager with automatic OAM and tile handling
     pub sprites: SpriteManager,
 
+    /// LCD flags and palettes applied when the screen is turned on
+    pub display: DisplayConfig,
+
+    /// Window layer (HUDs, dialog boxes) with its own tilemap
+    pub window: WindowManager,
+
+    /// Game Boy Color mode and palettes (off by default)
+    pub cgb: CgbConfig,
+
+    /// Super Game Boy packets and border (off by default)
+    pub sgb: SgbConfig,
+
+    /// APU settings and sound effects
+    pub audio: Audio,
+
+    /// Hardware timer, frame-based tasks and cooldowns
+    pub timers: Timers,
+
+    /// Camera driving SCX/SCY, if one was added
+    camera: Option<Camera>,
+
+    /// Maps streamed into the background as the camera moves
+    streamed_maps: Vec<StreamedMap>,
+
+    /// Registered fonts (handles share their string table with these)
+    fonts: Vec<Font>,
+
     /// Function registry for auto-including builtin functions
     functions: FunctionRegistry,
 
@@ -47,7 +91,8 @@ pub struct RustBoy {
     if_counter: usize,
 
     /// Counter for generating unique general-purpose labels
-    label_counter: usize,
+    /// (shared with the sprite manager)
+    labels: LabelCounter,
 
     /// Custom constants defined by the user
     constants: Vec<(String, String)>,
@@ -60,23 +105,49 @@ pub struct RustBoy {
 
     /// Animation delay value in frames (higher = slower animations)
     animation_delay: u8,
+
+    /// Tiles copied by the upload queue each frame
+    tile_upload_budget: u8,
+
+    /// Fixed random seed, instead of DIV and input timing
+    random_seed: Option<u16>,
+
+    /// Position of the UpdateKeys call in the main loop, once inputs were added
+    key_poll_at: Option<usize>,
+
+    /// Whether wPrevKeys is updated before the keys are polled
+    saves_previous_keys: bool,
 }
 
 impl RustBoy {
     /// Create a new RustBoy instance
     pub fn new() -> Self {
+        let labels = LabelCounter::new();
         Self {
             asm: Asm::new(),
             tiles: TileManager::new(),
             vars: VariableManager::new(),
-            sprites: SpriteManager::new(),
+            sprites: SpriteManager::with_labels(labels.clone()),
+            display: DisplayConfig::default(),
+            window: WindowManager::new(),
+            cgb: CgbConfig::new(),
+            sgb: SgbConfig::new(),
+            audio: Audio::new(),
+            timers: Timers::with_labels(labels.clone()),
+            camera: None,
+            streamed_maps: Vec::new(),
+            fonts: Vec::new(),
             functions: FunctionRegistry::new(),
             if_counter: 0,
-            label_counter: 0,
+            labels,
             constants: Vec::new(),
             init_code: Vec::new(),
             main_loop_code: Vec::new(),
             animation_delay: 8, // Default: update animation every 8 frames
+            tile_upload_budget: 4,
+            random_seed: None,
+            key_poll_at: None,
+            saves_previous_keys: false,
         }
     }
 
@@ -87,6 +158,135 @@ impl RustBoy {
         self
     }
 
+    /// Replace the LCD configuration used at startup
+    ///
+    /// The tile data mode is not applied to the tile manager; use
+    /// `set_tile_data_area` before adding background tiles to change it.
+    pub fn set_display(&mut self, config: DisplayConfig) -> &mut Self {
+        self.display = config;
+        self
+    }
+
+    /// Select where background and window tiles are read from (LCDC bit 4)
+    ///
+    /// Call before adding background tiles: with `Signed8800` (the default)
+    /// they go to $9000-$97FF and then $8800-$8FFF, with `Unsigned8000`
+    /// they share $8000-$8FFF with the sprites.
+    pub fn set_tile_data_area(&mut self, area: TileDataArea) -> &mut Self {
+        self.display.bg_tile_data = area;
+        self.tiles.set_tile_data_area(area);
+        self
+    }
+
+    /// Add a camera starting at a world position
+    ///
+    /// Creates the `wCameraX`/`wCameraY` variables and copies the camera
+    /// position to SCX/SCY at the start of every frame. Calling it again
+    /// returns the existing camera.
+    pub fn add_camera(&mut self, x: u16, y: u16) -> Camera {
+        if let Some(camera) = &self.camera {
+            return camera.clone();
+        }
+        let camera = Camera::new(
+            self.vars.create_u16("wCameraX", x),
+            self.vars.create_u16("wCameraY", y),
+            self.labels.clone(),
+        );
+        self.camera = Some(camera.clone());
+        camera
+    }
+
+    /// Stream a map larger than the 32x32 background as the camera moves
+    ///
+    /// Adds a camera at (0, 0) if there is none yet. The visible part of the
+    /// map is loaded at startup, then the column or row the camera exposes is
+    /// copied to the background map during VBlank. The map's tiles must be
+    /// added separately with `tiles.add_background`.
+    pub fn add_streamed_map(&mut self, map: StreamedMap) -> &mut Self {
+        if self.camera.is_none() {
+            self.add_camera(0, 0);
+        }
+        self.vars.create_u8(&map.column_var(), 0);
+        self.vars.create_u8(&map.row_var(), 0);
+        self.streamed_maps.push(map);
+        self
+    }
+
+    /// Reserve VRAM for tilesets uploaded at runtime
+    ///
+    /// Creates the 16-bit `w{name}Tileset` variable, which remembers the
+    /// tileset last queued into the slot.
+    pub fn add_tile_slot(&mut self, name: &str, bank: TileBank, tiles: u8) -> TileSlot {
+        let (address, first_tile) = self.tiles.reserve_slot(name, bank, tiles);
+        let current = self.vars.create_buffer(&slot_variable(name), 2);
+        TileSlot::new(name, address, first_tile, tiles, current)
+    }
+
+    /// Set how many tiles the upload queue copies per frame (default 4)
+    ///
+    /// Each tile takes about 200 cycles of the 1140 available in VBlank,
+    /// shared with the OAM, scrolling and map streaming updates.
+    pub fn set_tile_upload_budget(&mut self, tiles: u8) -> &mut Self {
+        self.tile_upload_budget = tiles.max(1);
+        self
+    }
+
+    /// Always start the random generator from `seed`
+    ///
+    /// By default it is seeded from DIV at startup and DIV is mixed in
+    /// whenever a button is pressed. A fixed seed makes every run draw the
+    /// same numbers, e.g. to compare the output of an emulator in tests.
+    pub fn set_random_seed(&mut self, seed: u16) -> &mut Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Check if the program draws random numbers
+    fn uses_random(&self) -> bool {
+        let tick = self.timers.generate_tick_code();
+        let code = || {
+            self.init_code
+                .iter()
+                .chain(&self.main_loop_code)
+                .chain(&tick)
+        };
+        self.functions.is_called("Random", code())
+            || self.functions.is_called("RandomBelow", code())
+    }
+
+    /// Check if the program queues tile uploads
+    fn uses_tile_queue(&self) -> bool {
+        let tick = self.timers.generate_tick_code();
+        self.functions.is_called(
+            "QueueTiles",
+            self.init_code
+                .iter()
+                .chain(&self.main_loop_code)
+                .chain(&tick),
+        )
+    }
+
+    /// Register a font: a background tileset and its characters in tile order
+    ///
+    /// Tile indices are taken when the font is added, for the current tile
+    /// data area, so call `set_tile_data_area` first. Digits must be
+    /// consecutive, starting at '0', to print numbers. Tile $FF ends strings,
+    /// so characters that would land on it are reported when building.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let font = gb.add_font("Font", TileSource::from_file("font.2bpp", 37),
+    ///     " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+    /// gb.init(font.print("HELLO", &TileRef::from_xy(1, 1)));
+    /// ```
+    pub fn add_font(&mut self, name: &str, source: TileSource, charset: &str) -> Font {
+        let tiles = self.tiles.add_background(name, source);
+        let first_tile = self.tiles.tile_index(tiles).unwrap_or(0);
+        let font = Font::new(name, tiles, first_tile, charset);
+        self.fonts.push(font.clone());
+        font
+    }
+
     /// Define a constant value
     pub fn define_const(&mut self, name: &str, value: impl std::fmt::Display) -> &mut Self {
         self.constants
@@ -110,14 +310,12 @@ impl RustBoy {
 
     /// Get the next general-purpose label counter (auto-increments)
     pub fn next_label_counter(&mut self) -> usize {
-        let c = self.label_counter;
-        self.label_counter += 1;
-        c
+        self.labels.next()
     }
 
     /// Generate a unique label with prefix
     pub fn unique_label(&mut self, prefix: &str) -> String {
-        format!("{}_{}", prefix, self.next_label_counter())
+        self.labels.unique(prefix)
     }
 
     /// Add initialization code (runs once at startup)
@@ -191,10 +389,9 @@ impl RustBoy {
     ///
     /// This method validates that the function exists (either as a builtin or
     /// user-defined function) and automatically registers builtin functions
-    /// when they are called.
-    ///
-    /// # Panics
-    /// Panics if the function doesn't exist.
+    /// when they are called. User functions may be defined after the call;
+    /// a function that is still unknown at build time is reported as a
+    /// `Diagnostic::UnknownFunction`.
     ///
     /// # Example
     /// ```ignore
@@ -206,14 +403,7 @@ impl RustBoy {
     /// gb.add_to_main_loop(gb.call("IsWallTile"));
     /// ```
     pub fn call(&mut self, name: &str) -> Vec<Instr> {
-        if !self.functions.call_function(name) {
-            let available = self.functions.available_functions();
-            panic!(
-                "Unknown function '{}'. Available functions: {}",
-                name,
-                available.join(", ")
-            );
-        }
+        self.functions.call_function(name);
         vec![Instr::Call {
             target: JumpTarget::Label(name.to_string()),
         }]
@@ -223,9 +413,7 @@ impl RustBoy {
     ///
     /// This method emits the setup instructions before the call directly to
     /// the main loop, allowing fluent chaining without borrow checker issues.
-    ///
-    /// # Panics
-    /// Panics if the function doesn't exist.
+    /// The function is validated like in `call`.
     ///
     /// # Example
     /// ```ignore
@@ -234,14 +422,7 @@ impl RustBoy {
     /// gb.add_to_main_loop(IfCall::is_true("IsWallTile", _ball_momentum_y.set(1)));
     /// ```
     pub fn call_args(&mut self, name: &str, setup: Vec<Instr>) -> &mut Self {
-        if !self.functions.call_function(name) {
-            let available = self.functions.available_functions();
-            panic!(
-                "Unknown function '{}'. Available functions: {}",
-                name,
-                available.join(", ")
-            );
-        }
+        self.functions.call_function(name);
         self.main_loop_code.extend(setup);
         self.main_loop_code.push(Instr::Call {
             target: JumpTarget::Label(name.to_string()),
@@ -254,27 +435,250 @@ impl RustBoy {
         self.functions.function_exists(name)
     }
 
+    /// Summary of the tiles, OAM entries and WRAM bytes allocated so far
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            sprite_tiles: self.tiles.sprite_usage(),
+            background_tiles: self.tiles.background_usage(),
+            oam_entries: self.sprites.oam_usage(),
+            wram_bytes: self.vars.wram_usage(),
+        }
+    }
+
+    /// Check that every tile, sprite and variable fit in memory
+    ///
+    /// Returns the memory usage summary on success, or a `BuildError`
+    /// listing every allocation that overflowed its region.
+    pub fn check_memory(&self) -> Result<MemoryUsage, BuildError> {
+        let diagnostics = self.memory_diagnostics();
+        let usage = self.memory_usage();
+        if diagnostics.is_empty() {
+            Ok(usage)
+        } else {
+            Err(BuildError::new(diagnostics, usage))
+        }
+    }
+
+    fn memory_diagnostics(&self) -> Vec<Diagnostic> {
+        self.tiles
+            .errors()
+            .iter()
+            .chain(self.sprites.errors())
+            .chain(self.vars.errors())
+            .map(|err| Diagnostic::from(err.clone()))
+            .collect()
+    }
+
+    /// Collect every problem recorded by the managers and the function registry,
+    /// plus duplicate and undefined labels found in the generated program
+    fn collect_diagnostics(&self, asm: &Asm) -> Vec<Diagnostic> {
+        let mut diagnostics = self.memory_diagnostics();
+
+        let available = self.functions.available_functions();
+        for name in self.functions.unknown_calls() {
+            diagnostics.push(Diagnostic::UnknownFunction {
+                name,
+                available: available.clone(),
+            });
+        }
+
+        for name in self.functions.duplicate_functions() {
+            diagnostics.push(Diagnostic::DuplicateFunction { name: name.clone() });
+        }
+
+        for name in self.vars.duplicates() {
+            diagnostics.push(Diagnostic::DuplicateVariable { name: name.clone() });
+        }
+
+        for name in self.tiles.uncompressible() {
+            diagnostics.push(Diagnostic::Uncompressible { name: name.clone() });
+        }
+
+        if self.display.bg_tile_data != self.tiles.tile_data_area()
+            && self.tiles.background_usage().used > 0
+        {
+            diagnostics.push(Diagnostic::TileDataAreaMismatch {
+                display: self.display.bg_tile_data,
+                tiles: self.tiles.tile_data_area(),
+            });
+        }
+
+        if self.window.is_enabled() && self.window.map() == self.display.bg_tile_map {
+            diagnostics.push(Diagnostic::WindowMapClash {
+                map: self.window.map(),
+            });
+        }
+
+        let (bg_palettes, obj_palettes) = self.cgb.palette_counts();
+        for (kind, count) in [("background", bg_palettes), ("object", obj_palettes)] {
+            if count > MAX_CGB_PALETTES {
+                diagnostics.push(Diagnostic::TooManyCgbPalettes {
+                    kind: kind.to_string(),
+                    count,
+                });
+            }
+        }
+        if !self.cgb.is_enabled() {
+            let features = [
+                (
+                    self.tiles.uses_vram_bank1(),
+                    "VRAM bank 1 tiles or attribute maps",
+                ),
+                (bg_palettes + obj_palettes > 0, "CGB palettes"),
+                (self.cgb.uses_detection(), "CGB detection"),
+                (self.cgb.double_speed(), "Double speed"),
+                (
+                    self.functions.is_called("HdmaCopy", asm.instructions()),
+                    "HDMA copies",
+                ),
+            ];
+            for (_, feature) in features.iter().filter(|(used, _)| *used) {
+                diagnostics.push(Diagnostic::CgbRequired {
+                    feature: feature.to_string(),
+                });
+            }
+        }
+
+        if !self.sgb.is_enabled() {
+            let features = [
+                (self.sgb.has_data(), "SGB packets or border"),
+                (
+                    self.functions.is_called("SgbTransfer", asm.instructions()),
+                    "SGB transfers",
+                ),
+            ];
+            for (_, feature) in features.iter().filter(|(used, _)| *used) {
+                diagnostics.push(Diagnostic::SgbRequired {
+                    feature: feature.to_string(),
+                });
+            }
+        }
+
+        if self.audio.sfx_count() > MAX_SOUND_EFFECTS {
+            diagnostics.push(Diagnostic::TooManySoundEffects {
+                count: self.audio.sfx_count(),
+            });
+        }
+        if self.audio.waveform_count() > MAX_WAVEFORMS {
+            diagnostics.push(Diagnostic::TooManyWaveforms {
+                count: self.audio.waveform_count(),
+            });
+        }
+
+        for overflow in self.tiles.slot_overflows() {
+            diagnostics.push(Diagnostic::SlotOverflow {
+                tileset: overflow.tileset,
+                slot: overflow.slot,
+                requested: overflow.requested,
+                capacity: overflow.capacity,
+            });
+        }
+
+        for font in &self.fonts {
+            for character in font.unusable_chars() {
+                diagnostics.push(Diagnostic::FontTileOverflow {
+                    font: font.name().to_string(),
+                    character,
+                });
+            }
+            for character in font.missing_chars() {
+                diagnostics.push(Diagnostic::UnknownCharacter {
+                    font: font.name().to_string(),
+                    character,
+                });
+            }
+        }
+
+        for map in &self.streamed_maps {
+            if let Some(reason) = map.validate() {
+                diagnostics.push(Diagnostic::InvalidMap {
+                    name: map.name().to_string(),
+                    reason,
+                });
+            }
+        }
+
+        diagnostics.extend(self.sprites.missing_sprites());
+
+        // Labels of unknown functions and duplicate variables are already
+        // reported above, so they are not repeated
+        for err in SymbolTable::from_asm(asm).check() {
+            let already_reported = match &err {
+                SymbolError::UndefinedLabel { name, .. } => diagnostics
+                    .iter()
+                    .any(|d| matches!(d, Diagnostic::UnknownFunction { name: n, .. } if n == name)),
+                SymbolError::DuplicateLabel { name, .. } => diagnostics
+                    .iter()
+                    .any(|d| matches!(d, Diagnostic::DuplicateVariable { name: n } if n == name)),
+                SymbolError::UndefinedSymbol { .. } => false,
+            };
+            if !already_reported {
+                diagnostics.push(err.into());
+            }
+        }
+
+        diagnostics
+    }
+
     /// Build the final assembly output
+    ///
+    /// # Panics
+    /// Panics with the formatted `BuildError` if the build fails.
+    /// Use `try_build` to handle the errors instead.
     pub fn build(&mut self) -> String {
+        match self.try_build() {
+            Ok(output) => output.into_asm(),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Build the final assembly output, reporting every problem found
+    ///
+    /// Unknown functions, missing sprite IDs, duplicate function or variable
+    /// names and memory overflows are all collected into a single
+    /// `BuildError` instead of producing broken assembly.
+    pub fn try_build(&mut self) -> Result<BuildOutput, BuildError> {
         // Start fresh assembly
         let mut asm = Asm::new();
 
         // === HEADER CHUNK ===
         asm.chunk(Chunk::Header);
         asm.include_hardware();
-        asm.emit_all(crate::gb_std::utility::header_section());
+        if self.cgb.is_enabled() || self.sgb.is_enabled() {
+            let cgb_flag = self.cgb.mode().map(|mode| mode.header_flag());
+            asm.emit_all(crate::gb_std::utility::flagged_header_section(
+                cgb_flag,
+                self.sgb.is_enabled(),
+            ));
+        } else {
+            asm.emit_all(crate::gb_std::utility::header_section());
+        }
 
         // === CONSTANTS CHUNK ===
         asm.chunk(Chunk::Constants);
         for (name, value) in &self.constants {
             asm.def(name, value);
         }
+        for font in &self.fonts {
+            asm.emit_all(font.generate_charmap());
+        }
+        let uses_tile_queue = self.uses_tile_queue();
+        if uses_tile_queue {
+            asm.def("TILE_QUEUE_SIZE", TILE_QUEUE_SIZE);
+            asm.def("TILE_QUEUE_BUDGET", self.tile_upload_budget);
+        }
 
         // === INIT CHUNK ===
         asm.chunk(Chunk::Init);
 
         // Entry point
         asm.label("EntryPoint");
+        if self.cgb.is_enabled() {
+            asm.emit_all(self.cgb.generate_detect_code());
+        }
+        if self.sgb.is_enabled() {
+            asm.emit_all(self.sgb.generate_detect_code());
+        }
         asm.call("WaitVBlank");
         self.functions.use_function(BuiltinFunction::WaitVBlank);
 
@@ -282,10 +686,19 @@ impl RustBoy {
         asm.ld_a(0);
         asm.ld_addr_def_a("rLCDC");
 
+        if self.cgb.is_enabled() && self.cgb.double_speed() {
+            asm.emit_all(self.cgb.switch_double_speed());
+        }
+
+        // The SGB border goes through VRAM, before the tiles are loaded
+        if self.sgb.is_enabled() {
+            asm.emit_all(self.sgb.generate_init_code());
+        }
+
         // Generate tile memcopy calls (tiles need Memcopy function)
         if !self.tiles.is_empty() {
             self.functions.use_function(BuiltinFunction::Memcopy);
-            asm.emit_all(self.tiles.generate_memcopy_calls());
+            asm.emit_all(self.tiles.generate_memcopy_calls(self.cgb.is_enabled()));
         }
 
         // Initialize sprites (OAM setup)
@@ -293,31 +706,99 @@ impl RustBoy {
             asm.emit_all(self.sprites.generate_init_code());
         }
 
+        // Turn on sound before user code can play it
+        asm.emit_all(self.audio.generate_init_code());
+        asm.emit_all(self.timers.generate_init_code());
+
+        // Seed the random generator before user code can draw numbers
+        let uses_random = self.uses_random();
+        if uses_random {
+            if self.vars.find(RANDOM_VAR).is_none() {
+                self.vars.create_buffer(RANDOM_VAR, 2);
+            }
+            match self.random_seed {
+                Some(seed) => {
+                    let [low, high] = seed.to_le_bytes();
+                    asm.ld_a(low).ld_addr_def_a(RANDOM_VAR);
+                    asm.ld_a(high).ld_addr_def_a(&format!("{} + 1", RANDOM_VAR));
+                }
+                None => {
+                    asm.ld_a_addr_def("rDIV").ld_addr_def_a(RANDOM_VAR);
+                    asm.ld_addr_def_a(&format!("{} + 1", RANDOM_VAR));
+                }
+            }
+        }
+
+        // Empty the tile upload queue and slots before user code can queue
+        if uses_tile_queue && self.vars.find("wTileQueue").is_none() {
+            self.vars
+                .create_buffer("wTileQueue", TILE_QUEUE_SIZE as u16 * TILE_QUEUE_ENTRY);
+            self.vars.create_buffer("wTileQueueCount", 1);
+        }
+        if uses_tile_queue {
+            asm.ld_a(0).ld_addr_def_a("wTileQueueCount");
+        }
+        asm.emit_all(self.tiles.generate_slot_init_code());
+
         // Emit user init code
         asm.emit_all(self.init_code.clone());
 
         // Add animation variables if animations are used
         if self.sprites.has_animations() {
-            self.vars.create_u8("wFrameCounter", 0);
+            self.vars.ensure_u8("wFrameCounter", 0);
 
             // Create enabled flag for each animation
             for (var_name, initial_value) in self.sprites.get_animation_variables() {
-                self.vars.create_u8(&var_name, initial_value);
+                self.vars.ensure_u8(&var_name, initial_value);
+            }
+        }
+
+        // Sound effect, music and wave RAM state, set when the APU is turned on
+        for (name, size) in self.audio.variables() {
+            if self.vars.find(name).is_none() {
+                self.vars.create_buffer(name, size);
+            }
+        }
+
+        // Task countdowns and cooldowns, set before the user init code
+        for (name, size) in self.timers.variables() {
+            if self.vars.find(&name).is_none() {
+                self.vars.create_buffer(&name, size);
             }
         }
 
+        // Console type, set at EntryPoint before the variables are initialized
+        if self.cgb.is_enabled() && self.vars.find(IS_CGB_VAR).is_none() {
+            self.vars.create_buffer(IS_CGB_VAR, 1);
+        }
+        if self.sgb.is_enabled() && self.vars.find(IS_SGB_VAR).is_none() {
+            self.vars.create_buffer(IS_SGB_VAR, 1);
+        }
+
         // Emit variable initialization
         asm.emit_all(self.vars.generate_init_code());
 
-        // Turn on screen
-        asm.ld_a_label("LCDCF_ON | LCDCF_BGON | LCDCF_OBJON | LCDCF_OBJ16"); //TODO set in the struct
-        asm.ld_addr_def_a("rLCDC");
+        // Load the visible part of streamed maps (needs the camera variables)
+        if let Some(camera) = &self.camera {
+            for map in &self.streamed_maps {
+                asm.emit_all(map.generate_init_code(camera));
+            }
+        }
 
-        // Set default palettes
-        asm.ld_a(0b11100100);
-        asm.ld_addr_def_a("rBGP");
-        asm.ld_a(0b11100100);
-        asm.ld_addr_def_a("rOBP0");
+        // Position the window and load its tilemap
+        let mut display = self.display.clone();
+        if self.window.is_enabled() {
+            if self.window.has_tilemap() {
+                self.functions.use_function(BuiltinFunction::Memcopy);
+            }
+            asm.emit_all(self.window.generate_init_code());
+            display.window_enabled = true;
+            display.window_tile_map = self.window.map();
+        }
+
+        // Set palettes and turn on screen
+        asm.emit_all(self.cgb.generate_init_code());
+        asm.emit_all(display.generate_init_code());
 
         // === MAIN LOOP CHUNK ===
         asm.chunk(Chunk::MainLoop);
@@ -327,6 +808,33 @@ impl RustBoy {
         asm.call("WaitNotVBlank");
         asm.call("WaitVBlank");
 
+        // Scroll the background while still in VBlank
+        if let Some(camera) = &self.camera {
+            asm.emit_all(camera.generate_vblank_update());
+        }
+        for map in &self.streamed_maps {
+            asm.emit_all(map.generate_update_call());
+        }
+        if uses_tile_queue {
+            asm.call("ProcessTileQueue");
+        }
+        if self.audio.has_music() {
+            asm.call("MusicTick");
+        }
+        asm.emit_all(self.timers.generate_tick_code());
+
+        // Button presses come at random times: mix DIV into the seed
+        if uses_random && self.random_seed.is_none() && self.vars.find("wNewKeys").is_some() {
+            asm.ld_a_addr_def("wNewKeys")
+                .or_label("a", "a")
+                .jr_cond(Condition::Z, "RandomMixed")
+                .ld_a_addr_def("rDIV")
+                .ld_hl_label(RANDOM_VAR)
+                .xor(Operand::Reg(Register::A), Operand::AddrReg(Register::HL))
+                .ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+            asm.label("RandomMixed");
+        }
+
         // Generate animation calls at start of main loop
         if self.sprites.has_animations() {
             asm.emit_all(self.sprites.generate_animation_calls(self.animation_delay));
@@ -342,6 +850,9 @@ impl RustBoy {
         asm.chunk(Chunk::Data);
         asm.emit_all(self.vars.generate_sections());
 
+        // Builtins called from helper code (e.g. fonts) are included too
+        self.functions.use_called_builtins(asm.instructions());
+
         // === FUNCTIONS CHUNK ===
         asm.chunk(Chunk::Functions);
         asm.emit_all(self.functions.generate_all());
@@ -349,17 +860,37 @@ impl RustBoy {
         // Generate animation functions
         for (name, body) in self.sprites.generate_animation_functions() {
             // Register function first so it's tracked (though we emit directly)
-            self.functions.register_user_function(&name, Vec::new());
+            if !self.functions.function_exists(&name) {
+                self.functions.register_user_function(&name, Vec::new());
+            }
             asm.emit_all(body);
         }
 
+        // Generate map streaming functions
+        if let Some(camera) = &self.camera {
+            let map_base = self.display.bg_tile_map.base_address();
+            for map in &self.streamed_maps {
+                asm.emit_all(map.generate_functions(camera, map_base));
+            }
+        }
+
         // === TILES CHUNK ===
         asm.chunk(Chunk::Tiles);
-        asm.emit_all(self.tiles.generate_tile_data());
+        asm.emit_all(self.tiles.generate_tile_data(self.cgb.is_enabled()));
 
         // === TILEMAP CHUNK ===
         asm.chunk(Chunk::Tilemap);
         asm.emit_all(self.tiles.generate_tilemap_data());
+        for map in &self.streamed_maps {
+            asm.emit_all(map.generate_data());
+        }
+        asm.emit_all(self.window.generate_tilemap_data());
+        for font in &self.fonts {
+            asm.emit_all(font.generate_strings());
+        }
+        asm.emit_all(self.cgb.generate_data());
+        asm.emit_all(self.sgb.generate_data());
+        asm.emit_all(self.audio.generate_data());
 
         // Include any raw assembly that was added (legacy Main chunk)
         let existing = self.asm.get_chunk(Chunk::Main).cloned().unwrap_or_default();
@@ -368,7 +899,15 @@ impl RustBoy {
             asm.emit_all(existing);
         }
 
-        asm.to_asm()
+        let diagnostics = self.collect_diagnostics(&asm);
+        if !diagnostics.is_empty() {
+            return Err(BuildError::new(diagnostics, self.memory_usage()));
+        }
+
+        Ok(BuildOutput {
+            asm: asm.to_asm(),
+            memory: self.memory_usage(),
+        })
     }
 
     /// Add code to the main game loop
@@ -395,10 +934,14 @@ impl RustBoy {
     ///
     /// This method takes an InputManager and generates the complete input
     /// handling code, including:
-    /// 1. Calling UpdateKeys to poll the controller
+    /// 1. Calling UpdateKeys to poll the controller (once per frame, even
+    ///    when several InputManagers are added)
     /// 2. Checking each registered button binding
     /// 3. Executing associated actions when buttons are pressed
     ///
+    /// The keys of the previous frame (for releases) and the auto-repeat
+    /// counters are kept in variables created here.
+    ///
     /// # Example
     /// ```ignore
     /// let mut inputs = InputManager::new();
@@ -415,16 +958,34 @@ impl RustBoy {
         self.functions.use_function(BuiltinFunction::UpdateKeys);
 
         // Auto-create input variables required by UpdateKeys
-        self.vars.create_u8("wCurKeys", 0);
-        self.vars.create_u8("wNewKeys", 0);
+        self.vars.ensure_u8("wCurKeys", 0);
+        self.vars.ensure_u8("wNewKeys", 0);
 
         // Add call to UpdateKeys
-        self.main_loop_code.push(Instr::Call {
-            target: JumpTarget::Label("UpdateKeys".to_string()),
+        let poll_at = *self.key_poll_at.get_or_insert_with(|| {
+            self.main_loop_code.push(Instr::Call {
+                target: JumpTarget::Label("UpdateKeys".to_string()),
+            });
+            self.main_loop_code.len() - 1
         });
 
+        // Keep the keys of the previous frame, before they are polled
+        if inputs.needs_previous_keys() && !self.saves_previous_keys {
+            self.saves_previous_keys = true;
+            self.vars.ensure_u8("wPrevKeys", 0);
+            let mut asm = Asm::new();
+            asm.ld_a_addr_def("wCurKeys").ld_addr_def_a("wPrevKeys");
+            let copy = asm.get_main_instrs();
+            self.key_poll_at = Some(poll_at + copy.len());
+            self.main_loop_code.splice(poll_at..poll_at, copy);
+        }
+
         // Add the input handling code
-        self.main_loop_code.extend(inputs.generate_code());
+        let input = inputs.generate_code(&self.labels);
+        for counter in &input.counters {
+            self.vars.ensure_u8(counter, 0);
+        }
+        self.main_loop_code.extend(input.code);
 
         self
     }
@@ -439,14 +1000,11 @@ impl RustBoy {
         y: u8,
         flags: u8,
     ) -> super::sprites::SpriteId {
-        // Get tile count before moving tile_source
-        let tile_count = tile_source.tile_count() as u8;
-
-        // Add the tile to the tile manager
+        // Add the tile to the tile manager, which also picks its OAM tile index
         let tile_id = self.tiles.add_sprite(name, tile_source);
+        let tile_index = self.tiles.tile_index(tile_id).unwrap_or(0);
 
-        // Add the sprite to the sprite manager with tile count for proper index allocation
-        let sprite_id = self.sprites.add(name, x, y, flags, tile_count);
+        let sprite_id = self.sprites.add(name, x, y, flags, tile_index);
 
         // Link the tile ID to the sprite
         self.sprites.set_tile_id(sprite_id, tile_id);
@@ -492,6 +1050,36 @@ impl RustBoy {
     }
 }
 
+/// Successful result of `RustBoy::try_build`
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    asm: String,
+    memory: MemoryUsage,
+}
+
+impl BuildOutput {
+    /// The generated RGBDS assembly
+    pub fn asm(&self) -> &str {
+        &self.asm
+    }
+
+    /// Consume the output, returning the generated assembly
+    pub fn into_asm(self) -> String {
+        self.asm
+    }
+
+    /// Memory usage of the built program
+    pub fn memory_usage(&self) -> &MemoryUsage {
+        &self.memory
+    }
+}
+
+impl std::fmt::Display for BuildOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.asm)
+    }
+}
+
 impl Default for RustBoy {
     fn default() -> Self {
         Self::new()
@@ -501,6 +1089,12 @@ impl Default for RustBoy {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::gb_std::inputs::PadButton;
+    use crate::rust_boy::{
+        AttrBlock, CgbMode, CgbPalette, Compression, Duty, Envelope, HardwareTimer, Instrument,
+        MusicError, Noise, SgbBorder, SgbMask, SgbPacket, Song, SoundEffect, Step, TileMapArea,
+        TileSource, TimerClock, WaveVolume, Waveform,
+    };
 
     #[test]
     fn test_new_rustboy() {
@@ -551,4 +1145,673 @@ mod tests {
         assert!(output.contains("Main:"));
         assert!(output.contains("WaitVBlank:"));
     }
+
+    #[test]
+    fn test_check_memory_reports_overflow() {
+        let mut gb = RustBoy::new();
+
+        let tiles = [["$00"; 8]; 200];
+        gb.add_sprite("Big", TileSource::from_raw(&tiles), 0, 0, 0);
+        gb.add_sprite("Bigger", TileSource::from_raw(&tiles), 0, 0, 0);
+
+        let err = gb.check_memory().unwrap_err();
+        // Only the tile manager allocates sprite tiles
+        assert_eq!(err.diagnostics().len(), 1);
+        assert_eq!(err.memory_usage().sprite_tiles.used, 200);
+        assert!(err.to_string().contains("sprite tile bank full: 'Bigger'"));
+    }
+
+    #[test]
+    fn test_try_build_collects_diagnostics() {
+        let mut gb = RustBoy::new();
+
+        gb.vars.create_u8("wScore", 0);
+        gb.vars.create_u8("wScore", 0);
+        gb.define_function_from("Helper", Vec::<Instr>::new());
+        gb.define_function_from("Helper", Vec::<Instr>::new());
+        let call = gb.call("DoesNotExist");
+        gb.add_to_main_loop(call);
+
+        let err = gb.try_build().unwrap_err();
+        let diagnostics = err.diagnostics();
+        assert_eq!(diagnostics.len(), 3);
+        assert!(diagnostics.contains(&Diagnostic::DuplicateVariable {
+            name: "wScore".to_string()
+        }));
+        assert!(diagnostics.contains(&Diagnostic::DuplicateFunction {
+            name: "Helper".to_string()
+        }));
+        assert!(matches!(
+            &diagnostics[0],
+            Diagnostic::UnknownFunction { name, .. } if name == "DoesNotExist"
+        ));
+    }
+
+    #[test]
+    fn test_call_before_define() {
+        let mut gb = RustBoy::new();
+
+        let call = gb.call("Later");
+        gb.add_to_main_loop(call);
+        gb.define_function_from("Later", Vec::<Instr>::new());
+
+        let output = gb.try_build().unwrap();
+        assert!(output.asm().contains("call Later"));
+        assert_eq!(output.memory_usage().oam_entries.used, 0);
+    }
+
+    #[test]
+    fn test_try_build_reports_labels() {
+        let mut gb = RustBoy::new();
+
+        gb.raw(|asm| {
+            asm.label("Twice").label("Twice").call("Nowhere");
+        });
+
+        let err = gb.try_build().unwrap_err();
+        assert_eq!(
+            err.diagnostics(),
+            &[
+                Diagnostic::Symbol(SymbolError::DuplicateLabel {
+                    name: "Twice".to_string(),
+                    count: 2,
+                }),
+                Diagnostic::Symbol(SymbolError::UndefinedLabel {
+                    name: "Nowhere".to_string(),
+                    scope: Some("Twice".to_string()),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_helpers_build() {
+        let mut gb = RustBoy::new();
+        let tile = [["$00"; 8]; 1];
+        let paddle = gb.add_sprite("Paddle", TileSource::from_raw(&tile), 16, 128, 0);
+
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::Left, gb.sprites.move_left_limit(paddle, 1, 15));
+        inputs.on_press(PadButton::Left, gb.sprites.move_left_limit(paddle, 1, 15));
+        gb.add_inputs(inputs);
+
+        assert!(gb.try_build().is_ok());
+    }
+
+    #[test]
+    fn test_input_triggers() {
+        let mut gb = RustBoy::new();
+        let mut menu = InputManager::new();
+        menu.on_pressed(PadButton::A, gb.call("WaitVBlank"));
+        menu.on_repeat(PadButton::Down, 20, 6, gb.call("WaitVBlank"));
+        menu.on_combo(
+            &[PadButton::Start, PadButton::Select],
+            gb.call("WaitVBlank"),
+        );
+        gb.add_inputs(menu);
+        // Added later, but the keys are still saved before they are polled
+        let mut game = InputManager::new();
+        game.on_released(PadButton::B, gb.call("WaitVBlank"));
+        gb.add_inputs(game);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert_eq!(output.matches("call UpdateKeys").count(), 1);
+        assert!(output.contains("ld a, [wCurKeys]\n    ld [wPrevKeys], a\n    call UpdateKeys"));
+        assert!(output.contains("ld a, [wNewKeys]\n    and a, PADF_A"));
+        assert!(output.contains("ld a, 20\n    jr CheckDownRepeatFire_1"));
+        assert!(output.contains("ld [wKeyRepeat_1], a"));
+        assert!(output.contains("wKeyRepeat_1: db"));
+        assert!(output.contains("cp PADF_START | PADF_SELECT"));
+        assert!(output.contains("ld a, [wPrevKeys]\n    and a, PADF_B"));
+    }
+
+    #[test]
+    fn test_camera_updates_scroll_registers() {
+        let mut gb = RustBoy::new();
+        let camera = gb.add_camera(0, 0);
+        assert_eq!(gb.add_camera(8, 8).x().name(), camera.x().name());
+        gb.add_to_main_loop(camera.scroll_by(1, 0));
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("ld [rSCX], a"));
+        assert!(output.contains("ld [rSCY], a"));
+        assert_eq!(output.matches("wCameraX: dw").count(), 1);
+    }
+
+    #[test]
+    fn test_streamed_map_builds() {
+        let mut gb = RustBoy::new();
+        let rows = vec![vec![0u8; 64]; 24];
+        gb.add_streamed_map(StreamedMap::from_rows("Level", &rows));
+        gb.add_streamed_map(StreamedMap::new("Broken", 40, 2, vec![0; 10]));
+
+        let err = gb.try_build().unwrap_err();
+        assert_eq!(err.diagnostics().len(), 1);
+        assert!(matches!(
+            &err.diagnostics()[0],
+            Diagnostic::InvalidMap { name, .. } if name == "Broken"
+        ));
+
+        let mut gb = RustBoy::new();
+        gb.add_streamed_map(StreamedMap::from_rows("Level", &rows));
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("call LevelStream"));
+        assert!(output.contains("LevelLoadColumn:"));
+        assert!(output.contains("LevelRows:"));
+    }
+
+    #[test]
+    fn test_window_enables_lcdc_bits() {
+        let mut gb = RustBoy::new();
+        gb.window.enable(0, 128).place_tiles(0, 0, &[1, 2, 3]);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("LCDCF_WINON | LCDCF_WIN9C00"));
+        assert!(output.contains("ld [rWX], a"));
+        assert!(output.contains("WindowTilemap:"));
+        assert!(output.contains("Memcopy:"));
+    }
+
+    #[test]
+    fn test_window_map_clash() {
+        let mut gb = RustBoy::new();
+        gb.display.bg_tile_map = TileMapArea::Map9C00;
+        gb.window.enable(0, 128);
+
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::WindowMapClash {
+            map: TileMapArea::Map9C00,
+        }));
+
+        gb.window.set_map(TileMapArea::Map9800);
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("LCDCF_BG9C00"));
+        assert!(!output.contains("LCDCF_WIN9C00"));
+    }
+
+    #[test]
+    fn test_font_strings_and_numbers() {
+        let mut gb = RustBoy::new();
+        let tile = [["$00"; 8]; 12];
+        gb.tiles
+            .add_background("Bricks", TileSource::from_raw(&[["$00"; 8]; 2]));
+        let font = gb.add_font("Font", TileSource::from_raw(&tile), " 0123456789!");
+        let score = gb.vars.create_u8("wScore", 0);
+        let points = gb.vars.create_bcd("wPoints", 4);
+
+        assert_eq!(font.encode("10"), vec![4, 3]);
+        gb.init(font.print("0!", &gb.window.tile_ref(0, 0)));
+        gb.add_to_main_loop(font.print_decimal(score.name(), &gb.window.tile_ref(4, 0)));
+        gb.add_to_main_loop(font.print_bcd(&points, &gb.window.tile_ref(8, 0)));
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("CHARMAP \"!\", 13"));
+        assert!(output.contains("db \"0!\", $FF"));
+        assert!(output.contains("PrintString:"));
+        assert!(output.contains("PrintDecimal:"));
+        // The font's '0' is tile 3, after the two brick tiles and ' '
+        assert!(output.contains("ld c, 3\n    ld de, wPoints+1\n    ld b, 2\n    call PrintBcd"));
+        assert!(output.contains("PrintBcd:"));
+
+        font.print("?", &gb.window.tile_ref(0, 1));
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::UnknownCharacter {
+            font: "Font".to_string(),
+            character: '?',
+        }));
+    }
+
+    #[test]
+    fn test_font_glyph_on_string_end() {
+        let mut gb = RustBoy::new();
+        // Fill $9000-$97FF so the font spills below $9000, up to tile $FF
+        gb.tiles
+            .add_background("Bricks", TileSource::Bytes(vec![[0; 16]; 128]));
+        gb.add_font("Font", TileSource::Bytes(vec![[0; 16]; 6]), " 01234");
+
+        let err = gb.try_build().unwrap_err();
+        let overflows: Vec<_> = err
+            .diagnostics()
+            .iter()
+            .filter(|d| matches!(d, Diagnostic::FontTileOverflow { .. }))
+            .collect();
+        assert_eq!(
+            overflows,
+            [&Diagnostic::FontTileOverflow {
+                font: "Font".to_string(),
+                character: '4',
+            }]
+        );
+    }
+
+    #[test]
+    fn test_compressed_tiles_use_decompressor() {
+        let mut gb = RustBoy::new();
+        let tiles = gb
+            .tiles
+            .add_background("Sky", TileSource::Bytes(vec![[0; 16]; 4]));
+        let map = gb.tiles.add_tilemap("SkyMap", &[[0; 32]; 18]);
+        gb.tiles
+            .compress(tiles, Compression::Rle)
+            .compress(map, Compression::Lz);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("call DecompressRle"));
+        assert!(output.contains("DecompressRle:"));
+        assert!(output.contains("DecompressLz:"));
+    }
+
+    #[test]
+    fn test_cgb_mode() {
+        let mut gb = RustBoy::new();
+        gb.tiles.add_bank1(
+            "Grass",
+            TileSource::Bytes(vec![[0; 16]; 2]),
+            TileBank::Background,
+        );
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::CgbRequired {
+            feature: "VRAM bank 1 tiles or attribute maps".to_string(),
+        }));
+
+        gb.cgb.enable(CgbMode::Compatible);
+        gb.cgb.add_bg_palette(CgbPalette::GRAYSCALE);
+        gb.tiles.add_attribute_map("GrassAttributes", &[[0x08; 32]]);
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("db CART_COMPATIBLE_DMG_GBC"));
+        assert!(output.contains("cp BOOTUP_A_CGB"));
+        assert!(output.contains("wIsCgb: ds 1"));
+        assert!(output.contains("ld [rVBK], a"));
+        assert!(output.contains("VramBank1Done:"));
+        assert!(output.contains("CgbBgPalettes:"));
+        assert!(output.contains("ld [rBCPD], a"));
+
+        for _ in 0..8 {
+            gb.cgb.add_obj_palette(CgbPalette::GRAYSCALE);
+        }
+        assert!(gb.try_build().is_ok());
+        gb.cgb.add_obj_palette(CgbPalette::GRAYSCALE);
+        let err = gb.try_build().unwrap_err();
+        assert_eq!(
+            err.diagnostics(),
+            &[Diagnostic::TooManyCgbPalettes {
+                kind: "object".to_string(),
+                count: 9,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_cgb_hdma_and_double_speed() {
+        let mut gb = RustBoy::new();
+        gb.tiles
+            .add_background("Bricks", TileSource::Bytes(vec![[0; 16]; 4]));
+        let output = gb.build();
+        assert!(!output.contains("HdmaCopy"));
+
+        gb.cgb.set_double_speed(true);
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::CgbRequired {
+            feature: "Double speed".to_string(),
+        }));
+
+        gb.cgb.enable(CgbMode::Exclusive);
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("SECTION \"Tile data\", ROM0, ALIGN[4]"));
+        assert!(output.contains("ld bc, BricksEnd - Bricks\n    call HdmaCopy"));
+        assert!(output.contains("HdmaCopy:"));
+        assert!(output.contains("Memcopy:"));
+        assert!(output.contains("call SwitchDoubleSpeed"));
+        assert!(output.contains("    stop"));
+    }
+
+    #[test]
+    fn test_sgb_packets_and_border() {
+        let mut gb = RustBoy::new();
+        gb.sgb.send_at_startup(SgbPacket::mask_en(SgbMask::Black));
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::SgbRequired {
+            feature: "SGB packets or border".to_string(),
+        }));
+
+        gb.sgb.enable();
+        gb.cgb.enable(CgbMode::Compatible);
+        let border = SgbBorder::new(vec![[0; 32]; 4], vec![0; 32 * 28], Vec::new()).unwrap();
+        gb.sgb.set_border(border);
+        let attr = SgbPacket::attr_blk(&[AttrBlock::new(0, 16, 19, 17, 1)]).unwrap();
+        let send = gb.sgb.send(&attr);
+        gb.add_to_main_loop(send);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("db CART_INDICATOR_SGB"));
+        assert!(output.contains("db CART_COMPATIBLE_DMG_GBC"));
+        assert!(output.contains("cp 20\n"));
+        assert!(output.contains("wIsSgb: ds 1"));
+        assert!(output.contains("call SgbVramTransfer"));
+        assert!(output.contains("SgbTransfer:"));
+        assert!(output.contains("SgbBorderMap:"));
+        assert!(output.contains("Memcopy:"));
+        // Four border packets, the startup one and the main loop one, stored once
+        assert!(gb.try_build().is_ok());
+        assert!(output.contains("SgbPacket5:"));
+        assert!(!output.contains("SgbPacket6:"));
+    }
+
+    #[test]
+    fn test_sgb_transfer_with_lcd_off() {
+        let mut gb = RustBoy::new();
+        gb.sgb.enable();
+        let border = SgbBorder::new(vec![[0; 32]; 4], vec![0; 32 * 28], Vec::new()).unwrap();
+        gb.sgb.set_border(border);
+
+        // The border is sent after the LCD is turned off, and LY never reaches
+        // VBlank then: any wait before the transfer turns the LCD on must be
+        // skipped when it is already off
+        let output = gb.try_build().unwrap().into_asm();
+        let lines: Vec<&str> = output
+            .lines()
+            .map(str::trim)
+            .skip_while(|&line| line != "SgbVramTransfer:")
+            .take_while(|line| !line.contains("LCDCF_ON | LCDCF_BGON | LCDCF_BG8000"))
+            .collect();
+        let wait = lines
+            .iter()
+            .position(|&line| line == "call WaitVBlank")
+            .unwrap();
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|&&line| line == "call WaitVBlank")
+                .count(),
+            1
+        );
+        assert_eq!(
+            lines[wait - 3..wait],
+            ["ld a, [rLCDC]", "and a, LCDCF_ON", "jr z, .lcdOff"]
+        );
+    }
+
+    #[test]
+    fn test_sound_effects() {
+        let mut gb = RustBoy::new();
+        assert!(!gb.build().contains("rNR52"));
+
+        let coin = gb.audio.add_sfx(
+            "Coin",
+            SoundEffect::Pulse2 {
+                duty: Duty::Quarter,
+                envelope: Envelope::fade_out(15, 1),
+                note: "B5".parse().unwrap(),
+                length: Some(32),
+            },
+        );
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::A, gb.audio.play(coin));
+        gb.add_inputs(inputs);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("ld a, AUDENA_ON\n    ld [rNR52], a"));
+        assert!(output.contains("call PlaySfx"));
+        assert!(output.contains("PlaySfx:"));
+        assert!(output.contains("ldh [c], a"));
+        assert!(output.contains("SfxTable:"));
+
+        for _ in 0..MAX_SOUND_EFFECTS {
+            gb.audio.add_sfx(
+                "Silence",
+                SoundEffect::Noise {
+                    noise: Noise::default(),
+                    envelope: Envelope::constant(0),
+                    length: None,
+                },
+            );
+        }
+        let err = gb.try_build().unwrap_err();
+        assert_eq!(
+            err.diagnostics(),
+            &[Diagnostic::TooManySoundEffects { count: 257 }]
+        );
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut gb = RustBoy::new();
+        gb.timers
+            .set_hardware_timer(HardwareTimer::new(TimerClock::Hz4096, 0));
+        gb.define_function_from("Tick", Vec::<Instr>::new());
+        gb.define_function_from("Shoot", Vec::<Instr>::new());
+        let call_tick = gb.call("Tick");
+        let call_shoot = gb.call("Shoot");
+        let tick = gb.timers.every(60, call_tick);
+        let fire = gb.timers.add_cooldown(8);
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::A, gb.timers.when_ready(fire, call_shoot));
+        inputs.on_press(PadButton::B, gb.timers.cancel(tick));
+        gb.add_inputs(inputs);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("ld a, TACF_START | TACF_4KHZ\n    ld [rTAC], a"));
+        assert!(output.contains("wTask0: ds 2"));
+        assert!(output.contains("wCooldown0: ds 1"));
+        // Timers count down before the inputs are checked
+        let tick_at = output.find("TaskDone_0:").unwrap();
+        assert!(output.find("CooldownTick_0:").unwrap() < tick_at);
+        assert!(tick_at < output.find("call UpdateKeys").unwrap());
+        assert!(output.contains("ld a, 8\n    ld [wCooldown0], a\n    call Shoot"));
+    }
+
+    #[test]
+    fn test_timer_started_from_init() {
+        let mut gb = RustBoy::new();
+        gb.define_function_from("Hide", Vec::<Instr>::new());
+        let call_hide = gb.call("Hide");
+        let hide = gb.timers.after(90, call_hide);
+        gb.init(gb.timers.start(hide));
+
+        // Nothing resets the slot between the start and the main loop
+        let output = gb.try_build().unwrap().into_asm();
+        let start = output.find("ld a, 90\n    ld [wTask0], a").unwrap();
+        let init_rest = &output[start..output.find("Main:").unwrap()];
+        assert_eq!(init_rest.matches("ld [wTask0], a").count(), 1);
+        assert!(output[..start].contains("ld a, 0\n    ld [wTask0], a"));
+    }
+
+    #[test]
+    fn test_random() {
+        let mut gb = RustBoy::new();
+        let drop = gb.vars.create_u8("wDrop", 0);
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::A, drop.random(1..=6));
+        gb.add_inputs(inputs);
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("ld a, [rDIV]\n    ld [wRandom], a\n    ld [wRandom + 1], a"));
+        assert!(output.contains("jr z, RandomMixed"));
+        assert!(output.contains("RandomBelow:"));
+        assert!(output.contains("Random:"));
+        assert!(output.contains("wRandom: ds 2"));
+
+        // A fixed seed, and no input timing
+        let mut gb = RustBoy::new();
+        gb.set_random_seed(0xBEEF);
+        let drop = gb.vars.create_u8("wDrop", 0);
+        gb.init(drop.random(0..=255));
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::A, drop.random(1..=6));
+        gb.add_inputs(inputs);
+        let output = gb.try_build().unwrap().into_asm();
+        let seed = output
+            .find("ld a, 239\n    ld [wRandom], a\n    ld a, 190")
+            .unwrap();
+        assert!(seed < output.find("call Random\n").unwrap());
+        assert!(!output.contains("rDIV"));
+        assert!(!output.contains("RandomMixed"));
+
+        let gb_without = RustBoy::new().build();
+        assert!(!gb_without.contains("wRandom"));
+    }
+
+    #[test]
+    fn test_music() {
+        let mut gb = RustBoy::new();
+        let mut song = Song::new(8, 2);
+        let bass = song.add_instrument(Instrument::Wave {
+            volume: WaveVolume::Full,
+            length: None,
+            wave: Some(Waveform::triangle()),
+        });
+        let line = song
+            .add_pattern(vec![Step::Note("C3".parse().unwrap(), bass), Step::Off])
+            .unwrap();
+        song.add_order([None, None, Some(line), None]);
+        let theme = gb.audio.add_song(song).unwrap();
+        let play = gb.audio.play_music(theme);
+        gb.init(play);
+        let mut inputs = InputManager::new();
+        inputs.on_press(PadButton::Start, gb.audio.pause_music());
+        inputs.on_press(PadButton::Select, gb.audio.resume_music());
+        gb.add_inputs(inputs);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("ld [rNR52], a"));
+        assert!(output.contains("ld hl, Song0\n    call MusicPlay"));
+        assert!(output.contains("call WaitVBlank\n    call MusicTick"));
+        assert!(output.contains("MusicTick:"));
+        assert!(output.contains("MusicWavePeriods:"));
+        assert!(output.contains("wMusicPointers: ds 8"));
+        assert!(output.contains("ld [wSfxTimers + 3], a"));
+        assert!(output.contains("LoadWave:"));
+        assert!(output.contains("WaveTable:\n    db $01, $23, $45"));
+        assert!(output.contains("ld a, 255\n    ld [wWaveLoaded], a"));
+
+        assert_eq!(
+            gb.audio.add_song(Song::new(8, 1)),
+            Err(MusicError::NoOrders)
+        );
+    }
+
+    #[test]
+    fn test_tile_data_area_mismatch() {
+        let mut gb = RustBoy::new();
+        gb.tiles
+            .add_background("Bricks", TileSource::Bytes(vec![[0; 16]; 2]));
+        gb.display.bg_tile_data = TileDataArea::Unsigned8000;
+        let err = gb.try_build().unwrap_err();
+        assert!(
+            err.diagnostics()
+                .contains(&Diagnostic::TileDataAreaMismatch {
+                    display: TileDataArea::Unsigned8000,
+                    tiles: TileDataArea::Signed8800,
+                })
+        );
+
+        let mut gb = RustBoy::new();
+        gb.set_tile_data_area(TileDataArea::Unsigned8000);
+        gb.tiles
+            .add_background("Bricks", TileSource::Bytes(vec![[0; 16]; 2]));
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("LCDCF_BG8000"));
+    }
+
+    #[test]
+    fn test_tile_upload_queue() {
+        let mut gb = RustBoy::new();
+        let slot = gb.add_tile_slot("Frames", TileBank::Sprite, 2);
+        let walk = gb
+            .tiles
+            .add_streamed("Walk", TileSource::Bytes(vec![[0; 16]; 2]));
+        let jump = gb
+            .tiles
+            .add_streamed("Jump", TileSource::Bytes(vec![[0; 16]; 3]));
+        gb.set_tile_upload_budget(2);
+        gb.add_to_main_loop(gb.tiles.upload(walk, &slot));
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("DEF TILE_QUEUE_BUDGET EQU 2"));
+        assert!(output.contains("call ProcessTileQueue"));
+        assert!(output.contains("QueueTiles:"));
+        assert!(output.contains("wTileQueue: ds 40"));
+        assert!(output.contains("ld hl, 32768"));
+        // Streamed tiles stay in ROM
+        assert!(!output.contains("ld de, Walk\nld hl, $"));
+
+        gb.add_to_main_loop(gb.tiles.upload(jump, &slot));
+        let err = gb.try_build().unwrap_err();
+        assert!(err.diagnostics().contains(&Diagnostic::SlotOverflow {
+            tileset: "Jump".to_string(),
+            slot: "Frames".to_string(),
+            requested: 3,
+            capacity: 2,
+        }));
+    }
+
+    #[test]
+    fn test_tile_upload_from_timer() {
+        let mut gb = RustBoy::new();
+        let slot = gb.add_tile_slot("Frames", TileBank::Sprite, 2);
+        let walk = gb
+            .tiles
+            .add_streamed("Walk", TileSource::Bytes(vec![[0; 16]; 2]));
+        let upload = gb.tiles.upload(walk, &slot);
+        gb.timers.every(30, upload);
+
+        let output = gb.try_build().unwrap().into_asm();
+        assert!(output.contains("call ProcessTileQueue"));
+        assert!(output.contains("wTileQueueCount: ds 1"));
+        assert!(output.contains("DEF TILE_QUEUE_SIZE EQU"));
+    }
+
+    #[test]
+    fn test_tile_upload_in_init() {
+        let mut gb = RustBoy::new();
+        let slot = gb.add_tile_slot("Frames", TileBank::Sprite, 2);
+        let walk = gb
+            .tiles
+            .add_streamed("Walk", TileSource::Bytes(vec![[0; 16]; 2]));
+        gb.init(gb.tiles.upload(walk, &slot));
+
+        let output = gb.try_build().unwrap().into_asm();
+        let init = &output[output.find("EntryPoint:").unwrap()..output.find("Main:").unwrap()];
+        let upload = init.find("call QueueTiles").unwrap();
+        // Queue and slot are emptied before the upload and never after it
+        assert!(init[..upload].contains("ld [wTileQueueCount], a"));
+        assert!(init[..upload].contains("ld [wFramesTileset + 1], a"));
+        assert!(!init[upload..].contains("ld [wTileQueueCount], a"));
+        assert!(init[upload..].contains("ld [wFramesTileset], a"));
+        assert_eq!(init.matches("ld [wFramesTileset], a").count(), 2);
+    }
+
+    #[test]
+    fn test_sprite_after_tile_slot() {
+        let mut gb = RustBoy::new();
+        gb.add_tile_slot("Frames", TileBank::Sprite, 4);
+        let hero = gb.add_sprite("Hero", TileSource::Bytes(vec![[0; 16]; 1]), 80, 72, 0);
+        let big = gb.add_sprite_16x16(
+            "Big",
+            TileSource::Bytes(vec![[0; 16]; 2]),
+            TileSource::Bytes(vec![[0; 16]; 2]),
+            40,
+            40,
+            0,
+        );
+
+        // OAM tile bytes follow the reserved slot, like the tile data
+        let hero_tiles = gb.sprites.get(hero).unwrap().tile_id;
+        assert_eq!(gb.tiles.get_address(hero_tiles), Some(0x8040));
+        assert_eq!(gb.sprites.get(hero).unwrap().tile_index, 4);
+        let halves = gb.sprites.get_composite_sprites(big).unwrap().clone();
+        assert_eq!(gb.sprites.get(halves[0]).unwrap().tile_index, 5);
+        assert_eq!(gb.sprites.get(halves[1]).unwrap().tile_index, 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "OAM exhausted")]
+    fn test_build_panics_on_overflow() {
+        let mut gb = RustBoy::new();
+
+        let tile = [["$00"; 8]; 1];
+        for i in 0..41 {
+            gb.add_sprite(&format!("S{}", i), TileSource::from_raw(&tile), 0, 0, 0);
+        }
+        gb.build();
+    }
 }
@@ -0,0 +1,511 @@
+//! Super Game Boy support: command packets and custom borders
+//!
+//! Once enabled with `SgbConfig::enable`, the header asks for SGB features
+//! and the console is detected at startup (stored in `wIsSgb`). Packets are
+//! built at build time, stored in ROM and sent with the `SgbTransfer`
+//! builtin, which does nothing on other consoles. Borders are sent through
+//! the screen with CHR_TRN and PCT_TRN while the game is still starting up.
+
+use std::cell::RefCell;
+use std::fmt;
+
+use super::cgb::{CgbPalette, Color};
+use crate::gb_asm::{Asm, Condition, Instr};
+
+/// Variable set to 1 at startup on a Super Game Boy, 0 otherwise
+pub(crate) const IS_SGB_VAR: &str = "wIsSgb";
+
+/// Most blocks a single ATTR_BLK command can hold
+pub const MAX_ATTR_BLOCKS: usize = 18;
+/// Most tiles in a border
+pub const MAX_BORDER_TILES: usize = 256;
+/// Most palettes in a border (SGB palettes 4 to 7)
+pub const MAX_BORDER_PALETTES: usize = 4;
+/// Border map size in tiles
+pub const BORDER_MAP_WIDTH: usize = 32;
+pub const BORDER_MAP_HEIGHT: usize = 28;
+
+/// SGB data that cannot be sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SgbError {
+    /// ATTR_BLK takes 1 to 18 blocks
+    AttrBlockCount(usize),
+    /// A border has at most 256 tiles
+    BorderTiles(usize),
+    /// A border has at most 4 palettes
+    BorderPalettes(usize),
+    /// A border map has exactly 32x28 entries
+    BorderMapSize(usize),
+}
+
+impl fmt::Display for SgbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SgbError::AttrBlockCount(count) => write!(
+                f,
+                "ATTR_BLK needs 1 to {} blocks, got {}",
+                MAX_ATTR_BLOCKS, count
+            ),
+            SgbError::BorderTiles(count) => write!(
+                f,
+                "border has {} tiles, the SGB takes at most {}",
+                count, MAX_BORDER_TILES
+            ),
+            SgbError::BorderPalettes(count) => write!(
+                f,
+                "border has {} palettes, the SGB takes at most {}",
+                count, MAX_BORDER_PALETTES
+            ),
+            SgbError::BorderMapSize(count) => write!(
+                f,
+                "border map has {} entries, expected {}",
+                count,
+                BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SgbError {}
+
+/// Pair of SGB palettes set by a PAL command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbPalettePair {
+    Pal01,
+    Pal23,
+    Pal03,
+    Pal12,
+}
+
+/// Screen masking set by MASK_EN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgbMask {
+    /// Show the game screen again
+    Cancel,
+    /// Keep showing the current picture
+    Freeze,
+    Black,
+    /// Fill the screen with color 0
+    Color0,
+}
+
+/// A rectangle of the screen colored by ATTR_BLK, in tiles (inclusive)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttrBlock {
+    pub x1: u8,
+    pub y1: u8,
+    pub x2: u8,
+    pub y2: u8,
+    /// Palettes (0-3) for the tiles inside, on the edge and outside of the
+    /// rectangle; `None` leaves them unchanged
+    pub inside: Option<u8>,
+    pub border: Option<u8>,
+    pub outside: Option<u8>,
+}
+
+impl AttrBlock {
+    /// Color a rectangle, edge included, with one palette
+    pub fn new(x1: u8, y1: u8, x2: u8, y2: u8, palette: u8) -> Self {
+        Self {
+            x1,
+            y1,
+            x2,
+            y2,
+            inside: Some(palette),
+            border: Some(palette),
+            outside: None,
+        }
+    }
+
+    fn bytes(&self) -> [u8; 6] {
+        let mut control = 0;
+        let mut palettes = 0;
+        for (bit, palette) in [self.inside, self.border, self.outside]
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(palette) = palette {
+                control |= 1 << bit;
+                palettes |= (palette & 0x03) << (bit * 2);
+            }
+        }
+        [
+            control,
+            palettes,
+            self.x1 & 0x1F,
+            self.y1 & 0x1F,
+            self.x2 & 0x1F,
+            self.y2 & 0x1F,
+        ]
+    }
+}
+
+/// A command for the SGB, made of one or more 16-byte packets
+///
+/// # Example
+/// ```ignore
+/// let colors = SgbPacket::pal(SgbPalettePair::Pal01, &sky, &ground);
+/// let hud = SgbPacket::attr_blk(&[AttrBlock::new(0, 16, 19, 17, 1)])?;
+/// gb.sgb.send_at_startup(colors).send_at_startup(hud);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SgbPacket(Vec<[u8; 16]>);
+
+impl SgbPacket {
+    /// Build a command: the first byte is `command * 8 + packet count`
+    fn new(command: u8, data: &[u8]) -> Self {
+        let count = (1 + data.len()).div_ceil(16);
+        let mut bytes = vec![(command << 3) | count as u8];
+        bytes.extend_from_slice(data);
+        bytes.resize(count * 16, 0);
+        SgbPacket(
+            bytes
+                .chunks(16)
+                .map(|chunk| chunk.try_into().expect("16 bytes"))
+                .collect(),
+        )
+    }
+
+    /// Set two SGB palettes; color 0 of `first` is shared by all palettes
+    pub fn pal(pair: SgbPalettePair, first: &CgbPalette, second: &CgbPalette) -> Self {
+        let command = match pair {
+            SgbPalettePair::Pal01 => 0x00,
+            SgbPalettePair::Pal23 => 0x01,
+            SgbPalettePair::Pal03 => 0x02,
+            SgbPalettePair::Pal12 => 0x03,
+        };
+        let colors = first.0.iter().chain(&second.0[1..]);
+        let data: Vec<u8> = colors.flat_map(|c| c.word().to_le_bytes()).collect();
+        Self::new(command, &data)
+    }
+
+    /// Color rectangles of the screen with SGB palettes 0-3
+    pub fn attr_blk(blocks: &[AttrBlock]) -> Result<Self, SgbError> {
+        if blocks.is_empty() || blocks.len() > MAX_ATTR_BLOCKS {
+            return Err(SgbError::AttrBlockCount(blocks.len()));
+        }
+        let mut data = vec![blocks.len() as u8];
+        data.extend(blocks.iter().flat_map(AttrBlock::bytes));
+        Ok(Self::new(0x04, &data))
+    }
+
+    /// Hide the game screen, e.g. while a border is sent
+    pub fn mask_en(mask: SgbMask) -> Self {
+        let mode = match mask {
+            SgbMask::Cancel => 0,
+            SgbMask::Freeze => 1,
+            SgbMask::Black => 2,
+            SgbMask::Color0 => 3,
+        };
+        Self::new(0x17, &[mode])
+    }
+
+    /// Receive 128 border tiles from the screen (`upper`: tiles $80-$FF)
+    pub fn chr_trn(upper: bool) -> Self {
+        Self::new(0x13, &[upper as u8])
+    }
+
+    /// Receive the border map and palettes from the screen
+    pub fn pct_trn() -> Self {
+        Self::new(0x14, &[])
+    }
+
+    pub fn packets(&self) -> &[[u8; 16]] {
+        &self.0
+    }
+}
+
+/// A custom border: SNES 4bpp tiles, a 32x28 map and up to 4 palettes
+///
+/// The game screen covers the middle of the map (columns 6-25, rows
+/// 5-22), which should use transparent tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SgbBorder {
+    tiles: Vec<[u8; 32]>,
+    map: Vec<u16>,
+    palettes: Vec<[Color; 16]>,
+}
+
+impl SgbBorder {
+    /// Create a border; `map` holds `map_entry` values, row by row
+    pub fn new(
+        tiles: Vec<[u8; 32]>,
+        map: Vec<u16>,
+        palettes: Vec<[Color; 16]>,
+    ) -> Result<Self, SgbError> {
+        if tiles.len() > MAX_BORDER_TILES {
+            return Err(SgbError::BorderTiles(tiles.len()));
+        }
+        if palettes.len() > MAX_BORDER_PALETTES {
+            return Err(SgbError::BorderPalettes(palettes.len()));
+        }
+        if map.len() != BORDER_MAP_WIDTH * BORDER_MAP_HEIGHT {
+            return Err(SgbError::BorderMapSize(map.len()));
+        }
+        Ok(Self {
+            tiles,
+            map,
+            palettes,
+        })
+    }
+
+    /// Map entry for a tile drawn with border palette 0-3
+    pub fn map_entry(tile: u8, palette: u8, flip_x: bool, flip_y: bool) -> u16 {
+        // Border palettes are SGB palettes 4-7
+        tile as u16
+            | ((4 + (palette & 0x03)) as u16) << 10
+            | (flip_x as u16) << 14
+            | (flip_y as u16) << 15
+    }
+
+    /// Convert 8x8 color indices (0-15) to a SNES 4bpp tile
+    pub fn tile_from_pixels(pixels: &[[u8; 8]; 8]) -> [u8; 32] {
+        let mut tile = [0; 32];
+        for (y, row) in pixels.iter().enumerate() {
+            for (x, &color) in row.iter().enumerate() {
+                let bit = 7 - x;
+                for plane in 0..4 {
+                    if color & (1 << plane) != 0 {
+                        // Planes 0-1 interleaved first, then planes 2-3
+                        let index = (plane / 2) * 16 + y * 2 + plane % 2;
+                        tile[index] |= 1 << bit;
+                    }
+                }
+            }
+        }
+        tile
+    }
+}
+
+/// SGB settings, packets and border, reached through `RustBoy::sgb`
+///
+/// # Example
+/// ```ignore
+/// gb.sgb.enable();
+/// gb.sgb.set_border(border);
+/// gb.add_to_main_loop(gb.sgb.send(&SgbPacket::mask_en(SgbMask::Black)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SgbConfig {
+    enabled: bool,
+    startup: Vec<SgbPacket>,
+    border: Option<SgbBorder>,
+    // Packets sent by the program, stored once in ROM. Helpers only
+    // borrow the config.
+    packets: RefCell<Vec<SgbPacket>>,
+}
+
+impl SgbConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Turn SGB support on
+    pub fn enable(&mut self) -> &mut Self {
+        self.enabled = true;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Send a packet at startup, after the border
+    pub fn send_at_startup(&mut self, packet: SgbPacket) -> &mut Self {
+        self.startup.push(packet);
+        self
+    }
+
+    /// Send a custom border at startup
+    pub fn set_border(&mut self, border: SgbBorder) -> &mut Self {
+        self.border = Some(border);
+        self
+    }
+
+    /// Whether startup packets or a border were added
+    pub(crate) fn has_data(&self) -> bool {
+        !self.startup.is_empty() || self.border.is_some()
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Send a packet (takes about 4 frames on a SGB, does nothing elsewhere)
+    pub fn send(&self, packet: &SgbPacket) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_hl_label(&self.packet_label(packet))
+            .call("SgbTransfer");
+        asm.get_main_instrs()
+    }
+
+    /// Clear the Z flag on a Super Game Boy, set it elsewhere
+    pub fn is_sgb(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(IS_SGB_VAR).or_label("a", "a");
+        asm.get_main_instrs()
+    }
+
+    /// Label of a stored packet, adding it on first use
+    fn packet_label(&self, packet: &SgbPacket) -> String {
+        let mut packets = self.packets.borrow_mut();
+        let index = match packets.iter().position(|p| p == packet) {
+            Some(index) => index,
+            None => {
+                packets.push(packet.clone());
+                packets.len() - 1
+            }
+        };
+        format!("SgbPacket{}", index)
+    }
+
+    // ============================================
+    // Build-time generation
+    // ============================================
+
+    /// Store the console type, must run at EntryPoint (reads `c`)
+    pub(crate) fn generate_detect_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        // The SGB boot ROM leaves $14 (20) in c
+        asm.ld_a_label("c")
+            .cp_imm(0x14)
+            .ld_a(0)
+            .jr_cond(Condition::NZ, "EntryPointNoSgb")
+            .inc_label("a");
+        asm.label("EntryPointNoSgb");
+        asm.ld_addr_def_a(IS_SGB_VAR);
+        asm.get_main_instrs()
+    }
+
+    /// Send the border and startup packets, with the screen off
+    ///
+    /// The border transfer overwrites $8000-$8FFF and the $9800 map, so this
+    /// runs before the tiles are loaded.
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        if let Some(border) = &self.border {
+            asm.emit_all(self.send(&SgbPacket::mask_en(SgbMask::Freeze)));
+            let mut transfers = vec![(SgbPacket::chr_trn(false), "SgbBorderTiles")];
+            if border.tiles.len() > MAX_BORDER_TILES / 2 {
+                transfers.push((SgbPacket::chr_trn(true), "SgbBorderTiles + $1000"));
+            }
+            transfers.push((SgbPacket::pct_trn(), "SgbBorderMap"));
+            for (packet, source) in transfers {
+                asm.ld_de_label(source)
+                    .ld_hl_label(&self.packet_label(&packet))
+                    .call("SgbVramTransfer");
+            }
+            asm.emit_all(self.send(&SgbPacket::mask_en(SgbMask::Cancel)));
+        }
+        for packet in &self.startup {
+            asm.emit_all(self.send(packet));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Packets and border data for a ROM chunk
+    pub(crate) fn generate_data(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (i, packet) in self.packets.borrow().iter().enumerate() {
+            asm.label(&format!("SgbPacket{}", i));
+            for bytes in packet.packets() {
+                asm.db(&hex_bytes(bytes));
+            }
+        }
+
+        if let Some(border) = &self.border {
+            asm.label("SgbBorderTiles");
+            for tile in &border.tiles {
+                for half in tile.chunks(16) {
+                    asm.db(&hex_bytes(half));
+                }
+            }
+            // PCT_TRN data: the map, then the palettes at $800
+            asm.label("SgbBorderMap");
+            for row in border.map.chunks(BORDER_MAP_WIDTH / 2) {
+                let entries: Vec<String> = row.iter().map(|e| format!("${:04X}", e)).collect();
+                asm.dw(&entries.join(", "));
+            }
+            asm.ds("$800 - (@ - SgbBorderMap)", "0");
+            for palette in &border.palettes {
+                for colors in palette.chunks(8) {
+                    let words: Vec<String> = colors
+                        .iter()
+                        .map(|c| format!("${:04X}", c.word()))
+                        .collect();
+                    asm.dw(&words.join(", "));
+                }
+            }
+        }
+        asm.get_main_instrs()
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+    values.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packets() {
+        let mask = SgbPacket::mask_en(SgbMask::Freeze);
+        assert_eq!(mask.packets().len(), 1);
+        assert_eq!(&mask.packets()[0][..2], &[0xB9, 0x01]);
+
+        let pal = SgbPacket::pal(
+            SgbPalettePair::Pal01,
+            &CgbPalette::GRAYSCALE,
+            &CgbPalette::new([Color::BLACK; 4]),
+        );
+        assert_eq!(&pal.packets()[0][..5], &[0x01, 0xFF, 0x7F, 0xB5, 0x56]);
+        assert_eq!(pal.packets()[0][15], 0);
+
+        // 2 + 6 * 3 bytes: two packets
+        let blocks = [AttrBlock::new(0, 0, 19, 17, 2); 3];
+        let attr = SgbPacket::attr_blk(&blocks).unwrap();
+        assert_eq!(attr.packets().len(), 2);
+        assert_eq!(
+            &attr.packets()[0][..8],
+            &[0x22, 3, 0x03, 0x0A, 0, 0, 19, 17]
+        );
+        assert_eq!(SgbPacket::attr_blk(&[]), Err(SgbError::AttrBlockCount(0)));
+    }
+
+    #[test]
+    fn test_border() {
+        let mut pixels = [[0; 8]; 8];
+        pixels[0][0] = 0b1111;
+        pixels[1][7] = 0b0100;
+        let tile = SgbBorder::tile_from_pixels(&pixels);
+        assert_eq!(&tile[..4], &[0x80, 0x80, 0, 0]);
+        assert_eq!(&tile[16..20], &[0x80, 0x80, 0x01, 0]);
+        assert_eq!(SgbBorder::map_entry(3, 1, true, false), 0x5403);
+
+        assert_eq!(
+            SgbBorder::new(vec![tile], vec![0; 10], Vec::new()),
+            Err(SgbError::BorderMapSize(10))
+        );
+
+        let border = SgbBorder::new(vec![tile; 200], vec![0; 32 * 28], Vec::new()).unwrap();
+        let mut sgb = SgbConfig::new();
+        sgb.enable().set_border(border);
+        let init: Vec<String> = sgb
+            .generate_init_code()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(init.contains(&"ld de, SgbBorderTiles + $1000".to_string()));
+        assert_eq!(
+            init.iter().filter(|l| *l == "call SgbVramTransfer").count(),
+            3
+        );
+
+        let data: Vec<String> = sgb.generate_data().iter().map(|i| i.to_string()).collect();
+        assert!(data.contains(&"SgbPacket4:".to_string()));
+        assert!(data.contains(&"ds $800 - (@ - SgbBorderMap), 0".to_string()));
+    }
+}
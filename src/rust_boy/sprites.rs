@@ -1,7 +1,11 @@
 //! Sprite management with automatic tile allocation and OAM handling
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 
+use super::error::Diagnostic;
+use super::labels::LabelCounter;
+use super::memory::{MemoryAllocator, MemoryError, MemoryRegion, OAM_ENTRY_BYTES, RegionUsage};
 use super::tiles::TileId;
 use crate::{
     gb_asm::{Asm, Condition, Instr, Operand, Register},
@@ -19,6 +23,7 @@ pub struct CompositeSpriteId(pub(crate) usize);
 /// A composite sprite made of multiple hardware sprites that move together
 #[derive(Debug, Clone)]
 pub(crate) struct CompositeSpriteData {
+    #[allow(dead_code)]
     pub name: String,
     /// The individual sprite IDs that make up this composite
     pub sprites: Vec<SpriteId>,
@@ -43,43 +48,131 @@ pub(crate) struct SpriteData {
     pub initial_animation: u8, // Index of initially active animation, or ANIM_DISABLED
 }
 
-/// Manages sprites with automatic tile allocation and OAM handling
+/// Manages sprites and their OAM entries; tiles are allocated by `TileManager`
 #[derive(Debug)]
 pub struct SpriteManager {
     sprites: HashMap<SpriteId, SpriteData>,
     composite_sprites: HashMap<CompositeSpriteId, CompositeSpriteData>,
     next_id: usize,
     next_composite_id: usize,
-    next_oam_index: u8,
-    next_tile_index: u8,
+    // One 4-byte entry per hardware sprite
+    oam_alloc: MemoryAllocator,
+    // Allocations that did not fit, reported at build time
+    errors: Vec<MemoryError>,
+    // Unknown sprite IDs passed to helpers, reported at build time.
+    // Helpers only borrow the manager so they can be used as arguments
+    // to `RustBoy` methods, hence the interior mutability.
+    missing: RefCell<Vec<Diagnostic>>,
+    // Shared with RustBoy for unique jump labels
+    labels: LabelCounter,
 }
 
 impl SpriteManager {
+    #[cfg(test)]
     pub(crate) fn new() -> Self {
+        Self::with_labels(LabelCounter::new())
+    }
+
+    /// Create a manager that draws its labels from a shared counter
+    pub(crate) fn with_labels(labels: LabelCounter) -> Self {
         Self {
             sprites: HashMap::new(),
             composite_sprites: HashMap::new(),
             next_id: 0,
             next_composite_id: 0,
-            next_oam_index: 0,
-            next_tile_index: 0,
+            oam_alloc: MemoryAllocator::new(MemoryRegion::Oam),
+            errors: Vec::new(),
+            missing: RefCell::new(Vec::new()),
+            labels,
+        }
+    }
+
+    /// Find a sprite, recording a diagnostic if the ID is unknown
+    pub(crate) fn lookup(&self, id: SpriteId, context: &str) -> Option<&SpriteData> {
+        let sprite = self.get(id);
+        if sprite.is_none() {
+            self.record_missing(Diagnostic::MissingSprite {
+                sprite: id,
+                context: context.to_string(),
+            });
+        }
+        sprite
+    }
+
+    /// Find a sprite for modification, recording a diagnostic if the ID is unknown
+    fn lookup_mut(&mut self, id: SpriteId, context: &str) -> Option<&mut SpriteData> {
+        let sprite = self.sprites.get_mut(&id);
+        if sprite.is_none() {
+            self.missing.borrow_mut().push(Diagnostic::MissingSprite {
+                sprite: id,
+                context: context.to_string(),
+            });
+        }
+        sprite
+    }
+
+    /// Find a composite sprite, recording a diagnostic if the ID is unknown
+    fn lookup_composite(
+        &self,
+        id: CompositeSpriteId,
+        context: &str,
+    ) -> Option<&CompositeSpriteData> {
+        let composite = self.composite_sprites.get(&id);
+        if composite.is_none() {
+            self.record_missing(Diagnostic::MissingCompositeSprite {
+                sprite: id,
+                context: context.to_string(),
+            });
         }
+        composite
+    }
+
+    /// Find a composite sprite for modification, recording a diagnostic if the ID is unknown
+    fn lookup_composite_mut(
+        &mut self,
+        id: CompositeSpriteId,
+        context: &str,
+    ) -> Option<&mut CompositeSpriteData> {
+        let composite = self.composite_sprites.get_mut(&id);
+        if composite.is_none() {
+            self.missing
+                .borrow_mut()
+                .push(Diagnostic::MissingCompositeSprite {
+                    sprite: id,
+                    context: context.to_string(),
+                });
+        }
+        composite
+    }
+
+    fn record_missing(&self, diagnostic: Diagnostic) {
+        self.missing.borrow_mut().push(diagnostic);
     }
 
     /// Add a new sprite with tile data and initial position
     /// Returns both the sprite ID and tile ID for reference
-    /// `tile_count` is the number of tiles this sprite uses (for proper tile index allocation)
-    pub fn add(&mut self, name: &str, x: u8, y: u8, flags: u8, tile_count: u8) -> SpriteId {
-        let tile_index = self.next_tile_index;
-        let oam_index = self.next_oam_index;
+    /// `tile_index` is the sprite's first tile in the $8000 bank, as given
+    /// by `TileManager::tile_index` for its tiles
+    ///
+    /// Running out of OAM entries is recorded and reported when the
+    /// program is built.
+    pub fn add(&mut self, name: &str, x: u8, y: u8, flags: u8, tile_index: u8) -> SpriteId {
+        let oam_addr = match self.oam_alloc.allocate(OAM_ENTRY_BYTES) {
+            Some(addr) => addr,
+            None => {
+                self.errors.push(MemoryError::OamExhausted {
+                    name: name.to_string(),
+                });
+                self.oam_alloc.current_address() - OAM_ENTRY_BYTES
+            }
+        };
+        let oam_index = ((oam_addr - self.oam_alloc.start_address()) / OAM_ENTRY_BYTES) as u8;
 
         // We'll use a placeholder TileId - the actual tile ID will be set by RustBoy
         let tile_id = TileId(usize::MAX);
 
         let id = SpriteId(self.next_id);
         self.next_id += 1;
-        self.next_oam_index += 1;
-        self.next_tile_index += tile_count;
 
         self.sprites.insert(
             id,
@@ -107,7 +200,7 @@ impl SpriteManager {
     }
 
     /// Get sprite data
-    pub fn get(&self, id: SpriteId) -> Option<&SpriteData> {
+    pub(crate) fn get(&self, id: SpriteId) -> Option<&SpriteData> {
         self.sprites.get(&id)
     }
 
@@ -116,6 +209,7 @@ impl SpriteManager {
     /// - `start_frame`: Relative start frame index (e.g., 0)
     /// - `end_frame`: Relative end frame index (e.g., 6)
     /// - `anim_type`: Type of animation (Loop, PingPong, Once)
+    ///
     /// Returns the animation index within this sprite
     pub fn add_animation(
         &mut self,
@@ -140,7 +234,7 @@ impl SpriteManager {
         anim_type: super::animations::AnimationType,
         frame_step: u8,
     ) -> u8 {
-        if let Some(sprite) = self.sprites.get_mut(&sprite_id) {
+        if let Some(sprite) = self.lookup_mut(sprite_id, "add_animation_with_step") {
             let index = sprite.animations.len() as u8;
             let animation = Animation {
                 name: name.to_string(),
@@ -162,14 +256,14 @@ impl SpriteManager {
     /// Set the initial animation for a sprite by animation index
     /// Use ANIM_DISABLED (255) to start with no animation
     pub fn set_initial_animation(&mut self, sprite_id: SpriteId, animation_index: u8) {
-        if let Some(sprite) = self.sprites.get_mut(&sprite_id) {
+        if let Some(sprite) = self.lookup_mut(sprite_id, "set_initial_animation") {
             sprite.initial_animation = animation_index;
         }
     }
 
     /// Set the initial animation for a sprite by animation name
     pub fn set_initial_animation_by_name(&mut self, sprite_id: SpriteId, name: &str) {
-        if let Some(sprite) = self.sprites.get_mut(&sprite_id) {
+        if let Some(sprite) = self.lookup_mut(sprite_id, "set_initial_animation_by_name") {
             for (i, anim) in sprite.animations.iter().enumerate() {
                 if anim.name == name {
                     sprite.initial_animation = i as u8;
@@ -184,7 +278,7 @@ impl SpriteManager {
     pub fn enable_animation(&self, sprite_id: SpriteId, animation_index: u8) -> Vec<Instr> {
         let mut asm = Asm::new();
 
-        if let Some(sprite) = self.sprites.get(&sprite_id) {
+        if let Some(sprite) = self.lookup(sprite_id, "enable_animation") {
             let var_name = format!("wAnim_{}_Current", sprite.name);
             asm.ld_a(animation_index);
             asm.ld_addr_def_a(&var_name);
@@ -195,7 +289,7 @@ impl SpriteManager {
 
     /// Generate code to enable an animation by name for a sprite
     pub fn enable_animation_by_name(&self, sprite_id: SpriteId, name: &str) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&sprite_id) {
+        if let Some(sprite) = self.lookup(sprite_id, "enable_animation_by_name") {
             for (i, anim) in sprite.animations.iter().enumerate() {
                 if anim.name == name {
                     return self.enable_animation(sprite_id, i as u8);
@@ -210,7 +304,7 @@ impl SpriteManager {
     pub fn disable_animation(&self, sprite_id: SpriteId) -> Vec<Instr> {
         let mut asm = Asm::new();
 
-        if let Some(sprite) = self.sprites.get(&sprite_id) {
+        if let Some(sprite) = self.lookup(sprite_id, "disable_animation") {
             let var_name = format!("wAnim_{}_Current", sprite.name);
             asm.ld_a(ANIM_DISABLED);
             asm.ld_addr_def_a(&var_name);
@@ -262,7 +356,8 @@ impl SpriteManager {
     ) -> u8 {
         let mut anim_index = 0u8;
 
-        if let Some(composite) = self.composite_sprites.get_mut(&composite_id) {
+        if let Some(composite) = self.lookup_composite_mut(composite_id, "add_composite_animation")
+        {
             let sprite_ids = composite.sprites.clone();
             composite.animation_names.push(name.to_string());
 
@@ -289,7 +384,9 @@ impl SpriteManager {
         composite_id: CompositeSpriteId,
         animation_index: u8,
     ) {
-        if let Some(composite) = self.composite_sprites.get(&composite_id) {
+        if let Some(composite) =
+            self.lookup_composite(composite_id, "set_composite_initial_animation")
+        {
             let sprite_ids = composite.sprites.clone();
             for sprite_id in sprite_ids {
                 self.set_initial_animation(sprite_id, animation_index);
@@ -306,7 +403,7 @@ impl SpriteManager {
     ) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&composite_id) {
+        if let Some(composite) = self.lookup_composite(composite_id, "enable_composite_animation") {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.enable_animation(*sprite_id, animation_index));
             }
@@ -320,7 +417,8 @@ impl SpriteManager {
     pub fn disable_composite_animation(&self, composite_id: CompositeSpriteId) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&composite_id) {
+        if let Some(composite) = self.lookup_composite(composite_id, "disable_composite_animation")
+        {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.disable_animation(*sprite_id));
             }
@@ -338,7 +436,7 @@ impl SpriteManager {
     ) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&id) {
+        if let Some(composite) = self.lookup_composite(id, "move_composite_left_limit") {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.move_left_limit(*sprite_id, distance, limit));
             }
@@ -356,7 +454,7 @@ impl SpriteManager {
     ) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&id) {
+        if let Some(composite) = self.lookup_composite(id, "move_composite_right_limit") {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.move_right_limit(*sprite_id, distance, limit));
             }
@@ -374,7 +472,7 @@ impl SpriteManager {
     ) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&id) {
+        if let Some(composite) = self.lookup_composite(id, "move_composite_up_limit") {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.move_up_limit(*sprite_id, distance, limit));
             }
@@ -392,7 +490,7 @@ impl SpriteManager {
     ) -> Vec<Instr> {
         let mut instrs = Vec::new();
 
-        if let Some(composite) = self.composite_sprites.get(&id) {
+        if let Some(composite) = self.lookup_composite(id, "move_composite_down_limit") {
             for sprite_id in &composite.sprites {
                 instrs.extend(self.move_down_limit(*sprite_id, distance, limit));
             }
@@ -411,10 +509,11 @@ impl SpriteManager {
         asm.ld_hl_label("_OAMRAM");
 
         // Clear OAM loop
-        asm.label("ClearOam");
+        let clear_label = self.labels.unique("ClearOam");
+        asm.label(&clear_label);
         asm.ld_hli_label("a");
         asm.dec_label("b");
-        asm.jp_cond(Condition::NZ, "ClearOam");
+        asm.jp_cond(Condition::NZ, &clear_label);
 
         // Draw all sprites to OAM (sorted by oam_index to ensure correct order)
         asm.ld_hl_label("_OAMRAM");
@@ -443,7 +542,7 @@ impl SpriteManager {
 
     /// Generate movement code for a specific sprite
     pub fn move_x_var(&self, id: SpriteId, var_name: &str) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_x_var") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4 + 1;
 
@@ -461,7 +560,7 @@ impl SpriteManager {
 
     /// Generate movement code for Y axis with variable
     pub fn move_y_var(&self, id: SpriteId, var_name: &str) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_y_var") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4;
 
@@ -479,10 +578,12 @@ impl SpriteManager {
 
     /// Move sprite left with limit
     pub fn move_left_limit(&self, id: SpriteId, distance: u8, limit: u8) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_left_limit") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4 + 1;
-            let jump_label = format!("Sprite{}LeftLimitEnd", sprite.oam_index);
+            let jump_label = self
+                .labels
+                .unique(&format!("Sprite{}LeftLimitEnd", sprite.oam_index));
 
             asm.ld_a_addr_def(&format!("_OAMRAM+{}", oam_offset));
             asm.sub(Operand::Reg(Register::A), Operand::Imm(distance));
@@ -499,10 +600,12 @@ impl SpriteManager {
 
     /// Move sprite right with limit
     pub fn move_right_limit(&self, id: SpriteId, distance: u8, limit: u8) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_right_limit") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4 + 1;
-            let jump_label = format!("Sprite{}RightLimitEnd", sprite.oam_index);
+            let jump_label = self
+                .labels
+                .unique(&format!("Sprite{}RightLimitEnd", sprite.oam_index));
 
             asm.ld_a_addr_def(&format!("_OAMRAM+{}", oam_offset));
             asm.add(Operand::Reg(Register::A), Operand::Imm(distance));
@@ -519,10 +622,12 @@ impl SpriteManager {
 
     /// Move sprite up with limit
     pub fn move_up_limit(&self, id: SpriteId, distance: u8, limit: u8) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_up_limit") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4;
-            let jump_label = format!("Sprite{}UpLimitEnd", sprite.oam_index);
+            let jump_label = self
+                .labels
+                .unique(&format!("Sprite{}UpLimitEnd", sprite.oam_index));
 
             asm.ld_a_addr_def(&format!("_OAMRAM+{}", oam_offset));
             asm.sub(Operand::Reg(Register::A), Operand::Imm(distance));
@@ -539,10 +644,12 @@ impl SpriteManager {
 
     /// Move sprite down with limit
     pub fn move_down_limit(&self, id: SpriteId, distance: u8, limit: u8) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "move_down_limit") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4;
-            let jump_label = format!("Sprite{}DownLimitEnd", sprite.oam_index);
+            let jump_label = self
+                .labels
+                .unique(&format!("Sprite{}DownLimitEnd", sprite.oam_index));
 
             asm.ld_a_addr_def(&format!("_OAMRAM+{}", oam_offset));
             asm.add(Operand::Reg(Register::A), Operand::Imm(distance));
@@ -559,7 +666,7 @@ impl SpriteManager {
 
     /// Get sprite pivot point (for collision detection)
     pub fn get_pivot(&self, id: SpriteId, x_offset: i16, y_offset: i16) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "get_pivot") {
             let mut asm = Asm::new();
             let oam_y_offset = sprite.oam_index * 4;
             let oam_x_offset = sprite.oam_index * 4 + 1;
@@ -585,7 +692,7 @@ impl SpriteManager {
 
     /// Get sprite Y position
     pub fn get_y(&self, id: SpriteId) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "get_y") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4;
 
@@ -599,7 +706,7 @@ impl SpriteManager {
 
     /// Get sprite X position
     pub fn get_x(&self, id: SpriteId) -> Vec<Instr> {
-        if let Some(sprite) = self.sprites.get(&id) {
+        if let Some(sprite) = self.lookup(id, "get_x") {
             let mut asm = Asm::new();
             let oam_offset = sprite.oam_index * 4 + 1;
 
@@ -611,6 +718,24 @@ impl SpriteManager {
         }
     }
 
+    /// Get the number of OAM entries in use
+    pub fn oam_usage(&self) -> RegionUsage {
+        RegionUsage {
+            used: self.oam_alloc.bytes_allocated() / OAM_ENTRY_BYTES,
+            capacity: self.oam_alloc.capacity() / OAM_ENTRY_BYTES,
+        }
+    }
+
+    /// Allocation errors collected while adding sprites
+    pub(crate) fn errors(&self) -> &[MemoryError] {
+        &self.errors
+    }
+
+    /// Unknown sprite IDs that were passed to sprite helpers
+    pub(crate) fn missing_sprites(&self) -> Vec<Diagnostic> {
+        self.missing.borrow().clone()
+    }
+
     /// Check if any sprites have been added
     pub fn is_empty(&self) -> bool {
         self.sprites.is_empty()
@@ -748,4 +873,53 @@ mod tests {
         assert_eq!(sm.get(paddle).unwrap().oam_index, 0);
         assert_eq!(sm.get(ball).unwrap().oam_index, 1);
     }
+
+    #[test]
+    fn test_limit_labels_are_unique() {
+        let mut sm = SpriteManager::new();
+        let paddle = sm.add("Paddle", 16, 128, 0, 1);
+
+        let mut code = sm.move_left_limit(paddle, 1, 15);
+        code.extend(sm.move_left_limit(paddle, 2, 15));
+
+        let errors = crate::gb_asm::SymbolTable::from_instrs(&code).check();
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_missing_sprite_recorded() {
+        let mut sm = SpriteManager::new();
+        let mut other = SpriteManager::new();
+        other.add("Paddle", 0, 0, 0, 1);
+        let foreign = other.add("Ball", 0, 0, 0, 1);
+
+        sm.add("Paddle", 0, 0, 0, 1);
+        assert!(sm.get_x(foreign).is_empty());
+        assert_eq!(
+            sm.missing_sprites(),
+            vec![Diagnostic::MissingSprite {
+                sprite: foreign,
+                context: "get_x".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_oam_exhausted() {
+        let mut sm = SpriteManager::new();
+
+        for i in 0..40 {
+            sm.add(&format!("Sprite{}", i), 0, 0, 0, 1);
+        }
+        assert!(sm.errors().is_empty());
+        assert_eq!(sm.oam_usage().used, 40);
+
+        sm.add("Extra", 0, 0, 0, 1);
+        assert_eq!(
+            sm.errors(),
+            &[MemoryError::OamExhausted {
+                name: "Extra".to_string()
+            }]
+        );
+    }
 }
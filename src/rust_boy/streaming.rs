@@ -0,0 +1,445 @@
+//! Maps larger than the 32x32 background, streamed as the camera moves
+//!
+//! The whole map is stored in ROM, row by row, together with a table of row
+//! pointers. The background map is used as a ring buffer: each frame, during
+//! VBlank, the column or row that the camera just exposed is copied to it.
+
+use super::camera::Camera;
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+
+/// Largest map side, in tiles (the camera tile position is kept in one byte)
+pub const MAX_STREAMED_MAP_SIZE: usize = 256;
+
+// Columns and rows touched by the screen when not aligned on a tile
+const VISIBLE_COLUMNS: u8 = 21;
+const VISIBLE_ROWS: u8 = 19;
+
+/// A tilemap of any size up to 256x256 tiles, stored in ROM
+///
+/// Register it with `RustBoy::add_streamed_map`; the camera then decides
+/// which part of the map is visible. One column and one row are streamed per
+/// frame, so the camera must not move more than 8 pixels per frame on each
+/// axis, and must stay inside the map.
+///
+/// # Example
+/// ```ignore
+/// let level = StreamedMap::from_rows("Level1", &rows); // e.g. 128x18 tiles
+/// gb.add_streamed_map(level);
+/// let camera = gb.add_camera(0, 0);
+/// gb.add_to_main_loop(camera.follow_sprite(&gb.sprites, player, 64, 40));
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamedMap {
+    name: String,
+    width: usize,
+    height: usize,
+    /// Tile IDs, row-major
+    data: Vec<u8>,
+}
+
+impl StreamedMap {
+    /// Create a map from row-major tile IDs
+    pub fn new(name: &str, width: usize, height: usize, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Create a map from its rows (all rows must have the same length)
+    pub fn from_rows<R: AsRef<[u8]>>(name: &str, rows: &[R]) -> Self {
+        let width = rows.first().map_or(0, |row| row.as_ref().len());
+        let mut data = Vec::with_capacity(width * rows.len());
+        for row in rows {
+            data.extend_from_slice(row.as_ref());
+        }
+        Self::new(name, width, rows.len(), data)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Width in tiles
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height in tiles
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Size of the map and its row table in ROM
+    pub fn size_bytes(&self) -> usize {
+        self.data.len() + self.height * 2
+    }
+
+    /// Describe why the map cannot be streamed, if it cannot
+    pub(crate) fn validate(&self) -> Option<String> {
+        if self.width == 0 || self.height == 0 {
+            return Some("map is empty".to_string());
+        }
+        if self.width > MAX_STREAMED_MAP_SIZE || self.height > MAX_STREAMED_MAP_SIZE {
+            return Some(format!(
+                "{}x{} tiles is larger than {}x{}",
+                self.width, self.height, MAX_STREAMED_MAP_SIZE, MAX_STREAMED_MAP_SIZE
+            ));
+        }
+        if self.data.len() != self.width * self.height {
+            return Some(format!(
+                "expected {} tiles for {}x{}, got {}",
+                self.width * self.height,
+                self.width,
+                self.height,
+                self.data.len()
+            ));
+        }
+        None
+    }
+
+    /// Variable holding the camera column the ring buffer was filled for
+    pub(crate) fn column_var(&self) -> String {
+        format!("w{}Column", self.name)
+    }
+
+    /// Variable holding the camera row the ring buffer was filled for
+    pub(crate) fn row_var(&self) -> String {
+        format!("w{}Row", self.name)
+    }
+
+    /// Map data and row pointer table for the Tilemap chunk
+    pub(crate) fn generate_data(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+
+        asm.label(&self.name);
+        for row in self.data.chunks(self.width.max(1)) {
+            let values: Vec<String> = row.iter().map(|&val| format!("${:02X}", val)).collect();
+            asm.db(&values.join(", "));
+        }
+        asm.label(&format!("{}End", self.name));
+
+        asm.label(&format!("{}Rows", self.name));
+        for y in 0..self.height {
+            asm.dw(&format!("{} + {}", self.name, y * self.width));
+        }
+
+        asm.get_main_instrs()
+    }
+
+    /// Fill the visible part of the background map (screen must be off)
+    pub(crate) fn generate_init_code(&self, camera: &Camera) -> Vec<Instr> {
+        let mut asm = Asm::new();
+
+        asm.emit_all(camera_tile(camera.x().name()));
+        asm.ld_addr_def_a(&self.column_var());
+        asm.emit_all(camera_tile(camera.y().name()));
+        asm.ld_addr_def_a(&self.row_var());
+
+        for i in 0..VISIBLE_COLUMNS {
+            asm.ld_a_addr_def(&self.column_var());
+            if i > 0 {
+                asm.add(Operand::Reg(Register::A), Operand::Imm(i));
+            }
+            asm.call(&format!("{}LoadColumn", self.name));
+        }
+
+        asm.get_main_instrs()
+    }
+
+    /// Per-frame streaming call, emitted right after VBlank
+    pub(crate) fn generate_update_call(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.call(&format!("{}Stream", self.name));
+        asm.get_main_instrs()
+    }
+
+    /// Streaming routines: `{Name}Stream`, `{Name}LoadColumn` and `{Name}LoadRow`
+    pub(crate) fn generate_functions(&self, camera: &Camera, map_base: u16) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.emit_all(self.generate_stream(camera));
+        asm.emit_all(self.generate_load_column(camera, map_base));
+        asm.emit_all(self.generate_load_row(camera, map_base));
+        asm.get_main_instrs()
+    }
+
+    fn generate_stream(&self, camera: &Camera) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.comment("Copy the column or row exposed by the camera to the background map");
+        asm.label(&format!("{}Stream", self.name));
+
+        let axes = [
+            (
+                camera.x().name(),
+                self.column_var(),
+                VISIBLE_COLUMNS - 1,
+                format!("{}LoadColumn", self.name),
+                ".rows",
+            ),
+            (
+                camera.y().name(),
+                self.row_var(),
+                VISIBLE_ROWS - 1,
+                format!("{}LoadRow", self.name),
+                ".done",
+            ),
+        ];
+        for (i, (camera_var, last_var, far_edge, load, next)) in axes.iter().enumerate() {
+            let forward = format!(".forward{}", i);
+            let load_label = format!(".load{}", i);
+
+            asm.emit_all(camera_tile(camera_var));
+            asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+            asm.ld_a_addr_def(last_var);
+            asm.cp(Operand::Reg(Register::B));
+            asm.jr_cond(Condition::Z, next);
+            // Flags survive the loads: carry means the camera moved forward
+            asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::B));
+            asm.ld_addr_def_a(last_var);
+            asm.jr_cond(Condition::C, &forward);
+            asm.jr(&load_label);
+            asm.label(&forward);
+            asm.add(Operand::Reg(Register::A), Operand::Imm(*far_edge));
+            asm.label(&load_label);
+            asm.call(load);
+            asm.label(next);
+        }
+        asm.ret();
+
+        asm.get_main_instrs()
+    }
+
+    /// Copy map column `a` (visible rows only) to the background map
+    fn generate_load_column(&self, camera: &Camera, map_base: u16) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.comment("Copy a map column to the background map");
+        asm.comment("@param a: map column");
+        asm.label(&format!("{}LoadColumn", self.name));
+
+        if self.width < MAX_STREAMED_MAP_SIZE {
+            asm.cp(Operand::Imm(self.width as u8));
+            asm.ret_cond(Condition::NC);
+        }
+        asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+        asm.emit_all(camera_tile(camera.y().name()));
+        asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+
+        // de = first visible row + column, hl = where it goes in VRAM
+        asm.emit_all(self.load_row_pointer());
+        asm.emit_all(add_to_de(Register::C));
+        asm.emit_all(vram_address(Register::B, Register::C, map_base));
+
+        // b = rows left below the camera, up to a screen (the last visible
+        // row is past the bottom of the map when the camera is row-aligned)
+        asm.ld_a((self.height.saturating_sub(1) & 0xFF) as u8)
+            .sub(Operand::Reg(Register::A), Operand::Reg(Register::B))
+            .cp(Operand::Imm(VISIBLE_ROWS - 1))
+            .jr_cond(Condition::C, ".rows")
+            .ld_a(VISIBLE_ROWS - 1);
+        asm.label(".rows");
+        asm.inc_label("a");
+        asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+        asm.label(".loop");
+        asm.ld_a_addr_reg(Register::DE);
+        asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+        // Next map row
+        asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E))
+            .add(
+                Operand::Reg(Register::A),
+                Operand::Imm((self.width & 0xFF) as u8),
+            )
+            .ld(Operand::Reg(Register::E), Operand::Reg(Register::A))
+            .ld(Operand::Reg(Register::A), Operand::Reg(Register::D))
+            .adc(
+                Operand::Reg(Register::A),
+                Operand::Imm((self.width >> 8) as u8),
+            )
+            .ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+        // Next background row, wrapping at the bottom of the 32x32 map
+        asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::L))
+            .add(Operand::Reg(Register::A), Operand::Imm(32))
+            .ld(Operand::Reg(Register::L), Operand::Reg(Register::A))
+            .ld(Operand::Reg(Register::A), Operand::Reg(Register::H))
+            .adc(Operand::Reg(Register::A), Operand::Imm(0))
+            .and(Operand::Imm(0x03))
+            .or(
+                Operand::Reg(Register::A),
+                Operand::Imm((map_base >> 8) as u8),
+            )
+            .ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+        asm.dec_label("b");
+        asm.jr_cond(Condition::NZ, ".loop");
+        asm.ret();
+
+        asm.get_main_instrs()
+    }
+
+    /// Copy map row `a` (visible columns only) to the background map
+    fn generate_load_row(&self, camera: &Camera, map_base: u16) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.comment("Copy a map row to the background map");
+        asm.comment("@param a: map row");
+        asm.label(&format!("{}LoadRow", self.name));
+
+        if self.height < MAX_STREAMED_MAP_SIZE {
+            asm.cp(Operand::Imm(self.height as u8));
+            asm.ret_cond(Condition::NC);
+        }
+        asm.ld(Operand::Reg(Register::C), Operand::Reg(Register::A));
+        asm.emit_all(camera_tile(camera.x().name()));
+        asm.ld(Operand::Reg(Register::B), Operand::Reg(Register::A));
+
+        // de = row + first visible column, hl = where it goes in VRAM
+        asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::C));
+        asm.emit_all(self.load_row_pointer());
+        asm.emit_all(add_to_de(Register::B));
+        asm.emit_all(vram_address(Register::C, Register::B, map_base));
+
+        asm.ld_b(self.width.min(VISIBLE_COLUMNS as usize) as u8);
+        asm.label(".loop");
+        asm.ld_a_addr_reg(Register::DE);
+        asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+        asm.inc_label("de");
+        // Next background column, wrapping at the right of the 32x32 map
+        asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::L))
+            .and(Operand::Imm(0xE0))
+            .ld(Operand::Reg(Register::C), Operand::Reg(Register::A))
+            .ld(Operand::Reg(Register::A), Operand::Reg(Register::L))
+            .inc_label("a")
+            .and(Operand::Imm(0x1F))
+            .or(Operand::Reg(Register::A), Operand::Reg(Register::C))
+            .ld(Operand::Reg(Register::L), Operand::Reg(Register::A));
+        asm.dec_label("b");
+        asm.jr_cond(Condition::NZ, ".loop");
+        asm.ret();
+
+        asm.get_main_instrs()
+    }
+
+    /// de = start of map row `a` (through the row table, clobbers hl)
+    fn load_row_pointer(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld(Operand::Reg(Register::L), Operand::Reg(Register::A))
+            .ld_h(0)
+            .add(Operand::Reg(Register::HL), Operand::Reg(Register::HL))
+            .ld_de_label(&format!("{}Rows", self.name))
+            .add(Operand::Reg(Register::HL), Operand::Reg(Register::DE))
+            .ld(Operand::Reg(Register::A), Operand::AddrRegInc(Register::HL))
+            .ld(Operand::Reg(Register::E), Operand::Reg(Register::A))
+            .ld_a_addr_reg(Register::HL)
+            .ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+        asm.get_main_instrs()
+    }
+}
+
+/// a = camera position / 8 (camera must stay below 2048 pixels)
+fn camera_tile(var: &str) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld_a_addr_def(&format!("{}+1", var))
+        .swap(Operand::Reg(Register::A))
+        .add(Operand::Reg(Register::A), Operand::Reg(Register::A))
+        .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+        .ld_a_addr_def(var)
+        .srl(Operand::Reg(Register::A))
+        .srl(Operand::Reg(Register::A))
+        .srl(Operand::Reg(Register::A))
+        .or(Operand::Reg(Register::A), Operand::Reg(Register::B));
+    asm.get_main_instrs()
+}
+
+/// de += reg
+fn add_to_de(reg: Register) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(Register::E))
+        .add(Operand::Reg(Register::A), Operand::Reg(reg))
+        .ld(Operand::Reg(Register::E), Operand::Reg(Register::A))
+        .ld(Operand::Reg(Register::A), Operand::Reg(Register::D))
+        .adc(Operand::Reg(Register::A), Operand::Imm(0))
+        .ld(Operand::Reg(Register::D), Operand::Reg(Register::A));
+    asm.get_main_instrs()
+}
+
+/// hl = map_base + (row % 32) * 32 + (column % 32)
+fn vram_address(row: Register, column: Register, map_base: u16) -> Vec<Instr> {
+    let mut asm = Asm::new();
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(row))
+        .and(Operand::Imm(0x1F))
+        .ld(Operand::Reg(Register::L), Operand::Reg(Register::A))
+        .ld_h(0);
+    for _ in 0..5 {
+        asm.add(Operand::Reg(Register::HL), Operand::Reg(Register::HL));
+    }
+    asm.ld(Operand::Reg(Register::A), Operand::Reg(column))
+        .and(Operand::Imm(0x1F))
+        .or(Operand::Reg(Register::A), Operand::Reg(Register::L))
+        .ld(Operand::Reg(Register::L), Operand::Reg(Register::A))
+        .ld(Operand::Reg(Register::A), Operand::Reg(Register::H))
+        .or(
+            Operand::Reg(Register::A),
+            Operand::Imm((map_base >> 8) as u8),
+        )
+        .ld(Operand::Reg(Register::H), Operand::Reg(Register::A));
+    asm.get_main_instrs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust_boy::labels::LabelCounter;
+    use crate::rust_boy::variables::VariableManager;
+
+    #[test]
+    fn test_from_rows_and_row_table() {
+        let rows = vec![vec![1u8; 40], vec![2u8; 40], vec![3u8; 40]];
+        let map = StreamedMap::from_rows("Level", &rows);
+        assert_eq!((map.width(), map.height()), (40, 3));
+        assert_eq!(map.size_bytes(), 40 * 3 + 6);
+        assert!(map.validate().is_none());
+
+        let lines: Vec<String> = map.generate_data().iter().map(|i| i.to_string()).collect();
+        assert!(lines.contains(&"LevelRows:".to_string()));
+        assert!(lines.contains(&"dw Level + 80".to_string()));
+    }
+
+    #[test]
+    fn test_load_column_stops_at_bottom() {
+        let mut vars = VariableManager::new();
+        let camera = Camera::new(
+            vars.create_u16("wCameraX", 0),
+            vars.create_u16("wCameraY", 0),
+            LabelCounter::new(),
+        );
+        let map = StreamedMap::from_rows("Level", &vec![vec![0u8; 40]; 24]);
+
+        let lines: Vec<String> = map
+            .generate_load_column(&camera, 0x9800)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        // Rows copied = min(23 - camera row, 18) + 1
+        let count = lines.iter().position(|l| l == "ld a, 23").unwrap();
+        assert_eq!(
+            lines[count..count + 7],
+            [
+                "ld a, 23",
+                "sub a, b",
+                "cp 18",
+                "jr c, .rows",
+                "ld a, 18",
+                ".rows:",
+                "inc a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_maps() {
+        let ragged = StreamedMap::from_rows("Ragged", &[vec![0u8; 4], vec![0u8; 3]]);
+        assert!(ragged.validate().is_some());
+
+        let wide = StreamedMap::new("Wide", 300, 1, vec![0; 300]);
+        assert!(wide.validate().unwrap().contains("larger than"));
+    }
+}
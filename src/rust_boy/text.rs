@@ -0,0 +1,256 @@
+//! Fonts and text rendering
+//!
+//! A font is a background tileset plus the characters it contains, in tile
+//! order. Strings are encoded at build time: the output gets one RGBDS
+//! charmap per font, and each string is stored once in ROM as `db "..."`
+//! followed by `STRING_END`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::tiles::TileId;
+use super::variables::BcdCounter;
+use crate::gb_asm::{Asm, Instr};
+use crate::gb_std::graphics::tile_ref::TileRef;
+
+/// Tile index marking the end of a string (cannot be used by a font character)
+pub const STRING_END: u8 = 0xFF;
+
+#[derive(Debug)]
+struct FontData {
+    name: String,
+    tiles: TileId,
+    first_tile: u8,
+    chars: Vec<char>,
+    /// Strings printed with this font, stored in ROM
+    strings: RefCell<Vec<String>>,
+    /// Characters that are not part of the font, reported at build time
+    missing: RefCell<Vec<char>>,
+}
+
+/// A handle to a registered font, returned by `RustBoy::add_font`
+///
+/// Like `Var` and `Camera`, the helpers return instructions. They use the
+/// `PrintString`, `PrintDecimal` and `PrintBcd` builtins, which are included
+/// automatically. VRAM must be accessible when the instructions run.
+///
+/// # Example
+/// ```ignore
+/// let font = gb.add_font("Font", TileSource::from_file("font.2bpp", 40),
+///     " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ!?:");
+///
+/// gb.init(font.print("PRESS START", &TileRef::from_xy(4, 8)));
+/// gb.add_to_main_loop(font.print_bcd(&score, &gb.window.tile_ref(6, 0)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Font(Rc<FontData>);
+
+impl Font {
+    pub(crate) fn new(name: &str, tiles: TileId, first_tile: u8, charset: &str) -> Self {
+        Font(Rc::new(FontData {
+            name: name.to_string(),
+            tiles,
+            first_tile,
+            chars: charset.chars().collect(),
+            strings: RefCell::new(Vec::new()),
+            missing: RefCell::new(Vec::new()),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Tileset holding the font glyphs
+    pub fn tiles(&self) -> TileId {
+        self.0.tiles
+    }
+
+    /// Tile index of a character, if the font has it
+    pub fn tile(&self, c: char) -> Option<u8> {
+        let index = self.0.chars.iter().position(|&ch| ch == c)?;
+        Some(self.0.first_tile.wrapping_add(index as u8))
+    }
+
+    /// Tile index of a character, recording a diagnostic if it is missing
+    fn tile_or_record(&self, c: char) -> u8 {
+        self.tile(c).unwrap_or_else(|| {
+            self.record_missing(c);
+            self.0.first_tile
+        })
+    }
+
+    fn record_missing(&self, c: char) {
+        let mut missing = self.0.missing.borrow_mut();
+        if !missing.contains(&c) {
+            missing.push(c);
+        }
+    }
+
+    /// Encode a string to tile indices at build time
+    ///
+    /// Characters missing from the font are reported when building and
+    /// replaced by the first glyph.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        text.chars().map(|c| self.tile_or_record(c)).collect()
+    }
+
+    /// Print a string at a tilemap position
+    pub fn print(&self, text: &str, at: &TileRef) -> Vec<Instr> {
+        let label = self.string_label(text);
+        let mut asm = Asm::new();
+        asm.ld_de_label(&label);
+        asm.emit_all(at.load_address());
+        asm.call("PrintString");
+        asm.get_main_instrs()
+    }
+
+    /// Print an 8-bit variable as three decimal digits
+    pub fn print_decimal(&self, var_name: &str, at: &TileRef) -> Vec<Instr> {
+        let zero = self.digit_tile();
+        let mut asm = Asm::new();
+        asm.emit_all(at.load_address());
+        asm.ld_d(zero);
+        asm.ld_a_addr_def(var_name);
+        asm.call("PrintDecimal");
+        asm.get_main_instrs()
+    }
+
+    /// Print a BCD counter with this font's digits (see `BcdCounter::draw`)
+    pub fn print_bcd(&self, counter: &BcdCounter, at: &TileRef) -> Vec<Instr> {
+        counter.draw(at, self.digit_tile())
+    }
+
+    /// Tile of '0'; digits must follow it in order
+    fn digit_tile(&self) -> u8 {
+        let zero = self.tile_or_record('0');
+        for (i, digit) in "123456789".chars().enumerate() {
+            if self.tile(digit) != Some(zero.wrapping_add(i as u8 + 1)) {
+                self.record_missing(digit);
+            }
+        }
+        zero
+    }
+
+    /// Label of a stored string, adding it on first use
+    fn string_label(&self, text: &str) -> String {
+        // Record missing characters now, the charmap is built from the font
+        self.encode(text);
+        let mut strings = self.0.strings.borrow_mut();
+        let index = match strings.iter().position(|s| s == text) {
+            Some(index) => index,
+            None => {
+                strings.push(text.to_string());
+                strings.len() - 1
+            }
+        };
+        format!("{}String{}", self.0.name, index)
+    }
+
+    /// Characters whose tile index would reach `STRING_END` or wrap past 255
+    pub(crate) fn unusable_chars(&self) -> Vec<char> {
+        let first_tile = self.0.first_tile as usize;
+        self.0
+            .chars
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| first_tile + i >= STRING_END as usize)
+            .map(|(_, &c)| c)
+            .collect()
+    }
+
+    /// Characters used in strings but missing from the font
+    pub(crate) fn missing_chars(&self) -> Vec<char> {
+        self.0.missing.borrow().clone()
+    }
+
+    /// `NEWCHARMAP` and `CHARMAP` lines for the Constants chunk
+    pub(crate) fn generate_charmap(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.raw(&format!("NEWCHARMAP {}", self.0.name));
+        for (i, &c) in self.0.chars.iter().enumerate() {
+            asm.raw(&format!(
+                "CHARMAP \"{}\", {}",
+                escape(c),
+                self.0.first_tile.wrapping_add(i as u8)
+            ));
+        }
+        // Back to the default charmap for user strings
+        asm.raw("SETCHARMAP main");
+        asm.get_main_instrs()
+    }
+
+    /// Strings printed with this font, for a ROM chunk
+    pub(crate) fn generate_strings(&self) -> Vec<Instr> {
+        let strings = self.0.strings.borrow();
+        if strings.is_empty() {
+            return Vec::new();
+        }
+
+        let mut asm = Asm::new();
+        asm.raw(&format!("SETCHARMAP {}", self.0.name));
+        for (i, text) in strings.iter().enumerate() {
+            asm.label(&format!("{}String{}", self.0.name, i));
+            let escaped: String = text.chars().map(escape).collect();
+            if escaped.is_empty() {
+                asm.db(&format!("${:02X}", STRING_END));
+            } else {
+                asm.db(&format!("\"{}\", ${:02X}", escaped, STRING_END));
+            }
+        }
+        asm.raw("SETCHARMAP main");
+        asm.get_main_instrs()
+    }
+}
+
+/// Escape a character for an RGBDS string literal
+fn escape(c: char) -> String {
+    match c {
+        '"' | '\\' | '{' | '}' => format!("\\{}", c),
+        _ => c.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font() -> Font {
+        Font::new("Font", TileId(0), 0x10, " 0123456789ABC\"")
+    }
+
+    #[test]
+    fn test_encode_and_missing_chars() {
+        let font = font();
+        assert_eq!(font.encode("A1 "), vec![0x1B, 0x12, 0x10]);
+        assert!(font.missing_chars().is_empty());
+
+        font.encode("AZ");
+        assert_eq!(font.missing_chars(), vec!['Z']);
+    }
+
+    #[test]
+    fn test_strings_are_stored_once() {
+        let font = font();
+        font.print("AB", &TileRef::from_xy(0, 0));
+        font.print("AB", &TileRef::from_xy(0, 1));
+        font.print("\"C\"", &TileRef::from_xy(0, 2));
+
+        let lines: Vec<String> = font
+            .generate_strings()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "SETCHARMAP Font",
+                "FontString0:",
+                "db \"AB\", $FF",
+                "FontString1:",
+                "db \"\\\"C\\\"\", $FF",
+                "SETCHARMAP main",
+            ]
+        );
+    }
+}
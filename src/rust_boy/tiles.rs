@@ -1,8 +1,22 @@
 //! Tile management with automatic VRAM allocation
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
 
-use crate::gb_asm::Instr;
+use super::cgb::IS_CGB_VAR;
+use super::compression::Compression;
+use super::display::{SpriteSize, TileDataArea};
+use super::image::{self, BackgroundImage, ImageError, PaletteMap, TileDedup};
+use super::memory::{MemoryAllocator, MemoryError, RegionUsage, TILE_BYTES, TileBank};
+use super::variables::Var;
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+use crate::gb_std::graphics::tile_ref::TileRef;
+
+/// Number of uploads the tile queue can hold at once
+pub const TILE_QUEUE_SIZE: u8 = 8;
+/// Bytes per queue entry: source, destination and tile count
+pub(crate) const TILE_QUEUE_ENTRY: u16 = 5;
 
 /// Unique identifier for a tile or tileset
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -15,8 +29,45 @@ pub enum TileSource {
     Raw(Vec<[String; 8]>),
     /// Binary file path (.2bpp format) with tile count
     File(String, usize),
+    /// Tiles in 2bpp format, 16 bytes each
+    Bytes(Vec<[u8; 16]>),
+}
+
+/// Error in tile data given as pixel rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TileError {
+    /// A row is not 8 pixels wide
+    RowLength { row: usize, length: usize },
+    /// The number of rows is not a multiple of 8
+    RowCount { rows: usize },
+    /// A pixel is not `.` or a color from 0 to 3
+    InvalidPixel {
+        row: usize,
+        column: usize,
+        found: char,
+    },
+}
+
+impl fmt::Display for TileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileError::RowLength { row, length } => {
+                write!(f, "row {} has {} pixels instead of 8", row, length)
+            }
+            TileError::RowCount { rows } => {
+                write!(f, "{} rows is not a whole number of 8x8 tiles", rows)
+            }
+            TileError::InvalidPixel { row, column, found } => write!(
+                f,
+                "pixel {} of row {} is {:?}, expected '.' or 0-3",
+                column, row, found
+            ),
+        }
+    }
 }
 
+impl std::error::Error for TileError {}
+
 impl TileSource {
     /// Create from the legacy format used in unbricked
     pub fn from_raw(data: &[[&str; 8]]) -> Self {
@@ -39,11 +90,169 @@ impl TileSource {
         TileSource::File(path.to_string(), tile_count)
     }
 
+    /// Convert a PNG file to 8x8 tiles, read left to right, top to bottom
+    ///
+    /// The file is read when this is called, relative to the current
+    /// directory (unlike `from_file`, whose path is used by `INCBIN`).
+    ///
+    /// # Example
+    /// ```ignore
+    /// let tiles = TileSource::from_png("assets/bricks.png", PaletteMap::Gray)?;
+    /// gb.tiles.add_background("Bricks", tiles);
+    /// ```
+    pub fn from_png(path: &str, palette: PaletteMap) -> Result<Self, ImageError> {
+        Self::from_png_sized(path, palette, SpriteSize::Size8x8)
+    }
+
+    /// Convert a PNG file to 8x16 sprite tiles (top then bottom of each block)
+    pub fn from_png_8x16(path: &str, palette: PaletteMap) -> Result<Self, ImageError> {
+        Self::from_png_sized(path, palette, SpriteSize::Size8x16)
+    }
+
+    fn from_png_sized(
+        path: &str,
+        palette: PaletteMap,
+        size: SpriteSize,
+    ) -> Result<Self, ImageError> {
+        let decoded = image::load_png(path)?;
+        let tiles = image::image_to_tiles(&decoded, palette, size)?;
+        Ok(TileSource::Bytes(tiles))
+    }
+
+    /// Create from 2bpp data, 16 bytes per tile
+    ///
+    /// An incomplete last tile is padded with color 0.
+    pub fn from_2bpp(data: &[u8]) -> Self {
+        TileSource::Bytes(
+            data.chunks(16)
+                .map(|chunk| {
+                    let mut tile = [0u8; 16];
+                    tile[..chunk.len()].copy_from_slice(chunk);
+                    tile
+                })
+                .collect(),
+        )
+    }
+
+    /// Create from 1bpp data (8 bytes per tile), as used by many fonts
+    ///
+    /// Set bits become color 3 and clear bits color 0; use `recolor` for
+    /// other colors. An incomplete last tile is padded with color 0.
+    pub fn from_1bpp(data: &[u8]) -> Self {
+        TileSource::Bytes(
+            data.chunks(8)
+                .map(|chunk| {
+                    let mut tile = [0u8; 16];
+                    for (row, &byte) in chunk.iter().enumerate() {
+                        tile[row * 2] = byte;
+                        tile[row * 2 + 1] = byte;
+                    }
+                    tile
+                })
+                .collect(),
+        )
+    }
+
+    /// Create from rows of 8 pixels, every 8 rows making a tile
+    ///
+    /// Each pixel is a color from `0` to `3`; `.` can be used for 0.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let ball = TileSource::from_pixels(&[
+    ///     "..3333..", ".322223.", "32211223", "32211223",
+    ///     "32222223", "32222223", ".322223.", "..3333..",
+    /// ])?;
+    /// ```
+    pub fn from_pixels(rows: &[&str]) -> Result<Self, TileError> {
+        if !rows.len().is_multiple_of(8) {
+            return Err(TileError::RowCount { rows: rows.len() });
+        }
+
+        let mut tiles = vec![[0u8; 16]; rows.len() / 8];
+        for (row, line) in rows.iter().enumerate() {
+            let length = line.chars().count();
+            if length != 8 {
+                return Err(TileError::RowLength { row, length });
+            }
+            let tile = &mut tiles[row / 8];
+            for (column, c) in line.chars().enumerate() {
+                let color = match c {
+                    '.' => 0,
+                    '0'..='3' => c as u8 - b'0',
+                    _ => {
+                        return Err(TileError::InvalidPixel {
+                            row,
+                            column,
+                            found: c,
+                        });
+                    }
+                };
+                let bit = 7 - column;
+                tile[(row % 8) * 2] |= (color & 1) << bit;
+                tile[(row % 8) * 2 + 1] |= (color >> 1) << bit;
+            }
+        }
+        Ok(TileSource::Bytes(tiles))
+    }
+
+    /// Tiles as 2bpp bytes, if they are known at build time
+    pub fn tiles(&self) -> Option<&[[u8; 16]]> {
+        match self {
+            TileSource::Bytes(tiles) => Some(tiles),
+            _ => None,
+        }
+    }
+
+    /// Mirror every tile horizontally
+    ///
+    /// Only `Bytes` tiles can be transformed; other sources are returned
+    /// unchanged.
+    pub fn flip_x(self) -> Self {
+        self.map_tiles(|tile| image::flip_tile(tile, image::ATTR_X_FLIP))
+    }
+
+    /// Mirror every tile vertically (`Bytes` tiles only)
+    pub fn flip_y(self) -> Self {
+        self.map_tiles(|tile| image::flip_tile(tile, image::ATTR_Y_FLIP))
+    }
+
+    /// Replace each color `c` by `colors[c]` (`Bytes` tiles only)
+    ///
+    /// # Example
+    /// ```ignore
+    /// // 1bpp font drawn in color 1 on color 0
+    /// let font = TileSource::from_1bpp(FONT_DATA).recolor([0, 1, 1, 1]);
+    /// ```
+    pub fn recolor(self, colors: [u8; 4]) -> Self {
+        self.map_tiles(|tile| {
+            let mut out = [0u8; 16];
+            for row in 0..8 {
+                for bit in 0..8 {
+                    let low = (tile[row * 2] >> bit) & 1;
+                    let high = (tile[row * 2 + 1] >> bit) & 1;
+                    let color = colors[(low | (high << 1)) as usize] & 0b11;
+                    out[row * 2] |= (color & 1) << bit;
+                    out[row * 2 + 1] |= (color >> 1) << bit;
+                }
+            }
+            out
+        })
+    }
+
+    fn map_tiles(self, f: impl Fn(&[u8; 16]) -> [u8; 16]) -> Self {
+        match self {
+            TileSource::Bytes(tiles) => TileSource::Bytes(tiles.iter().map(f).collect()),
+            other => other,
+        }
+    }
+
     /// Calculate the size in bytes
     pub fn size_bytes(&self) -> u16 {
         match self {
             TileSource::Raw(tiles) => (tiles.len() * 16) as u16, // 16 bytes per tile
             TileSource::File(_, tile_count) => (*tile_count * 16) as u16, // 16 bytes per tile
+            TileSource::Bytes(tiles) => (tiles.len() * 16) as u16,
         }
     }
 
@@ -52,6 +261,7 @@ impl TileSource {
         match self {
             TileSource::Raw(tiles) => tiles.len(),
             TileSource::File(_, tile_count) => *tile_count,
+            TileSource::Bytes(tiles) => tiles.len(),
         }
     }
 }
@@ -62,19 +272,150 @@ pub(crate) struct TileData {
     pub name: String,
     pub source: TileSource,
     pub vram_address: u16,
-    pub is_sprite: bool,  // Sprites go to $8000, background to $9000
+    pub is_sprite: bool,  // Sprites go to $8000, background to $9000 or $8800
     pub is_tilemap: bool, // Tilemaps go to $9800
+    pub compression: Option<Compression>,
+    pub streamed: bool, // Kept in ROM, uploaded through a TileSlot
+    pub vram_bank: u8,  // CGB VRAM bank, 1 is only loaded on a CGB
+}
+
+/// VRAM reserved for tilesets swapped at runtime, returned by `RustBoy::add_tile_slot`
+///
+/// Tilesets added with `TileManager::add_streamed` are uploaded into the
+/// slot with `TileManager::upload`. The upload goes through a queue that is
+/// processed right after VBlank starts, a few tiles per frame (see
+/// `RustBoy::set_tile_upload_budget`), so it is safe with the screen on.
+///
+/// # Example
+/// ```ignore
+/// let frames = gb.add_tile_slot("PlayerFrames", TileBank::Sprite, 4);
+/// let walk = gb.tiles.add_streamed("PlayerWalk", TileSource::from_png("walk.png", PaletteMap::Gray)?);
+/// let jump = gb.tiles.add_streamed("PlayerJump", TileSource::from_png("jump.png", PaletteMap::Gray)?);
+///
+/// gb.add_to_main_loop(gb.tiles.upload(jump, &frames));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TileSlot {
+    name: String,
+    address: u16,
+    first_tile: u8,
+    tiles: u8,
+    current: Var,
+}
+
+impl TileSlot {
+    pub(crate) fn new(name: &str, address: u16, first_tile: u8, tiles: u8, current: Var) -> Self {
+        Self {
+            name: name.to_string(),
+            address,
+            first_tile,
+            tiles,
+            current,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// VRAM address of the first tile
+    pub fn address(&self) -> u16 {
+        self.address
+    }
+
+    /// Number of tiles reserved
+    pub fn tiles(&self) -> u8 {
+        self.tiles
+    }
+
+    /// Tile index of the first tile, as used in OAM or tilemaps
+    pub fn first_tile(&self) -> u8 {
+        self.first_tile
+    }
+
+    /// 16-bit variable holding the number of the last tileset queued into
+    /// the slot
+    ///
+    /// `EMPTY_SLOT` ($FFFF) means no tileset was uploaded yet.
+    pub fn current(&self) -> &Var {
+        &self.current
+    }
+
+    /// Set the Z flag if `tileset` is the last tileset queued into the slot
+    ///
+    /// Uses a and b.
+    pub fn holds(&self, tileset: TileId) -> Vec<Instr> {
+        let [low, high] = (tileset.0 as u16).to_le_bytes();
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(self.current.name())
+            .xor(Operand::Reg(Register::A), Operand::Imm(low))
+            .ld(Operand::Reg(Register::B), Operand::Reg(Register::A))
+            .ld_a_addr_def(&format!("{} + 1", self.current.name()))
+            .xor(Operand::Reg(Register::A), Operand::Imm(high))
+            .or(Operand::Reg(Register::A), Operand::Reg(Register::B));
+        asm.get_main_instrs()
+    }
+}
+
+/// A tileset uploaded into a slot that is too small for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SlotOverflow {
+    pub tileset: String,
+    pub slot: String,
+    pub requested: u16,
+    pub capacity: u8,
+}
+
+/// First address background tiles can use with signed addressing
+const SIGNED_TILE_DATA_START: u16 = 0x8800;
+
+/// Value of a slot's `current` variable before any tileset is uploaded
+pub const EMPTY_SLOT: u16 = 0xFFFF;
+
+/// Name of the variable remembering the tileset queued into slot `name`
+pub(crate) fn slot_variable(name: &str) -> String {
+    format!("w{}Tileset", name)
+}
+
+fn store_u16(var: &str, value: u16) -> Vec<Instr> {
+    let [low, high] = value.to_le_bytes();
+    let mut asm = Asm::new();
+    asm.ld_a(low)
+        .ld_addr_def_a(var)
+        .ld_a(high)
+        .ld_addr_def_a(&format!("{} + 1", var));
+    asm.get_main_instrs()
 }
 
 /// Manages tiles with automatic VRAM allocation
+///
+/// With the default signed addressing (`TileDataArea::Signed8800`),
+/// background tiles fill $9000-$97FF first and then spill into the top of
+/// the $8800-$8FFF block shared with sprites, for up to 256 tiles. A
+/// single tileset of more than 128 tiles runs across $9000 instead, which
+/// needs both sides free, so add it before other background tiles. With
+/// unsigned addressing they only fit in $8000-$8FFF, next to the sprites.
+/// `tile_index` gives the index to write in tilemaps either way.
 #[derive(Debug)]
 pub struct TileManager {
     tiles: HashMap<TileId, TileData>,
     next_id: usize,
-    // Sprite tiles: $8000-$8FFF
-    next_sprite_addr: u16,
-    // Background tiles: $9000-$97FF
-    next_bg_addr: u16,
+    // $8000-$8FFF: sprite tiles from the bottom, background tiles that
+    // spill out of $9000-$97FF from the top (one per CGB VRAM bank)
+    sprite_alloc: [MemoryAllocator; 2],
+    // Background tiles: $9000-$97FF (signed addressing only)
+    bg_alloc: [MemoryAllocator; 2],
+    // LCDC tile data mode the background tiles are allocated for
+    tile_data_area: TileDataArea,
+    // Allocations that did not fit, reported at build time
+    errors: Vec<MemoryError>,
+    // Tilesets that cannot be compressed, reported at build time
+    uncompressible: Vec<String>,
+    // Uploads into slots that are too small, reported at build time
+    // (`upload` only borrows the manager)
+    slot_overflows: RefCell<Vec<SlotOverflow>>,
+    // `w{name}Tileset` variables of the reserved slots
+    slots: Vec<String>,
 }
 
 impl TileManager {
@@ -82,16 +423,165 @@ impl TileManager {
         Self {
             tiles: HashMap::new(),
             next_id: 0,
-            next_sprite_addr: 0x8000,
-            next_bg_addr: 0x9000,
+            sprite_alloc: [0, 1].map(|_| MemoryAllocator::with_bounds(0x8000, 0x9000)),
+            bg_alloc: [0, 1].map(|_| MemoryAllocator::with_bounds(0x9000, 0x9800)),
+            tile_data_area: TileDataArea::default(),
+            errors: Vec::new(),
+            uncompressible: Vec::new(),
+            slot_overflows: RefCell::new(Vec::new()),
+            slots: Vec::new(),
+        }
+    }
+
+    /// Reserve VRAM for a tileset in the given bank
+    ///
+    /// On overflow the error is recorded and the current bank pointer is
+    /// returned so the caller still gets a usable (if invalid) address.
+    fn allocate(&mut self, bank: TileBank, name: &str, tile_count: usize) -> u16 {
+        self.allocate_in(0, bank, name, tile_count)
+    }
+
+    /// Reserve VRAM for a tileset in the given bank of VRAM bank 0 or 1
+    fn allocate_in(&mut self, vram_bank: u8, bank: TileBank, name: &str, tile_count: usize) -> u16 {
+        let b = vram_bank as usize;
+        // More than 4095 tiles cannot fit anywhere (nor in a u16 byte count)
+        let size = tile_count
+            .checked_mul(TILE_BYTES as usize)
+            .and_then(|size| u16::try_from(size).ok());
+        let allocated = size.and_then(|size| match (bank, self.tile_data_area) {
+            (TileBank::Sprite, _) => self.sprite_alloc[b].allocate(size),
+            (TileBank::Background, TileDataArea::Signed8800) => self.bg_alloc[b]
+                .allocate(size)
+                .or_else(|| {
+                    // Spill into the shared block, above $8800
+                    let spill = self.sprite_alloc[b].top_address().checked_sub(size)?;
+                    if spill < SIGNED_TILE_DATA_START {
+                        return None;
+                    }
+                    self.sprite_alloc[b].allocate_top(size)
+                })
+                .or_else(|| self.allocate_straddling(b, size)),
+            (TileBank::Background, TileDataArea::Unsigned8000) => {
+                self.sprite_alloc[b].allocate_top(size)
+            }
+        });
+
+        match allocated {
+            Some(addr) => addr,
+            None => {
+                let (available, fallback) = match (bank, self.tile_data_area) {
+                    (TileBank::Sprite, _) => (
+                        self.sprite_alloc[b].bytes_remaining(),
+                        self.sprite_alloc[b].current_address(),
+                    ),
+                    (TileBank::Background, TileDataArea::Signed8800) => {
+                        let room = self.straddle_room(b).unwrap_or_else(|| {
+                            self.bg_alloc[b].bytes_remaining().max(self.spill_room(b))
+                        });
+                        (room, self.bg_alloc[b].current_address())
+                    }
+                    (TileBank::Background, TileDataArea::Unsigned8000) => (
+                        self.sprite_alloc[b].bytes_remaining(),
+                        self.sprite_alloc[b].top_address(),
+                    ),
+                };
+                self.errors.push(MemoryError::TileBankFull {
+                    name: name.to_string(),
+                    bank,
+                    requested: tile_count,
+                    available: available / TILE_BYTES,
+                });
+                fallback
+            }
+        }
+    }
+
+    /// Bytes free in the shared block between the sprites, $8800 and the
+    /// background tiles already spilled there
+    fn spill_room(&self, b: usize) -> u16 {
+        let floor = self.sprite_alloc[b]
+            .current_address()
+            .max(SIGNED_TILE_DATA_START);
+        self.sprite_alloc[b].top_address().saturating_sub(floor)
+    }
+
+    /// Bytes free for one tileset running from the shared block across
+    /// $9000, if nothing was allocated on either side of $9000 yet
+    fn straddle_room(&self, b: usize) -> Option<u16> {
+        let untouched = self.bg_alloc[b].bytes_allocated() == 0
+            && self.sprite_alloc[b].bytes_allocated_top() == 0;
+        untouched.then(|| self.bg_alloc[b].capacity() + self.spill_room(b))
+    }
+
+    /// Reserve more than 128 background tiles in signed mode: the first
+    /// ones get indices 128-255 below $9000 and the rest 0-127 above it
+    fn allocate_straddling(&mut self, b: usize, size: u16) -> Option<u16> {
+        if size > self.straddle_room(b)? {
+            return None;
+        }
+        let below = size.checked_sub(self.bg_alloc[b].capacity())?;
+        let start = self.sprite_alloc[b].allocate_top(below)?;
+        self.bg_alloc[b].allocate(size - below)?;
+        Some(start)
+    }
+
+    /// Select the LCDC tile data mode background tiles are allocated for
+    ///
+    /// Must match `DisplayConfig::bg_tile_data` (see
+    /// `RustBoy::set_tile_data_area`) and be set before adding background
+    /// tiles, since it decides where they go.
+    pub fn set_tile_data_area(&mut self, area: TileDataArea) -> &mut Self {
+        self.tile_data_area = area;
+        self
+    }
+
+    /// LCDC tile data mode background tiles are allocated for
+    pub fn tile_data_area(&self) -> TileDataArea {
+        self.tile_data_area
+    }
+
+    /// Tile index of the first tile of a tileset, as used in OAM or tilemaps
+    ///
+    /// Background indices follow the tile data mode: with signed addressing
+    /// tiles spilled into $8800-$8FFF get indices 128-255. Returns None for
+    /// tilemaps and streamed tiles, which have no fixed VRAM address.
+    pub fn tile_index(&self, id: TileId) -> Option<u8> {
+        let tile = self
+            .tiles
+            .get(&id)
+            .filter(|t| !t.is_tilemap && !t.streamed)?;
+        let bank = if tile.is_sprite {
+            TileBank::Sprite
+        } else {
+            TileBank::Background
+        };
+        Some(self.index_at(bank, tile.vram_address))
+    }
+
+    /// Tile index of a VRAM address in a bank
+    pub(crate) fn index_at(&self, bank: TileBank, address: u16) -> u8 {
+        match (bank, self.tile_data_area) {
+            (TileBank::Background, TileDataArea::Signed8800) => {
+                ((address as i32 - 0x9000) / TILE_BYTES as i32) as u8
+            }
+            _ => (address.saturating_sub(0x8000) / TILE_BYTES) as u8,
+        }
+    }
+
+    /// Write tile `tile` of a tileset at a tilemap position
+    ///
+    /// Like `TileRef::set_tile_at`, with the index computed from the
+    /// tileset's allocation. VRAM must be accessible.
+    pub fn set_tile(&self, at: &TileRef, tileset: TileId, tile: u8) -> Vec<Instr> {
+        match self.tile_index(tileset) {
+            Some(first) => at.set_tile_at(first.wrapping_add(tile)),
+            None => Vec::new(),
         }
     }
 
     /// Add sprite tiles (allocated from $8000)
     pub fn add_sprite(&mut self, name: &str, source: TileSource) -> TileId {
-        let size = source.size_bytes();
-        let addr = self.next_sprite_addr;
-        self.next_sprite_addr += size;
+        let addr = self.allocate(TileBank::Sprite, name, source.tile_count());
 
         let id = TileId(self.next_id);
         self.next_id += 1;
@@ -104,17 +594,18 @@ impl TileManager {
                 vram_address: addr,
                 is_sprite: true,
                 is_tilemap: false,
+                compression: None,
+                streamed: false,
+                vram_bank: 0,
             },
         );
 
         id
     }
 
-    /// Add background tiles (allocated from $9000)
+    /// Add background tiles (allocated from $9000, or $8800-$8FFF when full)
     pub fn add_background(&mut self, name: &str, source: TileSource) -> TileId {
-        let size = source.size_bytes();
-        let addr = self.next_bg_addr;
-        self.next_bg_addr += size;
+        let addr = self.allocate(TileBank::Background, name, source.tile_count());
 
         let id = TileId(self.next_id);
         self.next_id += 1;
@@ -127,6 +618,9 @@ impl TileManager {
                 vram_address: addr,
                 is_sprite: false,
                 is_tilemap: false,
+                compression: None,
+                streamed: false,
+                vram_bank: 0,
             },
         );
 
@@ -135,20 +629,57 @@ impl TileManager {
 
     /// Add a tilemap (goes to $9800)
     pub fn add_tilemap(&mut self, name: &str, tilemap: &[[u8; 32]]) -> TileId {
+        self.add_map(name, tilemap, 0)
+    }
+
+    /// Add a CGB attribute map (goes to $9800 in VRAM bank 1)
+    ///
+    /// Each byte holds the `TileAttributes` of the matching tilemap cell.
+    /// Attribute maps are only loaded on a CGB.
+    pub fn add_attribute_map(&mut self, name: &str, attributes: &[[u8; 32]]) -> TileId {
+        self.add_map(name, attributes, 1)
+    }
+
+    /// Add CGB tiles in VRAM bank 1, selected with `TileAttributes::vram_bank1`
+    ///
+    /// Bank 1 has the same layout as bank 0 and is allocated separately. The
+    /// tiles are only loaded on a CGB.
+    pub fn add_bank1(&mut self, name: &str, source: TileSource, bank: TileBank) -> TileId {
+        let addr = self.allocate_in(1, bank, name, source.tile_count());
+
         let id = TileId(self.next_id);
         self.next_id += 1;
 
-        // Store tilemap data as raw bytes converted to hex
-        let converted: Vec<[String; 8]> = tilemap
-            .chunks(8)
-            .map(|chunk| {
-                let mut arr: [String; 8] = Default::default();
-                for (i, row) in chunk.iter().enumerate() {
-                    let hex_values: Vec<String> =
-                        row.iter().map(|&val| format!("${:02X}", val)).collect();
-                    arr[i] = hex_values.join(", ");
-                }
-                arr
+        self.tiles.insert(
+            id,
+            TileData {
+                name: name.to_string(),
+                source,
+                vram_address: addr,
+                is_sprite: bank == TileBank::Sprite,
+                is_tilemap: false,
+                compression: None,
+                streamed: false,
+                vram_bank: 1,
+            },
+        );
+
+        id
+    }
+
+    fn add_map(&mut self, name: &str, tilemap: &[[u8; 32]], vram_bank: u8) -> TileId {
+        let id = TileId(self.next_id);
+        self.next_id += 1;
+
+        // Each 32-tile row is stored as two 16-byte chunks
+        let bytes: Vec<[u8; 16]> = tilemap
+            .iter()
+            .flat_map(|row| {
+                let (left, right) = row.split_at(16);
+                [
+                    left.try_into().expect("16 bytes"),
+                    right.try_into().expect("16 bytes"),
+                ]
             })
             .collect();
 
@@ -156,16 +687,191 @@ impl TileManager {
             id,
             TileData {
                 name: name.to_string(),
-                source: TileSource::Raw(converted),
+                source: TileSource::Bytes(bytes),
                 vram_address: 0x9800,
                 is_sprite: false,
                 is_tilemap: true,
+                compression: None,
+                streamed: false,
+                vram_bank,
+            },
+        );
+
+        id
+    }
+
+    /// Add tiles that stay in ROM until they are uploaded into a `TileSlot`
+    pub fn add_streamed(&mut self, name: &str, source: TileSource) -> TileId {
+        let id = TileId(self.next_id);
+        self.next_id += 1;
+
+        self.tiles.insert(
+            id,
+            TileData {
+                name: name.to_string(),
+                source,
+                vram_address: 0,
+                is_sprite: false,
+                is_tilemap: false,
+                compression: None,
+                streamed: true,
+                vram_bank: 0,
             },
         );
 
         id
     }
 
+    /// Reserve VRAM for a tile slot, returning its address and first tile index
+    pub(crate) fn reserve_slot(&mut self, name: &str, bank: TileBank, tiles: u8) -> (u16, u8) {
+        let address = self.allocate(bank, name, tiles as usize);
+        self.slots.push(slot_variable(name));
+        (address, self.index_at(bank, address))
+    }
+
+    /// Mark every slot empty, before user init code can queue uploads
+    pub(crate) fn generate_slot_init_code(&self) -> Vec<Instr> {
+        self.slots
+            .iter()
+            .flat_map(|var| store_u16(var, EMPTY_SLOT))
+            .collect()
+    }
+
+    /// Queue the upload of a tileset into a slot (safe with the screen on)
+    ///
+    /// The tiles are copied over the next frames, during VBlank, and the
+    /// slot remembers the tileset (see `TileSlot::holds`). A tileset larger
+    /// than the slot is reported when building.
+    pub fn upload(&self, tileset: TileId, slot: &TileSlot) -> Vec<Instr> {
+        let Some(tile) = self.tiles.get(&tileset) else {
+            return Vec::new();
+        };
+        let count = tile.source.tile_count();
+        if count > slot.tiles as usize {
+            self.slot_overflows.borrow_mut().push(SlotOverflow {
+                tileset: tile.name.clone(),
+                slot: slot.name.clone(),
+                requested: count as u16,
+                capacity: slot.tiles,
+            });
+        }
+
+        let mut asm = Asm::new();
+        asm.emit_all(Self::queue_upload(
+            &tile.name,
+            slot.address,
+            count.min(slot.tiles as usize) as u8,
+        ));
+        asm.emit_all(store_u16(slot.current.name(), tileset.0 as u16));
+        asm.get_main_instrs()
+    }
+
+    /// Queue a copy of `tiles` tiles from a ROM label to VRAM
+    ///
+    /// Uploads are dropped while the queue holds `TILE_QUEUE_SIZE` entries.
+    pub fn queue_upload(source_label: &str, destination: u16, tiles: u8) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_de_label(source_label)
+            .ld_hl(destination)
+            .ld_b(tiles)
+            .call("QueueTiles");
+        asm.get_main_instrs()
+    }
+
+    /// Import a background image as unique tiles plus a tilemap
+    ///
+    /// Identical tiles are stored once. The tiles are allocated like
+    /// `add_background` (an overflow is reported at build time with the
+    /// other memory errors), and images up to 32x32 tiles are registered as
+    /// the `{name}Tilemap` tilemap; narrower images are padded with the
+    /// first tile, with a `{name}Attributes` attribute map for
+    /// `TileDedup::WithFlips`. Larger images can be streamed with
+    /// `BackgroundImage::to_streamed_map`.
+    pub fn add_background_image(
+        &mut self,
+        name: &str,
+        path: &str,
+        palette: PaletteMap,
+        dedup: TileDedup,
+    ) -> Result<BackgroundImage, ImageError> {
+        let decoded = image::load_png(path)?;
+        let tiles = image::image_to_tiles(&decoded, palette, SpriteSize::Size8x8)?;
+        Ok(self.add_deduplicated(name, &tiles, decoded.width / 8, dedup))
+    }
+
+    fn add_deduplicated(
+        &mut self,
+        name: &str,
+        tiles: &[image::TileBytes],
+        width: usize,
+        dedup: TileDedup,
+    ) -> BackgroundImage {
+        let (unique, cells) = image::dedup_tiles(tiles, dedup);
+        let id = self.add_background(name, TileSource::Bytes(unique.clone()));
+
+        let first_tile = self.tile_index(id).unwrap_or(0);
+        let map: Vec<u8> = cells
+            .iter()
+            .map(|&(index, _)| first_tile.wrapping_add(index as u8))
+            .collect();
+        let attributes = cells.iter().map(|&(_, attr)| attr).collect();
+
+        let height = map.len().checked_div(width).unwrap_or(0);
+        let tilemap = (width <= 32 && height <= 32).then(|| {
+            let rows: Vec<[u8; 32]> = map
+                .chunks(width.max(1))
+                .map(|row| {
+                    let mut padded = [first_tile; 32];
+                    padded[..row.len()].copy_from_slice(row);
+                    padded
+                })
+                .collect();
+            self.add_tilemap(&format!("{}Tilemap", name), &rows)
+        });
+
+        let mut image = BackgroundImage {
+            tiles: id,
+            tilemap,
+            attribute_map: None,
+            unique_tiles: unique.len(),
+            width,
+            height,
+            map,
+            attributes,
+        };
+        // Without the attribute map, shared mirrored tiles show unflipped
+        if dedup == TileDedup::WithFlips && image.tilemap.is_some() {
+            image.attribute_map = Some(
+                self.add_attribute_map(&format!("{}Attributes", name), &image.attribute_rows()),
+            );
+        }
+        image
+    }
+
+    /// Store a tileset or tilemap compressed in ROM
+    ///
+    /// The data is compressed when building and decompressed straight to
+    /// VRAM by the init code. Only data known at build time can be
+    /// compressed: `TileSource::Bytes` tiles and tilemaps. Other sources are
+    /// reported when building.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let bricks = gb.tiles.add_background("Bricks", TileSource::from_png("bricks.png", PaletteMap::Gray)?);
+    /// gb.tiles.compress(bricks, Compression::Lz);
+    /// ```
+    pub fn compress(&mut self, id: TileId, compression: Compression) -> &mut Self {
+        if let Some(tile) = self.tiles.get_mut(&id) {
+            // Streamed tiles are copied as-is by the upload queue
+            if matches!(tile.source, TileSource::Bytes(_)) && !tile.streamed {
+                tile.compression = Some(compression);
+            } else {
+                self.uncompressible.push(tile.name.clone());
+            }
+        }
+        self
+    }
+
     /// Get the VRAM address for a tile
     pub fn get_address(&self, id: TileId) -> Option<u16> {
         self.tiles.get(&id).map(|t| t.vram_address)
@@ -176,49 +882,68 @@ impl TileManager {
         self.tiles.get(&id).map(|t| t.name.as_str())
     }
 
+    /// Get tile usage of the sprite bank (VRAM bank 0)
+    ///
+    /// The capacity includes the block shared with background tiles.
+    pub fn sprite_usage(&self) -> RegionUsage {
+        RegionUsage {
+            used: self.sprite_alloc[0].bytes_allocated_bottom() / TILE_BYTES,
+            capacity: self.sprite_alloc[0].capacity() / TILE_BYTES,
+        }
+    }
+
+    /// Get tile usage of the background bank (VRAM bank 0)
+    ///
+    /// The capacity includes the block shared with sprite tiles.
+    pub fn background_usage(&self) -> RegionUsage {
+        let capacity = match self.tile_data_area {
+            TileDataArea::Signed8800 => {
+                self.bg_alloc[0].capacity() + (0x9000 - SIGNED_TILE_DATA_START)
+            }
+            TileDataArea::Unsigned8000 => self.sprite_alloc[0].capacity(),
+        };
+        RegionUsage {
+            used: (self.bg_alloc[0].bytes_allocated() + self.sprite_alloc[0].bytes_allocated_top())
+                / TILE_BYTES,
+            capacity: capacity / TILE_BYTES,
+        }
+    }
+
+    /// Allocation errors collected while adding tiles
+    pub(crate) fn errors(&self) -> &[MemoryError] {
+        &self.errors
+    }
+
+    /// Uploads into slots that were too small
+    pub(crate) fn slot_overflows(&self) -> Vec<SlotOverflow> {
+        self.slot_overflows.borrow().clone()
+    }
+
+    /// Tilesets that were asked to be compressed but cannot be
+    pub(crate) fn uncompressible(&self) -> &[String] {
+        &self.uncompressible
+    }
+
     /// Generate tile data instructions for the Tiles chunk
-    pub(crate) fn generate_tile_data(&self) -> Vec<Instr> {
+    ///
+    /// With `hdma`, the data gets its own 16-byte aligned section for
+    /// `HdmaCopy`. Uncompressed tiles are a whole number of tiles long, so
+    /// they come first and all stay aligned.
+    pub(crate) fn generate_tile_data(&self, hdma: bool) -> Vec<Instr> {
         use crate::gb_asm::Asm;
 
         let mut asm = Asm::new();
-
-        // Generate sprite tiles first
-        for tile in self.tiles.values().filter(|t| t.is_sprite && !t.is_tilemap) {
-            asm.label(&tile.name);
-            match &tile.source {
-                TileSource::Raw(data) => {
-                    for tile_data in data {
-                        for line in tile_data {
-                            asm.dw(line);
-                        }
-                    }
-                }
-                TileSource::File(path, _) => {
-                    asm.incbin(path);
-                }
-            }
-            asm.label(&format!("{}End", tile.name));
+        if hdma {
+            asm.section("Tile data", "ROM0, ALIGN[4]");
         }
 
-        // Then background tiles
-        for tile in self
-            .tiles
-            .values()
-            .filter(|t| !t.is_sprite && !t.is_tilemap)
-        {
+        // Uncompressed before compressed, then sprite tiles before background tiles
+        let mut tiles: Vec<(&TileId, &TileData)> =
+            self.tiles.iter().filter(|(_, t)| !t.is_tilemap).collect();
+        tiles.sort_by_key(|(id, t)| (t.compression.is_some(), !t.is_sprite, id.0));
+        for (_, tile) in tiles {
             asm.label(&tile.name);
-            match &tile.source {
-                TileSource::Raw(data) => {
-                    for tile_data in data {
-                        for line in tile_data {
-                            asm.dw(line);
-                        }
-                    }
-                }
-                TileSource::File(path, _) => {
-                    asm.incbin(path);
-                }
-            }
+            emit_source(&mut asm, tile);
             asm.label(&format!("{}End", tile.name));
         }
 
@@ -233,13 +958,16 @@ impl TileManager {
 
         for tile in self.tiles.values().filter(|t| t.is_tilemap) {
             asm.label(&tile.name);
-            if let TileSource::Raw(data) = &tile.source {
-                for row in data {
-                    for line in row {
-                        if !line.is_empty() {
-                            asm.db(line);
-                        }
-                    }
+            if tile.compression.is_some() {
+                emit_source(&mut asm, tile);
+            } else if let TileSource::Bytes(data) = &tile.source {
+                for row in data.chunks(2) {
+                    let values: Vec<String> = row
+                        .iter()
+                        .flatten()
+                        .map(|&val| format!("${:02X}", val))
+                        .collect();
+                    asm.db(&values.join(", "));
                 }
             }
             asm.label(&format!("{}End", tile.name));
@@ -249,28 +977,99 @@ impl TileManager {
     }
 
     /// Generate memcopy calls for the Main chunk
-    pub(crate) fn generate_memcopy_calls(&self) -> Vec<Instr> {
+    ///
+    /// With `hdma` (CGB mode), uncompressed tilesets are copied by
+    /// `HdmaCopy`, which falls back to `Memcopy` on a DMG.
+    pub(crate) fn generate_memcopy_calls(&self, hdma: bool) -> Vec<Instr> {
         use crate::gb_asm::Asm;
 
         let mut asm = Asm::new();
 
-        for tile in self.tiles.values() {
-            let dest_addr = format!("${:04X}", tile.vram_address);
-            asm.ld_de_label(&tile.name)
-                .ld_hl_label(&dest_addr)
-                .ld_bc_label(&format!("{}End - {}", tile.name, tile.name))
-                .call("Memcopy");
+        let copy = |asm: &mut Asm, tile: &TileData| {
+            let dest_addr = MemoryAllocator::format_address(tile.vram_address);
+            asm.ld_de_label(&tile.name).ld_hl_label(&dest_addr);
+            let routine = if hdma && !tile.is_tilemap {
+                "HdmaCopy"
+            } else {
+                "Memcopy"
+            };
+            match tile.compression {
+                Some(compression) => asm.call(compression.routine()),
+                None => asm
+                    .ld_bc_label(&format!("{}End - {}", tile.name, tile.name))
+                    .call(routine),
+            };
+        };
+
+        for tile in self
+            .tiles
+            .values()
+            .filter(|t| !t.streamed && t.vram_bank == 0)
+        {
+            copy(&mut asm, tile);
+        }
+
+        // VRAM bank 1 only exists on a CGB, a DMG would overwrite bank 0
+        if self.uses_vram_bank1() {
+            asm.ld_a_addr_def(IS_CGB_VAR)
+                .or_label("a", "a")
+                .jr_cond(Condition::Z, "VramBank1Done")
+                .ld_a(1)
+                .ld_addr_def_a("rVBK");
+            for tile in self.tiles.values().filter(|t| t.vram_bank == 1) {
+                copy(&mut asm, tile);
+            }
+            asm.ld_a(0).ld_addr_def_a("rVBK");
+            asm.label("VramBank1Done");
         }
 
         asm.get_main_instrs()
     }
 
+    /// Check if tiles or attribute maps go to CGB VRAM bank 1
+    pub(crate) fn uses_vram_bank1(&self) -> bool {
+        self.tiles.values().any(|t| t.vram_bank == 1)
+    }
+
     /// Check if any tiles have been added
     pub fn is_empty(&self) -> bool {
         self.tiles.is_empty()
     }
 }
 
+/// Emit the data of a tileset or compressed tilemap
+fn emit_source(asm: &mut crate::gb_asm::Asm, tile: &TileData) {
+    if let (Some(compression), TileSource::Bytes(data)) = (tile.compression, &tile.source) {
+        let packed = compression.compress(data.as_flattened());
+        for chunk in packed.chunks(16) {
+            let values: Vec<String> = chunk.iter().map(|&val| format!("${:02X}", val)).collect();
+            asm.db(&values.join(", "));
+        }
+        return;
+    }
+
+    match &tile.source {
+        TileSource::Raw(data) => {
+            for tile_data in data {
+                for line in tile_data {
+                    asm.dw(line);
+                }
+            }
+        }
+        TileSource::File(path, _) => {
+            asm.incbin(path);
+        }
+        TileSource::Bytes(data) => {
+            // Graphics literals keep the generated assembly readable
+            for tile in data {
+                for line in image::tile_to_rows(tile) {
+                    asm.dw(&line);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -297,4 +1096,218 @@ mod tests {
 
         assert_eq!(tm.get_address(id), Some(0x9000));
     }
+
+    #[test]
+    fn test_background_bank_full() {
+        let mut tm = TileManager::new();
+
+        let tiles_data = [["$00"; 8]; 100];
+        tm.add_background("First", TileSource::from_raw(&tiles_data));
+        assert!(tm.errors().is_empty());
+
+        // Spills into the shared block, with signed indices
+        let second = tm.add_background("Second", TileSource::from_raw(&tiles_data));
+        assert!(tm.errors().is_empty());
+        assert_eq!(tm.get_address(second), Some(0x89C0));
+        assert_eq!(tm.tile_index(second), Some(0x9C));
+
+        tm.add_background("Third", TileSource::from_raw(&tiles_data));
+        assert_eq!(
+            tm.errors(),
+            &[MemoryError::TileBankFull {
+                name: "Third".to_string(),
+                bank: TileBank::Background,
+                requested: 100,
+                available: 28,
+            }]
+        );
+        assert_eq!(tm.background_usage().used, 200);
+    }
+
+    #[test]
+    fn test_oversized_tileset() {
+        let mut tm = TileManager::new();
+        tm.add_background("Huge", TileSource::Bytes(vec![[0; 16]; 4096]));
+        tm.add_sprite("Sheet", TileSource::from_file("sheet.2bpp", 70000));
+        assert_eq!(
+            tm.errors(),
+            &[
+                MemoryError::TileBankFull {
+                    name: "Huge".to_string(),
+                    bank: TileBank::Background,
+                    requested: 4096,
+                    available: 256,
+                },
+                MemoryError::TileBankFull {
+                    name: "Sheet".to_string(),
+                    bank: TileBank::Sprite,
+                    requested: 70000,
+                    available: 256,
+                },
+            ]
+        );
+        assert_eq!(tm.background_usage().used, 0);
+        assert_eq!(tm.sprite_usage().used, 0);
+    }
+
+    #[test]
+    fn test_large_signed_tileset() {
+        // 129-256 tiles run from the shared block across $9000
+        let mut tm = TileManager::new();
+        let map = tm.add_background("Map", TileSource::Bytes(vec![[0; 16]; 200]));
+        assert!(tm.errors().is_empty());
+        assert_eq!(tm.get_address(map), Some(0x8B80));
+        assert_eq!(tm.tile_index(map), Some(0xB8));
+        assert_eq!(tm.background_usage().used, 200);
+
+        let mut tm = TileManager::new();
+        let map = tm.add_background("Map", TileSource::Bytes(vec![[0; 16]; 256]));
+        assert!(tm.errors().is_empty());
+        assert_eq!(tm.get_address(map), Some(0x8800));
+        assert_eq!(tm.tile_index(map), Some(0x80));
+
+        // Sprites reaching into the shared block leave less room
+        let mut tm = TileManager::new();
+        tm.add_sprite("Sprites", TileSource::Bytes(vec![[0; 16]; 140]));
+        tm.add_background("Map", TileSource::Bytes(vec![[0; 16]; 245]));
+        assert!(matches!(
+            tm.errors(),
+            [MemoryError::TileBankFull {
+                requested: 245,
+                available: 244,
+                ..
+            }]
+        ));
+        let map = tm.add_background("Smaller", TileSource::Bytes(vec![[0; 16]; 244]));
+        assert_eq!(tm.get_address(map), Some(0x88C0));
+        assert_eq!(tm.errors().len(), 1);
+    }
+
+    #[test]
+    fn test_shared_block() {
+        let mut tm = TileManager::new();
+        let sprites = tm.add_sprite("Sprites", TileSource::Bytes(vec![[0; 16]; 200]));
+        tm.add_background("Map", TileSource::Bytes(vec![[0; 16]; 128]));
+        assert_eq!(tm.tile_index(sprites), Some(0));
+
+        // 56 tiles are left between the sprites and $9000
+        let spill = tm.add_background("Spill", TileSource::Bytes(vec![[0; 16]; 56]));
+        assert_eq!(tm.get_address(spill), Some(0x8C80));
+        assert_eq!(tm.tile_index(spill), Some(0xC8));
+        tm.add_sprite("More", TileSource::Bytes(vec![[0; 16]; 1]));
+        assert!(matches!(
+            tm.errors(),
+            [MemoryError::TileBankFull { available: 0, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_unsigned_tile_data() {
+        let mut tm = TileManager::new();
+        tm.set_tile_data_area(TileDataArea::Unsigned8000);
+        tm.add_sprite("Player", TileSource::Bytes(vec![[0; 16]; 4]));
+        let bg = tm.add_background("Bricks", TileSource::Bytes(vec![[0; 16]; 16]));
+
+        assert_eq!(tm.get_address(bg), Some(0x8F00));
+        assert_eq!(tm.tile_index(bg), Some(0xF0));
+        assert_eq!(tm.background_usage().used, 16);
+        assert_eq!(tm.sprite_usage().used, 4);
+
+        let lines: Vec<String> = tm
+            .set_tile(&TileRef::new(0x9800), bg, 3)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(lines.contains(&"ld a, 243".to_string()));
+    }
+
+    #[test]
+    fn test_compressed_tiles() {
+        let mut tm = TileManager::new();
+        let blank = tm.add_background("Blank", TileSource::Bytes(vec![[0; 16]; 8]));
+        let legacy = tm.add_background("Legacy", TileSource::from_raw(&[["$00"; 8]; 1]));
+        tm.compress(blank, Compression::Rle)
+            .compress(legacy, Compression::Rle);
+
+        assert_eq!(tm.uncompressible(), &["Legacy".to_string()]);
+        // Allocation still uses the decompressed size
+        assert_eq!(tm.background_usage().used, 9);
+
+        let data: Vec<String> = tm
+            .generate_tile_data(false)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(data.contains(&"db $FF, $00, $00".to_string()));
+        let calls: Vec<String> = tm
+            .generate_memcopy_calls(false)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert!(calls.contains(&"call DecompressRle".to_string()));
+        assert!(calls.contains(&"call Memcopy".to_string()));
+    }
+
+    #[test]
+    fn test_bytes_constructors() {
+        let ball = TileSource::from_pixels(&[
+            "..3333..", ".322223.", "32211223", "32211223", "32222223", "32222223", ".322223.",
+            "..3333..",
+        ])
+        .unwrap();
+        let tile = ball.tiles().unwrap()[0];
+        assert_eq!(&tile[..2], &[0x3C, 0x3C]);
+        assert_eq!(&tile[4..6], &[0x99, 0xE7]);
+        assert_eq!(image::tile_to_rows(&tile)[2], "`32211223");
+
+        let font = TileSource::from_1bpp(&[0xF0; 8]);
+        assert_eq!(font.tiles().unwrap()[0], [0xF0; 16]);
+        let recolored = font.recolor([0, 1, 1, 1]).flip_x();
+        assert_eq!(&recolored.tiles().unwrap()[0][..2], &[0x0F, 0x00]);
+
+        assert_eq!(TileSource::from_2bpp(&[0xAA; 20]).tile_count(), 2);
+        assert_eq!(
+            TileSource::from_pixels(&["..3x....", "", "", "", "", "", "", ""]).unwrap_err(),
+            TileError::InvalidPixel {
+                row: 0,
+                column: 3,
+                found: 'x',
+            }
+        );
+    }
+
+    #[test]
+    fn test_background_image_dedup() {
+        let mut tm = TileManager::new();
+        tm.add_background("Font", TileSource::from_raw(&[["$00"; 8]; 4]));
+
+        // 3x2 tiles: two distinct tiles
+        let (blank, solid) = ([0u8; 16], [0xFFu8; 16]);
+        let tiles = [blank, solid, blank, solid, solid, blank];
+        let image = tm.add_deduplicated("Title", &tiles, 3, TileDedup::Exact);
+
+        assert_eq!(image.unique_tiles(), 2);
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.map(), &[4, 5, 4, 5, 5, 4]);
+        assert_eq!(tm.background_usage().used, 6);
+        assert_eq!(tm.get_label(image.tilemap().unwrap()), Some("TitleTilemap"));
+        assert!(image.attribute_map().is_none());
+
+        // Mirrored tiles need their flips in VRAM bank 1
+        let mut arrow = [0u8; 16];
+        arrow[0] = 0x80;
+        let mut mirrored = [0u8; 16];
+        mirrored[0] = 0x01;
+        let arrows = tm.add_deduplicated("Arrows", &[arrow, mirrored], 2, TileDedup::WithFlips);
+        assert_eq!(arrows.unique_tiles(), 1);
+        let attributes = arrows.attribute_map().unwrap();
+        assert_eq!(tm.get_label(attributes), Some("ArrowsAttributes"));
+        assert!(tm.uses_vram_bank1());
+
+        // Larger than the background: no tilemap, stream it instead
+        let wide = vec![blank; 40];
+        let level = tm.add_deduplicated("Level", &wide, 40, TileDedup::Exact);
+        assert!(level.tilemap().is_none());
+        assert_eq!(level.to_streamed_map("LevelMap").width(), 40);
+    }
 }
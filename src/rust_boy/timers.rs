@@ -0,0 +1,384 @@
+//! Timers: hardware timer settings, frame-based tasks and cooldowns
+//!
+//! Tasks and cooldowns count frames in WRAM slots that are updated once
+//! per frame, before the main loop code. A task runs its code when its
+//! countdown reaches zero: repeating tasks reload it, one-shot tasks wait
+//! for `Timers::start`. A cooldown limits how often code can run, e.g. a
+//! fire button held down.
+
+use super::labels::LabelCounter;
+use crate::gb_asm::{Asm, Condition, Instr, Operand, Register};
+
+/// Input clock of the hardware timer (rTAC bits 0-1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerClock {
+    Hz4096,
+    Hz16384,
+    Hz65536,
+    Hz262144,
+}
+
+impl TimerClock {
+    pub fn hz(&self) -> u32 {
+        match self {
+            TimerClock::Hz4096 => 4096,
+            TimerClock::Hz16384 => 16384,
+            TimerClock::Hz65536 => 65536,
+            TimerClock::Hz262144 => 262144,
+        }
+    }
+
+    /// hardware.inc constant for the clock select bits
+    fn constant(&self) -> &'static str {
+        match self {
+            TimerClock::Hz4096 => "TACF_4KHZ",
+            TimerClock::Hz16384 => "TACF_16KHZ",
+            TimerClock::Hz65536 => "TACF_65KHZ",
+            TimerClock::Hz262144 => "TACF_262KHZ",
+        }
+    }
+}
+
+/// Hardware timer settings: rTIMA counts up at `clock` and is reloaded
+/// with `modulo` (rTMA) when it overflows
+///
+/// Clocks are doubled in CGB double speed mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareTimer {
+    pub clock: TimerClock,
+    pub modulo: u8,
+}
+
+impl HardwareTimer {
+    pub fn new(clock: TimerClock, modulo: u8) -> Self {
+        Self { clock, modulo }
+    }
+
+    /// rTAC value with the timer started
+    pub fn tac(&self) -> u8 {
+        let select = match self.clock {
+            TimerClock::Hz4096 => 0b00,
+            TimerClock::Hz262144 => 0b01,
+            TimerClock::Hz65536 => 0b10,
+            TimerClock::Hz16384 => 0b11,
+        };
+        0x04 | select
+    }
+
+    /// Overflows per second
+    pub fn frequency(&self) -> f64 {
+        self.clock.hz() as f64 / (256 - self.modulo as u16) as f64
+    }
+}
+
+/// A frame-based task, returned by `Timers::after` and `Timers::every`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(usize);
+
+impl TaskId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    fn var(&self) -> String {
+        format!("wTask{}", self.0)
+    }
+}
+
+/// A cooldown, returned by `Timers::add_cooldown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CooldownId(usize);
+
+impl CooldownId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+
+    fn var(&self) -> String {
+        format!("wCooldown{}", self.0)
+    }
+}
+
+struct Task {
+    frames: u16,
+    repeat: bool,
+    action: Vec<Instr>,
+}
+
+/// Hardware timer, tasks and cooldowns, reached through `RustBoy::timers`
+///
+/// # Example
+/// ```ignore
+/// // Drift the clouds one pixel every 4 frames
+/// gb.timers.every(4, gb.sprites.move_right_limit(cloud, 1, 160));
+///
+/// // Slide the message box away two seconds after startup
+/// let hide = gb.timers.after(120, gb.window.move_to(7, 144));
+/// gb.init(gb.timers.start(hide));
+///
+/// // At most one shot every 8 frames while A is held
+/// let reload = gb.timers.add_cooldown(8);
+/// inputs.on_press(PadButton::A, gb.timers.when_ready(reload, shoot));
+/// ```
+pub struct Timers {
+    hardware: Option<HardwareTimer>,
+    tasks: Vec<Task>,
+    cooldowns: Vec<u8>,
+    labels: LabelCounter,
+}
+
+impl Timers {
+    pub(crate) fn with_labels(labels: LabelCounter) -> Self {
+        Self {
+            hardware: None,
+            tasks: Vec::new(),
+            cooldowns: Vec::new(),
+            labels,
+        }
+    }
+
+    /// Start the hardware timer at startup with these settings
+    pub fn set_hardware_timer(&mut self, timer: HardwareTimer) -> &mut Self {
+        self.hardware = Some(timer);
+        self
+    }
+
+    pub fn hardware_timer(&self) -> Option<HardwareTimer> {
+        self.hardware
+    }
+
+    /// Run `action` once, `frames` frames after `start` is called
+    ///
+    /// 0 frames is treated as 1.
+    pub fn after(&mut self, frames: u16, action: Vec<Instr>) -> TaskId {
+        self.add_task(frames, false, action)
+    }
+
+    /// Run `action` every `frames` frames, starting at power on
+    ///
+    /// 0 frames is treated as 1.
+    pub fn every(&mut self, frames: u16, action: Vec<Instr>) -> TaskId {
+        self.add_task(frames, true, action)
+    }
+
+    fn add_task(&mut self, frames: u16, repeat: bool, action: Vec<Instr>) -> TaskId {
+        self.tasks.push(Task {
+            frames: frames.max(1),
+            repeat,
+            action,
+        });
+        TaskId(self.tasks.len() - 1)
+    }
+
+    /// Add a cooldown of `frames` frames for `when_ready`
+    pub fn add_cooldown(&mut self, frames: u8) -> CooldownId {
+        self.cooldowns.push(frames);
+        CooldownId(self.cooldowns.len() - 1)
+    }
+
+    /// Whether anything needs updating every frame
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty() && self.cooldowns.is_empty()
+    }
+
+    /// Task slots (countdown, 0 when stopped) and cooldown slots (frames
+    /// left) as (name, size), set by `generate_init_code`
+    pub(crate) fn variables(&self) -> Vec<(String, u16)> {
+        let tasks = (0..self.tasks.len()).map(|index| (TaskId(index).var(), 2));
+        let cooldowns = (0..self.cooldowns.len()).map(|index| (CooldownId(index).var(), 1));
+        tasks.chain(cooldowns).collect()
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Start or restart a task's countdown
+    pub fn start(&self, task: TaskId) -> Vec<Instr> {
+        let frames = self.tasks.get(task.0).map_or(1, |t| t.frames);
+        store_u16(&task.var(), frames)
+    }
+
+    /// Stop a task until it is started again
+    pub fn cancel(&self, task: TaskId) -> Vec<Instr> {
+        store_u16(&task.var(), 0)
+    }
+
+    /// Run `action` unless the cooldown is still running, then restart it
+    pub fn when_ready(&self, cooldown: CooldownId, action: Vec<Instr>) -> Vec<Instr> {
+        let frames = self.cooldowns.get(cooldown.0).copied().unwrap_or(0);
+        let busy = self.labels.unique("CooldownBusy");
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def(&cooldown.var())
+            .or_label("a", "a")
+            .jp_cond(Condition::NZ, &busy)
+            .ld_a(frames)
+            .ld_addr_def_a(&cooldown.var());
+        asm.emit_all(action);
+        asm.label(&busy);
+        asm.get_main_instrs()
+    }
+
+    /// End a cooldown early
+    pub fn reset_cooldown(&self, cooldown: CooldownId) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a(0).ld_addr_def_a(&cooldown.var());
+        asm.get_main_instrs()
+    }
+
+    /// Load the hardware timer counter (rTIMA) into a
+    pub fn read_hardware_timer(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a_addr_def("rTIMA");
+        asm.get_main_instrs()
+    }
+
+    // ============================================
+    // Build-time generation
+    // ============================================
+
+    /// Configure and start the hardware timer, and set the task and
+    /// cooldown slots before user init code can start or cancel them
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        if let Some(timer) = self.hardware {
+            asm.ld_a(timer.modulo)
+                .ld_addr_def_a("rTMA")
+                .ld_addr_def_a("rTIMA")
+                .ld_a_label(&format!("TACF_START | {}", timer.clock.constant()))
+                .ld_addr_def_a("rTAC");
+        }
+        for (index, task) in self.tasks.iter().enumerate() {
+            // Repeating tasks start counting right away
+            let initial = if task.repeat { task.frames } else { 0 };
+            asm.emit_all(store_u16(&TaskId(index).var(), initial));
+        }
+        if !self.cooldowns.is_empty() {
+            asm.ld_a(0);
+            for index in 0..self.cooldowns.len() {
+                asm.ld_addr_def_a(&CooldownId(index).var());
+            }
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Count down cooldowns and tasks, running tasks that reach zero
+    pub(crate) fn generate_tick_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for index in 0..self.cooldowns.len() {
+            let var = CooldownId(index).var();
+            let done = format!("CooldownTick_{}", index);
+            asm.ld_a_addr_def(&var)
+                .or_label("a", "a")
+                .jr_cond(Condition::Z, &done)
+                .dec_label("a")
+                .ld_addr_def_a(&var);
+            asm.label(&done);
+        }
+        for (index, task) in self.tasks.iter().enumerate() {
+            let var = TaskId(index).var();
+            let high = format!("{} + 1", var);
+            let done = format!("TaskDone_{}", index);
+            // Stopped tasks hold 0
+            asm.ld_a_addr_def(&var)
+                .ld(Operand::Reg(Register::L), Operand::Reg(Register::A))
+                .ld_a_addr_def(&high)
+                .ld(Operand::Reg(Register::H), Operand::Reg(Register::A))
+                .or(Operand::Reg(Register::A), Operand::Reg(Register::L))
+                .jp_cond(Condition::Z, &done)
+                .dec_label("hl")
+                .ld_a_label("l")
+                .ld_addr_def_a(&var)
+                .ld_a_label("h")
+                .ld_addr_def_a(&high)
+                .or(Operand::Reg(Register::A), Operand::Reg(Register::L))
+                .jp_cond(Condition::NZ, &done);
+            if task.repeat {
+                asm.emit_all(store_u16(&var, task.frames));
+            }
+            asm.emit_all(task.action.clone());
+            asm.label(&done);
+        }
+        asm.get_main_instrs()
+    }
+}
+
+fn store_u16(var: &str, value: u16) -> Vec<Instr> {
+    let [low, high] = value.to_le_bytes();
+    let mut asm = Asm::new();
+    asm.ld_a(low)
+        .ld_addr_def_a(var)
+        .ld_a(high)
+        .ld_addr_def_a(&format!("{} + 1", var));
+    asm.get_main_instrs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(instrs: &[Instr]) -> Vec<String> {
+        instrs.iter().map(|i| i.to_string()).collect()
+    }
+
+    fn call(name: &str) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.call(name);
+        asm.get_main_instrs()
+    }
+
+    #[test]
+    fn test_hardware_timer() {
+        let timer = HardwareTimer::new(TimerClock::Hz4096, 0);
+        assert_eq!(timer.tac(), 0x04);
+        assert_eq!(timer.frequency(), 16.0);
+        let timer = HardwareTimer::new(TimerClock::Hz16384, 192);
+        assert_eq!(timer.tac(), 0x07);
+        assert_eq!(timer.frequency(), 256.0);
+
+        let mut timers = Timers::with_labels(LabelCounter::new());
+        assert!(timers.generate_init_code().is_empty());
+        timers.set_hardware_timer(timer);
+        let init = lines(&timers.generate_init_code());
+        assert_eq!(init[0], "ld a, 192");
+        assert!(init.contains(&"ld a, TACF_START | TACF_16KHZ".to_string()));
+    }
+
+    #[test]
+    fn test_tasks_and_cooldowns() {
+        let mut timers = Timers::with_labels(LabelCounter::new());
+        assert!(timers.is_empty());
+        let blink = timers.every(300, call("Blink"));
+        let hide = timers.after(0, call("Hide"));
+        let fire = timers.add_cooldown(8);
+        assert_eq!(
+            timers.variables(),
+            [
+                ("wTask0".to_string(), 2),
+                ("wTask1".to_string(), 2),
+                ("wCooldown0".to_string(), 1),
+            ]
+        );
+        let init = lines(&timers.generate_init_code()).join("\n");
+        assert!(init.contains("ld a, 44\nld [wTask0], a\nld a, 1\nld [wTask0 + 1], a"));
+        assert!(init.contains("ld a, 0\nld [wTask1], a\nld a, 0\nld [wTask1 + 1], a"));
+        assert!(init.ends_with("ld a, 0\nld [wCooldown0], a"));
+
+        assert_eq!(
+            lines(&timers.start(hide)),
+            ["ld a, 1", "ld [wTask1], a", "ld a, 0", "ld [wTask1 + 1], a"]
+        );
+        assert_eq!(lines(&timers.cancel(blink))[1], "ld [wTask0], a");
+
+        let ready = lines(&timers.when_ready(fire, call("Shoot")));
+        assert_eq!(ready[2], "jp nz, CooldownBusy_0");
+        assert_eq!(ready[3], "ld a, 8");
+        assert_eq!(ready.last().unwrap(), "CooldownBusy_0:");
+
+        let tick = lines(&timers.generate_tick_code()).join("\n");
+        assert!(tick.contains("dec a\nld [wCooldown0], a\nCooldownTick_0:"));
+        // The repeating task reloads 300 = $12C before its action
+        assert!(tick.contains("ld a, 44\nld [wTask0], a\nld a, 1\nld [wTask0 + 1], a\ncall Blink"));
+        assert!(tick.contains("jp nz, TaskDone_1\ncall Hide\nTaskDone_1:"));
+    }
+}
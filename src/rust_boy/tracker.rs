@@ -0,0 +1,400 @@
+//! Import of 4-channel ProTracker modules (.mod) into songs
+//!
+//! Module channels 1-4 play on pulse 1, pulse 2, wave and noise. Samples
+//! cannot be converted, so each sample becomes a plain instrument for the
+//! channel it is played on, at the sample's volume; wave instruments use a
+//! triangle waveform. Notes, note cuts
+//! (`C00`), volumes set with a note (`Cxx`) and a single speed (`Fxx`)
+//! are imported; other effects are skipped and listed in
+//! `ModImport::unsupported`.
+//!
+//! # Example
+//! ```ignore
+//! let import = ModImport::from_file("music/title.mod")?;
+//! for effect in &import.unsupported {
+//!     eprintln!("warning: {}", effect);
+//! }
+//! let title = gb.audio.add_song(import.song)?;
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::audio::{Channel, Duty, Envelope, Noise, Note, Pitch, Sweep, WaveVolume};
+use super::music::{Instrument, PatternId, Song, Step};
+use super::waveform::Waveform;
+
+/// Rows in a module pattern
+const MOD_ROWS: usize = 64;
+const MOD_CHANNELS: usize = 4;
+const SAMPLE_COUNT: usize = 31;
+/// Offset of the song length, after the title and sample headers
+const ORDERS_OFFSET: usize = 20 + SAMPLE_COUNT * 30;
+const SIGNATURE_OFFSET: usize = ORDERS_OFFSET + 2 + 128;
+const PATTERNS_OFFSET: usize = SIGNATURE_OFFSET + 4;
+/// Amiga period of ProTracker's C-1, imported as C3
+const C1_PERIOD: f64 = 856.0;
+const C1_MIDI: i32 = 48;
+/// ProTracker's default speed, in ticks per row
+const DEFAULT_SPEED: u8 = 6;
+
+/// A module that cannot be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerError {
+    /// The file could not be read
+    Io { path: String, message: String },
+    /// The data is not a valid module
+    Corrupt(String),
+    /// A valid module feature the importer does not handle
+    Unsupported(String),
+}
+
+impl fmt::Display for TrackerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackerError::Io { path, message } => {
+                write!(f, "cannot read '{}': {}", path, message)
+            }
+            TrackerError::Corrupt(reason) => write!(f, "invalid module: {}", reason),
+            TrackerError::Unsupported(feature) => write!(f, "unsupported module: {}", feature),
+        }
+    }
+}
+
+impl std::error::Error for TrackerError {}
+
+/// An effect skipped while importing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedEffect {
+    /// Module pattern number
+    pub pattern: usize,
+    pub row: usize,
+    pub channel: Channel,
+    /// Effect command (0-F) and parameter
+    pub effect: u8,
+    pub param: u8,
+}
+
+impl fmt::Display for UnsupportedEffect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pattern {} row {} ({:?}): effect {:X}{:02X} is not supported",
+            self.pattern, self.row, self.channel, self.effect, self.param
+        )
+    }
+}
+
+/// A song imported from a module, with the effects that were skipped
+#[derive(Debug, Clone)]
+pub struct ModImport {
+    pub song: Song,
+    pub unsupported: Vec<UnsupportedEffect>,
+}
+
+/// One cell of a module pattern
+#[derive(Debug, Clone, Copy)]
+struct ModCell {
+    sample: u8,
+    period: u16,
+    effect: u8,
+    param: u8,
+}
+
+impl ModImport {
+    /// Read and import a module file
+    pub fn from_file(path: &str) -> Result<Self, TrackerError> {
+        let data = std::fs::read(path).map_err(|err| TrackerError::Io {
+            path: path.to_string(),
+            message: err.to_string(),
+        })?;
+        Self::from_bytes(&data)
+    }
+
+    /// Import a module from its bytes
+    pub fn from_bytes(data: &[u8]) -> Result<Self, TrackerError> {
+        if data.len() < PATTERNS_OFFSET {
+            return Err(TrackerError::Corrupt("file is too short".to_string()));
+        }
+        let signature = &data[SIGNATURE_OFFSET..PATTERNS_OFFSET];
+        if !matches!(signature, b"M.K." | b"M!K!" | b"4CHN" | b"FLT4") {
+            return Err(TrackerError::Unsupported(format!(
+                "signature '{}' (only 4-channel modules are imported)",
+                String::from_utf8_lossy(signature)
+            )));
+        }
+        let volumes: Vec<u8> = (0..SAMPLE_COUNT)
+            .map(|i| data[20 + i * 30 + 25].min(64))
+            .collect();
+        let length = data[ORDERS_OFFSET] as usize;
+        if length == 0 || length > 128 {
+            return Err(TrackerError::Corrupt(format!("song length {}", length)));
+        }
+        let orders = &data[ORDERS_OFFSET + 2..ORDERS_OFFSET + 2 + length];
+        let pattern_count = data[ORDERS_OFFSET + 2..SIGNATURE_OFFSET]
+            .iter()
+            .max()
+            .map_or(0, |max| *max as usize + 1);
+        let pattern_size = MOD_ROWS * MOD_CHANNELS * 4;
+        if data.len() < PATTERNS_OFFSET + pattern_count * pattern_size {
+            return Err(TrackerError::Corrupt(format!(
+                "{} patterns do not fit in the file",
+                pattern_count
+            )));
+        }
+        let patterns: Vec<Vec<ModCell>> = (0..pattern_count)
+            .map(|p| {
+                let start = PATTERNS_OFFSET + p * pattern_size;
+                data[start..start + pattern_size]
+                    .chunks(4)
+                    .map(|b| ModCell {
+                        sample: (b[0] & 0xF0) | (b[2] >> 4),
+                        period: ((b[0] & 0x0F) as u16) << 8 | b[1] as u16,
+                        effect: b[2] & 0x0F,
+                        param: b[3],
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut importer = Importer {
+            volumes,
+            speed: None,
+            instruments: HashMap::new(),
+            song: Song::new(DEFAULT_SPEED, MOD_ROWS as u8),
+            unsupported: Vec::new(),
+        };
+        // Each module pattern is imported once, as one pattern per channel
+        let mut imported: HashMap<usize, [Option<PatternId>; 4]> = HashMap::new();
+        let mut song_orders = Vec::new();
+        let last_pattern = orders[length - 1] as usize;
+        for &pattern in orders {
+            let pattern = pattern as usize;
+            let channels = *imported.entry(pattern).or_insert_with(|| {
+                let last_order = pattern == last_pattern;
+                importer.import_pattern(pattern, &patterns[pattern], last_order)
+            });
+            song_orders.push(channels);
+        }
+
+        let Importer {
+            speed,
+            mut song,
+            unsupported,
+            ..
+        } = importer;
+        // The speed is only known once every pattern was read
+        song.set_tempo(speed.unwrap_or(DEFAULT_SPEED));
+        for order in song_orders {
+            song.add_order(order);
+        }
+        Ok(ModImport { song, unsupported })
+    }
+}
+
+struct Importer {
+    /// Default volume of each sample (0-64)
+    volumes: Vec<u8>,
+    speed: Option<u8>,
+    /// Instrument for each channel kind, sample and volume
+    instruments: HashMap<(usize, u8, u8), u8>,
+    song: Song,
+    unsupported: Vec<UnsupportedEffect>,
+}
+
+impl Importer {
+    fn import_pattern(
+        &mut self,
+        pattern: usize,
+        cells: &[ModCell],
+        last_order: bool,
+    ) -> [Option<PatternId>; 4] {
+        let mut ids = [None; 4];
+        for (index, channel) in Channel::ALL.into_iter().enumerate() {
+            let mut sample = 0;
+            let mut steps = Vec::with_capacity(MOD_ROWS);
+            for row in 0..MOD_ROWS {
+                let cell = cells[row * MOD_CHANNELS + index];
+                if cell.sample != 0 {
+                    sample = cell.sample;
+                }
+                let mut volume = None;
+                let mut step = Step::Empty;
+                match (cell.effect, cell.param) {
+                    (0x0, 0x00) => {}
+                    (0xC, 0x00) => step = Step::Off,
+                    (0xC, param) if cell.period != 0 => volume = Some(param.min(64)),
+                    (0xF, param) if param < 0x20 && self.set_speed(param) => {}
+                    // ProTracker's default tempo, nothing to change
+                    (0xF, 125) => {}
+                    // A jump back to the start is the driver's own loop
+                    (0xB, 0x00) if last_order => {}
+                    (effect, param) => self.unsupported.push(UnsupportedEffect {
+                        pattern,
+                        row,
+                        channel,
+                        effect,
+                        param,
+                    }),
+                }
+                if cell.period != 0
+                    && sample != 0
+                    && let Some(note) = period_note(cell.period)
+                {
+                    let default_volume = self.volumes.get(sample as usize - 1).copied();
+                    let volume = volume.or(default_volume).unwrap_or(64);
+                    step = Step::Note(note, self.instrument(channel, sample, volume));
+                }
+                steps.push(step);
+            }
+            if steps.iter().any(|step| *step != Step::Empty) {
+                ids[index] = self.song.add_pattern(steps).ok();
+            }
+        }
+        ids
+    }
+
+    /// Keep the first speed; return false for a later, different one
+    fn set_speed(&mut self, speed: u8) -> bool {
+        match self.speed {
+            None if speed > 0 => {
+                self.speed = Some(speed);
+                true
+            }
+            Some(current) => current == speed,
+            None => false,
+        }
+    }
+
+    fn instrument(&mut self, channel: Channel, sample: u8, volume: u8) -> u8 {
+        // Both pulse channels share their instruments
+        let kind = match channel {
+            Channel::Pulse1 | Channel::Pulse2 => 0,
+            Channel::Wave => 1,
+            Channel::Noise => 2,
+        };
+        if let Some(&instrument) = self.instruments.get(&(kind, sample, volume)) {
+            return instrument;
+        }
+        let level = (volume as u16 * 15).div_ceil(64) as u8;
+        let instrument = match kind {
+            0 => Instrument::Pulse {
+                duty: Duty::Half,
+                envelope: Envelope::constant(level),
+                sweep: Sweep::OFF,
+                length: None,
+            },
+            1 => Instrument::Wave {
+                volume: match level {
+                    0 => WaveVolume::Mute,
+                    1..=5 => WaveVolume::Quarter,
+                    6..=10 => WaveVolume::Half,
+                    _ => WaveVolume::Full,
+                },
+                length: None,
+                // Samples are not imported: a soft tone stands in for them
+                wave: Some(Waveform::triangle()),
+            },
+            _ => Instrument::Noise {
+                noise: Noise::new(1, 0),
+                envelope: Envelope::fade_out(level, 1),
+                length: None,
+            },
+        };
+        let number = self.song.add_instrument(instrument);
+        self.instruments.insert((kind, sample, volume), number);
+        number
+    }
+}
+
+/// Note played at an Amiga period, if the driver can play it
+fn period_note(period: u16) -> Option<Note> {
+    let semitones = (12.0 * (C1_PERIOD / period as f64).log2()).round() as i32;
+    let midi = C1_MIDI + semitones;
+    let octave = u8::try_from(midi / 12 - 1).ok()?;
+    Note::new(Pitch::from_semitone((midi % 12) as u8), octave).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rust_boy::Audio;
+
+    /// A module with one pattern, played twice
+    fn module(cells: &[(usize, usize, [u8; 4])]) -> Vec<u8> {
+        let mut data = vec![0; PATTERNS_OFFSET + MOD_ROWS * MOD_CHANNELS * 4];
+        data[20 + 25] = 64; // Sample 1 volume
+        data[20 + 30 + 25] = 32; // Sample 2 volume
+        data[ORDERS_OFFSET] = 2;
+        data[SIGNATURE_OFFSET..PATTERNS_OFFSET].copy_from_slice(b"M.K.");
+        for &(row, channel, bytes) in cells {
+            let offset = PATTERNS_OFFSET + (row * MOD_CHANNELS + channel) * 4;
+            data[offset..offset + 4].copy_from_slice(&bytes);
+        }
+        data
+    }
+
+    #[test]
+    fn test_period_notes() {
+        assert_eq!(period_note(428), Some("C4".parse().unwrap()));
+        assert_eq!(period_note(856), Some("C3".parse().unwrap()));
+        assert_eq!(period_note(240), Some("A#4".parse().unwrap()));
+        assert_eq!(period_note(4000), None);
+    }
+
+    #[test]
+    fn test_import_mod() {
+        // C-2 with sample 1 and speed 4, A#2 with sample 2, a cut, a vibrato
+        let data = module(&[
+            (0, 0, [0x01, 0xAC, 0x1F, 0x04]),
+            (0, 2, [0x00, 0xF0, 0x20, 0x00]),
+            (4, 0, [0x00, 0x00, 0x0C, 0x00]),
+            (8, 1, [0x00, 0xF0, 0x14, 0x37]),
+        ]);
+        let import = ModImport::from_bytes(&data).unwrap();
+        assert_eq!(import.song.tempo(), 4);
+        assert_eq!(
+            import.unsupported,
+            vec![UnsupportedEffect {
+                pattern: 0,
+                row: 8,
+                channel: Channel::Pulse2,
+                effect: 0x4,
+                param: 0x37,
+            }]
+        );
+
+        let mut audio = Audio::new();
+        audio.add_song(import.song).unwrap();
+        let data: Vec<String> = audio
+            .generate_data()
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        // Pulse 1, wave and pulse 2 have notes, noise stays silent
+        assert!(
+            data.contains(&"dw Song0Pattern0, Song0Pattern1, Song0Pattern2, Song0Rest".to_string())
+        );
+        // Sample 1 at full volume, then sample 2 at half volume on the wave channel
+        assert!(data.contains(&"db $00, $80, $F0, $00, $00".to_string()));
+        assert!(data.contains(&"db $80, $00, $40, $00, $00".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_modules() {
+        assert!(matches!(
+            ModImport::from_bytes(&[0; 100]),
+            Err(TrackerError::Corrupt(_))
+        ));
+        let mut data = module(&[]);
+        data[SIGNATURE_OFFSET..PATTERNS_OFFSET].copy_from_slice(b"8CHN");
+        assert!(matches!(
+            ModImport::from_bytes(&data),
+            Err(TrackerError::Unsupported(_))
+        ));
+        assert!(matches!(
+            ModImport::from_file("missing.mod"),
+            Err(TrackerError::Io { .. })
+        ));
+    }
+}
@@ -1,8 +1,14 @@
 //! Variable management with automatic WRAM allocation
 
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 
-use crate::gb_asm::{Asm, Instr};
+use super::memory::{MemoryAllocator, MemoryError, RegionUsage};
+use crate::gb_asm::{Asm, Instr, JumpTarget, Operand, Register};
+use crate::gb_std::graphics::tile_ref::TileRef;
+
+/// State of the `Random` builtins, seeded at startup
+pub(crate) const RANDOM_VAR: &str = "wRandom";
 
 /// Unique identifier for a variable
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -22,6 +28,7 @@ pub struct VarId(pub(crate) usize);
 /// ```
 #[derive(Debug, Clone)]
 pub struct Var {
+    id: VarId,
     name: String,
     var_type: VarType,
 }
@@ -46,10 +53,227 @@ impl Var {
         asm.get_main_instrs()
     }
 
+    /// Set the variable to a random number in `range`
+    ///
+    /// Uses the `Random` builtins; 16-bit variables get a high byte of 0.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let drop = gb.vars.create_u8("wDrop", 0);
+    /// gb.add_to_main_loop(drop.random(0..=5));
+    /// ```
+    pub fn random(&self, range: RangeInclusive<u8>) -> Vec<Instr> {
+        let (low, high) = (*range.start(), (*range.end()).max(*range.start()));
+        let mut asm = Asm::new();
+        match high - low {
+            255 => asm.call("Random"),
+            span => asm.ld_b(span + 1).call("RandomBelow"),
+        };
+        if low > 0 {
+            asm.add(Operand::Reg(Register::A), Operand::Imm(low));
+        }
+        asm.ld_addr_def_a(&self.name);
+        if self.var_type.size() == 2 {
+            asm.ld_a(0).ld_addr_def_a(&format!("{} + 1", self.name));
+        }
+        asm.get_main_instrs()
+    }
+
     /// Get the variable name/label
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Get the identifier used to query the VariableManager
+    pub fn id(&self) -> VarId {
+        self.id
+    }
+
+    /// Get the variable type
+    pub fn var_type(&self) -> VarType {
+        self.var_type
+    }
+}
+
+/// Smallest number of digits of a BCD counter
+pub const BCD_MIN_DIGITS: u8 = 2;
+/// Largest number of digits of a BCD counter
+pub const BCD_MAX_DIGITS: u8 = 8;
+
+/// A handle to a packed BCD counter, returned by `VariableManager::create_bcd`
+///
+/// Each byte holds two decimal digits, least significant byte first. The
+/// counter wraps around like an odometer when it goes past its last digit.
+///
+/// # Example
+/// ```ignore
+/// let score = gb.vars.create_bcd("wScore", 6);
+/// let high_score = gb.vars.create_bcd("wHighScore", 6);
+///
+/// gb.add_to_main_loop(score.add(150));
+/// gb.add_to_main_loop(score.update_high_score(&high_score));
+/// gb.add_to_main_loop(score.draw(&gb.window.tile_ref(1, 0), DIGIT_TILES));
+/// ```
+#[derive(Debug, Clone)]
+pub struct BcdCounter {
+    var: Var,
+    digits: u8,
+}
+
+impl BcdCounter {
+    /// Get the variable holding the counter
+    pub fn var(&self) -> &Var {
+        &self.var
+    }
+
+    /// Get the variable name/label
+    pub fn name(&self) -> &str {
+        self.var.name()
+    }
+
+    /// Number of decimal digits
+    pub fn digits(&self) -> u8 {
+        self.digits
+    }
+
+    /// Number of bytes used in WRAM
+    pub fn bytes(&self) -> u8 {
+        self.digits.div_ceil(2)
+    }
+
+    /// Label of one byte of the counter (0 is the least significant)
+    fn byte(&self, index: u8) -> String {
+        if index == 0 {
+            self.name().to_string()
+        } else {
+            format!("{}+{}", self.name(), index)
+        }
+    }
+
+    /// Add points to the counter (digits past the last one are dropped)
+    pub fn add(&self, points: u32) -> Vec<Instr> {
+        let points = to_bcd(points, self.digits);
+        let mut asm = Asm::new();
+        asm.ld_hl_label(self.name());
+        for (i, &byte) in points.iter().enumerate() {
+            asm.ld_a_addr_reg(Register::HL);
+            if i == 0 {
+                asm.add(Operand::Reg(Register::A), Operand::Imm(byte));
+            } else {
+                asm.adc(Operand::Reg(Register::A), Operand::Imm(byte));
+            }
+            asm.daa();
+            if i + 1 == points.len() {
+                // Keep the unused top digit of odd counters at 0
+                if self.digits % 2 == 1 {
+                    asm.and(Operand::Imm(0x0F));
+                }
+                asm.ld(Operand::AddrReg(Register::HL), Operand::Reg(Register::A));
+            } else {
+                asm.ld_hli_label("a");
+            }
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Set the counter to a value
+    pub fn set(&self, value: u32) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        for (i, byte) in to_bcd(value, self.digits).into_iter().enumerate() {
+            asm.ld_a(byte).ld_addr_def_a(&self.byte(i as u8));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Set the counter back to 0
+    pub fn reset(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.xor(Operand::Reg(Register::A), Operand::Reg(Register::A));
+        for i in 0..self.bytes() {
+            asm.ld_addr_def_a(&self.byte(i));
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Compare with another counter using the `CompareBcd` builtin
+    ///
+    /// Afterwards the Z flag is set if both are equal and the C flag is set
+    /// if this counter is lower. Both counters should have the same number
+    /// of bytes; otherwise only the low bytes of the larger one are compared.
+    ///
+    /// # Example
+    /// ```ignore
+    /// gb.add_to_main_loop(
+    ///     IfCall::is_greater_eq("CompareBcd", extra_life).with_setup(score.compare_args(&goal)),
+    /// );
+    /// ```
+    pub fn compare(&self, other: &BcdCounter) -> Vec<Instr> {
+        let mut instrs = self.compare_args(other);
+        instrs.push(call("CompareBcd"));
+        instrs
+    }
+
+    /// Arguments of the `CompareBcd` builtin, for `IfCall::with_setup`
+    pub fn compare_args(&self, other: &BcdCounter) -> Vec<Instr> {
+        let bytes = self.bytes().min(other.bytes());
+        let mut asm = Asm::new();
+        asm.ld_de_label(&self.byte(bytes - 1))
+            .ld_hl_label(&other.byte(bytes - 1))
+            .ld_b(bytes);
+        asm.get_main_instrs()
+    }
+
+    /// Copy this counter to `high_score` if it is greater
+    pub fn update_high_score(&self, high_score: &BcdCounter) -> Vec<Instr> {
+        let mut instrs = self.compare_args(high_score);
+        instrs.push(call("UpdateHighScore"));
+        instrs
+    }
+
+    /// Draw the counter at a tilemap position using the `PrintBcd` builtin
+    ///
+    /// `digit_base` is the tile of the digit 0; digits 1 to 9 must follow
+    /// it. VRAM must be accessible when the instructions run.
+    pub fn draw(&self, at: &TileRef, digit_base: u8) -> Vec<Instr> {
+        let mut bytes = self.bytes();
+        let mut asm = Asm::new();
+        asm.emit_all(at.load_address());
+        asm.ld_c(digit_base);
+        if self.digits % 2 == 1 {
+            // The top byte only holds one digit
+            asm.ld_a_addr_def(&self.byte(bytes - 1))
+                .and(Operand::Imm(0x0F))
+                .add(Operand::Reg(Register::A), Operand::Reg(Register::C))
+                .ld_hli_label("a");
+            bytes -= 1;
+        }
+        asm.ld_de_label(&self.byte(bytes - 1))
+            .ld_b(bytes)
+            .call("PrintBcd");
+        asm.get_main_instrs()
+    }
+}
+
+/// Call instruction for a builtin
+fn call(name: &str) -> Instr {
+    Instr::Call {
+        target: JumpTarget::Label(name.to_string()),
+    }
+}
+
+/// Packed BCD bytes of a value, least significant byte first
+///
+/// Digits past `digits` are dropped.
+fn to_bcd(value: u32, digits: u8) -> Vec<u8> {
+    let mut value = value % 10u32.pow(digits as u32);
+    (0..digits.div_ceil(2))
+        .map(|_| {
+            let low = (value % 10) as u8;
+            let high = (value / 10 % 10) as u8;
+            value /= 100;
+            (high << 4) | low
+        })
+        .collect()
 }
 
 /// Variable type and size
@@ -63,6 +287,10 @@ pub enum VarType {
     I8,
     /// 2 bytes signed (dw, interpreted as signed)
     I16,
+    /// Packed BCD counter with this many digits (ds, two digits per byte)
+    Bcd(u8),
+    /// Buffer of this many bytes (ds, not initialized)
+    Buffer(u16),
 }
 
 impl VarType {
@@ -71,6 +299,8 @@ impl VarType {
         match self {
             VarType::U8 | VarType::I8 => 1,
             VarType::U16 | VarType::I16 => 2,
+            VarType::Bcd(digits) => digits.div_ceil(2) as u16,
+            VarType::Buffer(size) => *size,
         }
     }
 
@@ -79,6 +309,7 @@ impl VarType {
         match self {
             VarType::U8 | VarType::I8 => "db",
             VarType::U16 | VarType::I16 => "dw",
+            VarType::Bcd(_) | VarType::Buffer(_) => "ds",
         }
     }
 }
@@ -90,7 +321,6 @@ pub(crate) struct Variable {
     pub var_type: VarType,
     pub initial_value: i32,
     pub wram_address: u16,
-    pub section: String, // Section name for grouping
 }
 
 /// Manages variables with automatic WRAM allocation
@@ -98,8 +328,13 @@ pub(crate) struct Variable {
 pub struct VariableManager {
     variables: HashMap<VarId, Variable>,
     next_id: usize,
-    next_wram_addr: u16,
+    // Variables are emitted in WRAM0 sections: $C000-$CFFF
+    wram_alloc: MemoryAllocator,
     sections: HashMap<String, Vec<VarId>>,
+    // Allocations that did not fit, reported at build time
+    errors: Vec<MemoryError>,
+    // Names created more than once, reported at build time
+    duplicates: Vec<String>,
 }
 
 impl VariableManager {
@@ -107,8 +342,10 @@ impl VariableManager {
         Self {
             variables: HashMap::new(),
             next_id: 0,
-            next_wram_addr: 0xC000,
+            wram_alloc: MemoryAllocator::with_bounds(0xC000, 0xD000),
             sections: HashMap::new(),
+            errors: Vec::new(),
+            duplicates: Vec::new(),
         }
     }
 
@@ -132,6 +369,22 @@ impl VariableManager {
         self.create_var(name, VarType::I16, initial as i32, "Variables")
     }
 
+    /// Create a packed BCD counter of 2 to 8 digits, starting at 0
+    ///
+    /// Digit counts outside that range are clamped.
+    pub fn create_bcd(&mut self, name: &str, digits: u8) -> BcdCounter {
+        let digits = digits.clamp(BCD_MIN_DIGITS, BCD_MAX_DIGITS);
+        BcdCounter {
+            var: self.create_var(name, VarType::Bcd(digits), 0, "Variables"),
+            digits,
+        }
+    }
+
+    /// Create a buffer of `size` bytes, left uninitialized
+    pub fn create_buffer(&mut self, name: &str, size: u16) -> Var {
+        self.create_var(name, VarType::Buffer(size), 0, "Variables")
+    }
+
     /// Create a variable in a specific section
     pub fn create_in_section(
         &mut self,
@@ -144,8 +397,21 @@ impl VariableManager {
     }
 
     fn create_var(&mut self, name: &str, var_type: VarType, initial: i32, section: &str) -> Var {
-        let addr = self.next_wram_addr;
-        self.next_wram_addr += var_type.size();
+        if self.find(name).is_some() {
+            self.duplicates.push(name.to_string());
+        }
+
+        let addr = match self.wram_alloc.allocate(var_type.size()) {
+            Some(addr) => addr,
+            None => {
+                self.errors.push(MemoryError::WramExhausted {
+                    name: name.to_string(),
+                    requested: var_type.size(),
+                    available: self.wram_alloc.bytes_remaining(),
+                });
+                self.wram_alloc.current_address()
+            }
+        };
 
         let id = VarId(self.next_id);
         self.next_id += 1;
@@ -155,7 +421,6 @@ impl VariableManager {
             var_type,
             initial_value: initial,
             wram_address: addr,
-            section: section.to_string(),
         };
 
         self.variables.insert(id, var);
@@ -165,11 +430,35 @@ impl VariableManager {
             .push(id);
 
         Var {
+            id,
             name: name.to_string(),
             var_type,
         }
     }
 
+    /// Get an unsigned 8-bit variable, creating it if it does not exist yet
+    ///
+    /// Used for variables that RustBoy needs internally (e.g. `wCurKeys`)
+    /// and that the user may already have created.
+    pub(crate) fn ensure_u8(&mut self, name: &str, initial: u8) -> Var {
+        match self.find(name) {
+            Some(var) => var,
+            None => self.create_u8(name, initial),
+        }
+    }
+
+    /// Look up a variable by its label
+    pub fn find(&self, name: &str) -> Option<Var> {
+        self.variables
+            .iter()
+            .find(|(_, v)| v.name == name)
+            .map(|(id, v)| Var {
+                id: *id,
+                name: v.name.clone(),
+                var_type: v.var_type,
+            })
+    }
+
     /// Get the assembly label name for a variable
     pub fn get_label(&self, id: VarId) -> Option<&str> {
         self.variables.get(&id).map(|v| v.name.as_str())
@@ -185,6 +474,24 @@ impl VariableManager {
         self.variables.get(&id).map(|v| v.var_type)
     }
 
+    /// Get the number of WRAM bytes used by variables
+    pub fn wram_usage(&self) -> RegionUsage {
+        RegionUsage {
+            used: self.wram_alloc.bytes_allocated(),
+            capacity: self.wram_alloc.capacity(),
+        }
+    }
+
+    /// Allocation errors collected while creating variables
+    pub(crate) fn errors(&self) -> &[MemoryError] {
+        &self.errors
+    }
+
+    /// Variable names that were created more than once
+    pub(crate) fn duplicates(&self) -> &[String] {
+        &self.duplicates
+    }
+
     /// Generate variable section instructions for the Data chunk
     pub(crate) fn generate_sections(&self) -> Vec<Instr> {
         use crate::gb_asm::Asm;
@@ -196,8 +503,16 @@ impl VariableManager {
 
             for id in var_ids {
                 if let Some(var) = self.variables.get(id) {
-                    // Format: varName: db or varName: dw
-                    asm.raw(&format!("{}: {}", var.name, var.var_type.directive()));
+                    // Format: varName: db, varName: dw or varName: ds N
+                    match var.var_type {
+                        VarType::Bcd(_) | VarType::Buffer(_) => asm.raw(&format!(
+                            "{}: {} {}",
+                            var.name,
+                            var.var_type.directive(),
+                            var.var_type.size()
+                        )),
+                        _ => asm.raw(&format!("{}: {}", var.name, var.var_type.directive())),
+                    };
                 }
             }
         }
@@ -257,6 +572,18 @@ impl VariableManager {
                     }
                     asm.ld_addr_def_a(&format!("{}+1", var.name));
                 }
+                VarType::Buffer(_) => {}
+                VarType::Bcd(digits) => {
+                    let bytes = to_bcd(var.initial_value as u32, digits);
+                    for (i, byte) in bytes.into_iter().enumerate() {
+                        asm.ld_a(byte);
+                        if i == 0 {
+                            asm.ld_addr_def_a(&var.name);
+                        } else {
+                            asm.ld_addr_def_a(&format!("{}+{}", var.name, i));
+                        }
+                    }
+                }
             }
         }
 
@@ -277,20 +604,42 @@ mod tests {
     fn test_u8_variable() {
         let mut vm = VariableManager::new();
 
-        let id = vm.create_u8("wScore", 0);
+        let id = vm.create_u8("wScore", 0).id();
 
         assert_eq!(vm.get_label(id), Some("wScore"));
         assert_eq!(vm.get_address(id), Some(0xC000));
         assert_eq!(vm.get_type(id), Some(VarType::U8));
     }
 
+    #[test]
+    fn test_random() {
+        let mut vm = VariableManager::new();
+        let lines =
+            |instrs: Vec<Instr>| -> Vec<String> { instrs.iter().map(|i| i.to_string()).collect() };
+
+        let drop = vm.create_u8("wDrop", 0);
+        assert_eq!(
+            lines(drop.random(0..=5)),
+            ["ld b, 6", "call RandomBelow", "ld [wDrop], a"]
+        );
+        assert_eq!(
+            lines(drop.random(1..=255)),
+            ["ld b, 255", "call RandomBelow", "add a, 1", "ld [wDrop], a"]
+        );
+        assert_eq!(lines(drop.random(0..=255))[0], "call Random");
+
+        let x = vm.create_u16("wX", 0);
+        assert_eq!(lines(x.random(8..=8))[..2], ["ld b, 1", "call RandomBelow"]);
+        assert_eq!(lines(x.random(8..=152)).last().unwrap(), "ld [wX + 1], a");
+    }
+
     #[test]
     fn test_multiple_variables() {
         let mut vm = VariableManager::new();
 
-        let id1 = vm.create_u8("wVar1", 0);
-        let id2 = vm.create_u16("wVar2", 0);
-        let id3 = vm.create_u8("wVar3", 0);
+        let id1 = vm.create_u8("wVar1", 0).id();
+        let id2 = vm.create_u16("wVar2", 0).id();
+        let id3 = vm.create_u8("wVar3", 0).id();
 
         assert_eq!(vm.get_address(id1), Some(0xC000));
         assert_eq!(vm.get_address(id2), Some(0xC001)); // After 1 byte
@@ -301,9 +650,95 @@ mod tests {
     fn test_i8_variable() {
         let mut vm = VariableManager::new();
 
-        let id = vm.create_i8("wMomentum", -1);
+        let id = vm.create_i8("wMomentum", -1).id();
 
         assert_eq!(vm.get_label(id), Some("wMomentum"));
         assert_eq!(vm.get_type(id), Some(VarType::I8));
     }
+
+    #[test]
+    fn test_duplicate_variable() {
+        let mut vm = VariableManager::new();
+
+        vm.create_u8("wScore", 0);
+        vm.ensure_u8("wScore", 0);
+        assert!(vm.duplicates().is_empty());
+
+        vm.create_u16("wScore", 0);
+        assert_eq!(vm.duplicates(), &["wScore".to_string()]);
+    }
+
+    #[test]
+    fn test_wram_exhausted() {
+        let mut vm = VariableManager::new();
+
+        vm.create_in_section("wBuffer", VarType::U16, 0, "Buffers");
+        for i in 0..2047 {
+            vm.create_u16(&format!("wFill{}", i), 0);
+        }
+        assert!(vm.errors().is_empty());
+        assert!(vm.wram_usage().is_full());
+
+        vm.create_u8("wOverflow", 0);
+        assert_eq!(
+            vm.errors(),
+            &[MemoryError::WramExhausted {
+                name: "wOverflow".to_string(),
+                requested: 1,
+                available: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_bcd_counter() {
+        let mut vm = VariableManager::new();
+
+        let score = vm.create_bcd("wScore", 5);
+        let next = vm.create_u8("wNext", 0);
+        assert_eq!(score.bytes(), 3);
+        assert_eq!(vm.get_address(next.id()), Some(0xC003));
+        assert_eq!(vm.create_bcd("wTimer", 12).digits(), 8);
+
+        assert_eq!(to_bcd(12345, 5), vec![0x45, 0x23, 0x01]);
+        assert_eq!(to_bcd(123456, 5), vec![0x56, 0x34, 0x02]);
+
+        let lines: Vec<String> = score.add(150).iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "ld hl, wScore",
+                "ld a, [hl]",
+                "add a, 80",
+                "daa",
+                "ld [hli], a",
+                "ld a, [hl]",
+                "adc a, 1",
+                "daa",
+                "ld [hli], a",
+                "ld a, [hl]",
+                "adc a, 0",
+                "daa",
+                "and a, 15",
+                "ld [hl], a",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bcd_draw_odd_digits() {
+        let mut vm = VariableManager::new();
+        let coins = vm.create_bcd("wCoins", 3);
+
+        let lines: Vec<String> = coins
+            .draw(&TileRef::from_xy(1, 0), 0x10)
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+        assert_eq!(lines[2], "ld a, [wCoins+1]");
+        assert_eq!(
+            &lines[lines.len() - 3..],
+            &["ld de, wCoins", "ld b, 1", "call PrintBcd"]
+        );
+    }
 }
@@ -0,0 +1,106 @@
+//! Wave channel samples
+//!
+//! The wave channel plays 32 4-bit samples from `_AUD3WAVERAM`, which can
+//! only be written safely while the channel's DAC is off. Waveforms added
+//! to `Audio` are stored in `WaveTable` and uploaded by the `LoadWave`
+//! builtin, which turns the DAC off first and skips the copy when the
+//! waveform is already loaded. The next wave trigger turns the DAC back on.
+
+use std::f64::consts::PI;
+
+/// Number of 4-bit samples in wave RAM
+pub const WAVE_SAMPLES: usize = 32;
+
+/// Most waveforms in `WaveTable` ($FF means "keep wave RAM as is")
+pub const MAX_WAVEFORMS: usize = 255;
+
+/// One period of the wave channel, 32 samples from 0 to 15
+///
+/// # Example
+/// ```ignore
+/// let bass = Instrument::Wave {
+///     volume: WaveVolume::Full,
+///     length: None,
+///     wave: Some(Waveform::triangle()),
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Waveform([u8; WAVE_SAMPLES]);
+
+impl Waveform {
+    /// Samples from 0 to 15; higher bits are ignored
+    pub fn from_nibbles(samples: [u8; WAVE_SAMPLES]) -> Self {
+        Waveform(samples.map(|sample| sample & 0x0F))
+    }
+
+    /// High for the first half of the period, like a 50% pulse
+    pub fn square() -> Self {
+        Self::from_fn(|i| if i < WAVE_SAMPLES / 2 { 15 } else { 0 })
+    }
+
+    /// Rising ramp, bright and buzzy
+    pub fn saw() -> Self {
+        Self::from_fn(|i| (i / 2) as u8)
+    }
+
+    /// Up then down, a soft tone suited to bass lines
+    pub fn triangle() -> Self {
+        Self::from_fn(|i| match i {
+            0..16 => i as u8,
+            _ => (31 - i) as u8,
+        })
+    }
+
+    pub fn sine() -> Self {
+        Self::from_fn(|i| {
+            let angle = 2.0 * PI * i as f64 / WAVE_SAMPLES as f64;
+            (7.5 + 7.5 * angle.sin()).round() as u8
+        })
+    }
+
+    fn from_fn(sample: impl Fn(usize) -> u8) -> Self {
+        Self::from_nibbles(std::array::from_fn(sample))
+    }
+
+    pub fn samples(&self) -> &[u8; WAVE_SAMPLES] {
+        &self.0
+    }
+
+    /// Wave RAM bytes, the first sample of each pair in the high nibble
+    pub fn bytes(&self) -> [u8; 16] {
+        std::array::from_fn(|i| self.0[i * 2] << 4 | self.0[i * 2 + 1])
+    }
+}
+
+/// Number of a waveform in `WaveTable`, returned by `Audio::add_waveform`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WaveId(pub(crate) u8);
+
+impl WaveId {
+    pub fn index(&self) -> u8 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_waveforms() {
+        assert_eq!(Waveform::square().bytes()[..8], [0xFF; 8][..]);
+        assert_eq!(Waveform::square().bytes()[8], 0x00);
+        assert_eq!(Waveform::saw().bytes()[..3], [0x00, 0x11, 0x22]);
+        assert_eq!(Waveform::triangle().bytes()[7], 0xEF);
+        assert_eq!(Waveform::triangle().bytes()[8], 0xFE);
+
+        let sine = Waveform::sine();
+        assert_eq!(sine.samples()[0], 8);
+        assert_eq!(sine.samples()[8], 15);
+        assert_eq!(sine.samples()[24], 0);
+
+        let mut samples = [0x1F; WAVE_SAMPLES];
+        samples[1] = 0x03;
+        assert_eq!(Waveform::from_nibbles(samples).bytes()[0], 0xF3);
+    }
+}
@@ -0,0 +1,197 @@
+//! Window layer for HUDs and dialog boxes
+//!
+//! The window is drawn over the background from its top-left corner down to
+//! the bottom-right of the screen, using its own tilemap (the one the
+//! background does not use, $9C00 by default).
+
+use super::display::TileMapArea;
+use crate::gb_asm::{Asm, Instr};
+use crate::gb_std::graphics::tile_ref::TileRef;
+
+/// Width and height of a tilemap, in tiles
+const MAP_SIZE: usize = 32;
+
+/// Hardware offset of the WX register
+const WX_OFFSET: u8 = 7;
+
+/// Window position and tilemap contents
+///
+/// # Example
+/// ```ignore
+/// // Status bar on the last two rows of the screen
+/// gb.window.enable(0, 128);
+/// gb.window.place_tiles(0, 0, &[SCORE_LABEL_TILES]);
+///
+/// // Update a digit from game code (during VBlank)
+/// gb.add_to_main_loop(gb.window.write_tile(7, 0, DIGIT_OFFSET + 3));
+/// ```
+#[derive(Debug)]
+pub struct WindowManager {
+    enabled: bool,
+    x: u8,
+    y: u8,
+    map: TileMapArea,
+    /// Initial contents, copied to the window tilemap at startup
+    rows: Vec<[u8; MAP_SIZE]>,
+}
+
+impl WindowManager {
+    pub(crate) fn new() -> Self {
+        Self {
+            enabled: false,
+            x: 0,
+            y: 0,
+            map: TileMapArea::Map9C00,
+            rows: Vec::new(),
+        }
+    }
+
+    /// Show the window with its top-left corner at a screen position (pixels)
+    pub fn enable(&mut self, x: u8, y: u8) -> &mut Self {
+        self.enabled = true;
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Use another tilemap for the window (default $9C00)
+    ///
+    /// It must differ from `display.bg_tile_map`, or the window contents
+    /// would overwrite the background; a clash is reported when building.
+    pub fn set_map(&mut self, map: TileMapArea) -> &mut Self {
+        self.map = map;
+        self
+    }
+
+    /// Check if the window is shown at startup
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Tilemap used by the window
+    pub fn map(&self) -> TileMapArea {
+        self.map
+    }
+
+    /// Set tiles of the initial window contents, starting at (x, y) in tiles
+    ///
+    /// Tiles past the right edge of the 32x32 tilemap are ignored.
+    pub fn place_tiles(&mut self, x: u8, y: u8, tiles: &[u8]) -> &mut Self {
+        let (x, y) = (x as usize, y as usize);
+        if y >= MAP_SIZE {
+            return self;
+        }
+        if self.rows.len() <= y {
+            self.rows.resize(y + 1, [0; MAP_SIZE]);
+        }
+        for (i, &tile) in tiles.iter().enumerate().take(MAP_SIZE.saturating_sub(x)) {
+            self.rows[y][x + i] = tile;
+        }
+        self
+    }
+
+    // ============================================
+    // Runtime helpers
+    // ============================================
+
+    /// Reference to a tile of the window tilemap (x, y in tiles)
+    pub fn tile_ref(&self, x: u8, y: u8) -> TileRef {
+        TileRef::from_coords(x, y, self.map.base_address())
+    }
+
+    /// Write one tile of the window (VRAM must be accessible)
+    pub fn write_tile(&self, x: u8, y: u8, tile: u8) -> Vec<Instr> {
+        self.tile_ref(x, y).set_tile_at(tile)
+    }
+
+    /// Write a row of tiles starting at (x, y) (VRAM must be accessible)
+    pub fn write_tiles(&self, x: u8, y: u8, tiles: &[u8]) -> Vec<Instr> {
+        let mut instrs = self.tile_ref(x, y).load_address();
+        for &tile in tiles {
+            instrs.extend(TileRef::set_tile_and_next(tile));
+        }
+        instrs
+    }
+
+    /// Write text starting at (x, y), converting each character to a tile
+    ///
+    /// # Example
+    /// ```ignore
+    /// gb.window.write_text(0, 0, "LIVES", |c| FONT_START + (c as u8 - b'A'));
+    /// ```
+    pub fn write_text(&self, x: u8, y: u8, text: &str, to_tile: impl Fn(char) -> u8) -> Vec<Instr> {
+        let tiles: Vec<u8> = text.chars().map(to_tile).collect();
+        self.write_tiles(x, y, &tiles)
+    }
+
+    /// Move the window to a screen position (pixels)
+    pub fn move_to(&self, x: u8, y: u8) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.ld_a(x.saturating_add(WX_OFFSET))
+            .ld_addr_def_a("rWX")
+            .ld_a(y)
+            .ld_addr_def_a("rWY");
+        asm.get_main_instrs()
+    }
+
+    /// Position the window and copy its initial contents (screen must be off)
+    pub(crate) fn generate_init_code(&self) -> Vec<Instr> {
+        let mut asm = Asm::new();
+        asm.emit_all(self.move_to(self.x, self.y));
+        if !self.rows.is_empty() {
+            asm.ld_de_label("WindowTilemap")
+                .ld_hl(self.map.base_address())
+                .ld_bc_label("WindowTilemapEnd - WindowTilemap")
+                .call("Memcopy");
+        }
+        asm.get_main_instrs()
+    }
+
+    /// Initial contents for the Tilemap chunk
+    pub(crate) fn generate_tilemap_data(&self) -> Vec<Instr> {
+        if self.rows.is_empty() {
+            return Vec::new();
+        }
+        let mut asm = Asm::new();
+        asm.label("WindowTilemap");
+        for row in &self.rows {
+            let values: Vec<String> = row.iter().map(|&val| format!("${:02X}", val)).collect();
+            asm.db(&values.join(", "));
+        }
+        asm.label("WindowTilemapEnd");
+        asm.get_main_instrs()
+    }
+
+    /// Check if the initial contents need Memcopy
+    pub(crate) fn has_tilemap(&self) -> bool {
+        !self.rows.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_tiles_clips_to_map() {
+        let mut window = WindowManager::new();
+        window.place_tiles(30, 1, &[1, 2, 3, 4]);
+
+        assert_eq!(window.rows.len(), 2);
+        assert_eq!(&window.rows[1][30..], &[1, 2]);
+    }
+
+    #[test]
+    fn test_write_tiles_addresses_window_map() {
+        let window = WindowManager::new();
+        let lines: Vec<String> = window
+            .write_tiles(2, 1, &[5, 6])
+            .iter()
+            .map(|i| i.to_string())
+            .collect();
+
+        // $9C00 + 32 + 2
+        assert_eq!(lines[0], format!("ld hl, {}", 0x9C22));
+        assert_eq!(lines.len(), 5);
+    }
+}